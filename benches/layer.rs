@@ -0,0 +1,104 @@
+//! Overhead of the layer on requests that never extract `Tx`.
+//!
+//! Besides timing, this reports the number and total size of heap allocations made per request,
+//! counted with a wrapping global allocator.
+
+use std::{
+    alloc::{GlobalAlloc, Layout, System},
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use criterion::{criterion_group, criterion_main, Criterion};
+use sea_orm::{Database, DatabaseConnection};
+use tower::{Service, ServiceExt};
+
+struct CountingAllocator;
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+static ALLOCATED_BYTES: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        ALLOCATED_BYTES.fetch_add(layout.size(), Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: CountingAllocator = CountingAllocator;
+
+fn unused_tx(c: &mut Criterion) {
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .unwrap();
+    let pool: DatabaseConnection = runtime
+        .block_on(Database::connect("sqlite::memory:"))
+        .unwrap();
+
+    let handler = || async { "hello" };
+    let without_layer = axum::Router::new().route("/", axum::routing::get(handler));
+    // axum boxes the services and bodies of layered routes, so this is the baseline for `with_layer`
+    let with_noop_layer = without_layer
+        .clone()
+        .layer(tower::layer::util::Identity::new());
+    let with_layer = without_layer
+        .clone()
+        .layer(axum_sea_orm_tx::Layer::new(pool));
+
+    let mut group = c.benchmark_group("unused_tx");
+    for (name, app) in [
+        ("without_layer", without_layer),
+        ("with_noop_layer", with_noop_layer),
+        ("with_layer", with_layer),
+    ] {
+        let (allocations, bytes) = runtime.block_on(async {
+            let mut app = app.clone();
+            // Warm up, so lazily initialised state isn't counted
+            request(&mut app).await;
+            let before = (
+                ALLOCATIONS.load(Ordering::Relaxed),
+                ALLOCATED_BYTES.load(Ordering::Relaxed),
+            );
+            request(&mut app).await;
+            (
+                ALLOCATIONS.load(Ordering::Relaxed) - before.0,
+                ALLOCATED_BYTES.load(Ordering::Relaxed) - before.1,
+            )
+        });
+        println!("unused_tx/{name}: {allocations} allocations ({bytes} bytes) per request");
+
+        group.bench_function(name, |b| {
+            b.to_async(&runtime).iter_batched(
+                || app.clone(),
+                |mut app| async move { request(&mut app).await },
+                criterion::BatchSize::SmallInput,
+            )
+        });
+    }
+    group.finish();
+}
+
+async fn request(app: &mut axum::Router) {
+    let response = app
+        .ready()
+        .await
+        .unwrap()
+        .call(
+            http::Request::builder()
+                .uri("/")
+                .body(axum::body::Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert!(response.status().is_success());
+}
+
+criterion_group!(benches, unused_tx);
+criterion_main!(benches);
@@ -2,10 +2,11 @@
 
 use std::error::Error;
 
-use axum::{response::IntoResponse, routing::get, Json};
+use axum::{body::StreamBody, response::IntoResponse, routing::get, Json};
 use axum_sea_orm_tx::Tx;
+use futures_util::TryStreamExt;
 use http::StatusCode;
-use sea_orm::{ConnectionTrait, Database, DatabaseConnection, DbErr, Statement};
+use sea_orm::{ConnectionTrait, Database, DatabaseConnection, DbErr, FromQueryResult, Statement};
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn Error>> {
@@ -23,6 +24,7 @@ async fn main() -> Result<(), Box<dyn Error>> {
     // Standard axum app setup
     let app = axum::Router::new()
         .route("/numbers", get(list_numbers).post(generate_number))
+        .route("/numbers.ndjson", get(stream_numbers))
         // Apply the Tx middleware
         .layer(axum_sea_orm_tx::Layer::new(pool.clone()));
 
@@ -48,6 +50,31 @@ async fn list_numbers(tx: Tx<DatabaseConnection>) -> Result<Json<Vec<i32>>, DbEr
     Ok(Json(numbers))
 }
 
+#[derive(FromQueryResult, serde::Serialize)]
+struct Number {
+    number: i32,
+}
+
+// Stream every number as newline-delimited JSON, without loading them all into memory first. The
+// transaction stays open until the last number has been sent.
+async fn stream_numbers(tx: Tx<DatabaseConnection>) -> impl IntoResponse {
+    let stmt = Statement::from_string(
+        tx.get_database_backend(),
+        "SELECT number FROM numbers".to_string(),
+    );
+
+    let numbers = tx.stream_response::<Number>(stmt).map_ok(|number| {
+        let mut line = serde_json::to_vec(&number).unwrap();
+        line.push(b'\n');
+        line
+    });
+
+    (
+        [(http::header::CONTENT_TYPE, "application/x-ndjson")],
+        StreamBody::new(numbers),
+    )
+}
+
 async fn generate_number(tx: Tx<DatabaseConnection>) -> Result<(StatusCode, Json<i32>), DbError> {
     let number: i32 = tx
         .query_one(Statement::from_string(
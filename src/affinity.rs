@@ -0,0 +1,106 @@
+//! Connections held between requests that share a key.
+
+use std::{collections::VecDeque, sync::Arc};
+
+use parking_lot::Mutex;
+use sea_orm::{ConnectionTrait, DatabaseTransaction, DbErr};
+
+use crate::{connection::retry_if_locked, runtime::Sleep, BackoffPolicy};
+
+/// The key that requests share a connection by, see
+/// [`Layer::connection_affinity`](crate::Layer::connection_affinity).
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct AffinityKey(String);
+
+impl AffinityKey {
+    /// Create a key, e.g. from the request's tenant.
+    pub fn new(key: impl Into<String>) -> Self {
+        Self(key.into())
+    }
+}
+
+/// Connections held for the next request with the same [`AffinityKey`], shared by every request
+/// handled by a [`Layer`].
+///
+/// SeaORM can't begin a transaction on a particular pool connection, so each connection is held
+/// with a transaction open on it, which is taken by the next request with its key. Once that
+/// request's transaction is resolved, it's ended with a plain `COMMIT` or `ROLLBACK`, and a new one
+/// is begun on the same connection to hold it again.
+///
+/// [`Layer`]: crate::Layer
+#[derive(Clone)]
+pub(crate) struct Affinity {
+    held: Arc<Mutex<VecDeque<(AffinityKey, DatabaseTransaction)>>>,
+    size: usize,
+}
+
+impl Affinity {
+    /// Create an empty set that will hold up to `size` connections.
+    pub(crate) fn new(size: usize) -> Self {
+        Self {
+            held: Arc::new(Mutex::new(VecDeque::with_capacity(size))),
+            size,
+        }
+    }
+
+    /// Take the connection held for `key`, if any.
+    pub(crate) fn take(&self, key: &AffinityKey) -> Option<DatabaseTransaction> {
+        let mut held = self.held.lock();
+        let index = held.iter().position(|(held, _)| held == key)?;
+        held.remove(index).map(|(_, tx)| tx)
+    }
+
+    /// Commit the transaction on `tx`, and hold its connection for `key`.
+    ///
+    /// If `backoff` is given, the commit is retried if the database is locked, as in
+    /// [`Connection::commit_retrying`](crate::connection::Connection::commit_retrying).
+    pub(crate) async fn commit(
+        &self,
+        key: AffinityKey,
+        tx: DatabaseTransaction,
+        backoff: Option<&BackoffPolicy>,
+        sleep: Option<Sleep>,
+    ) -> Result<(), DbErr> {
+        let commit = || async { tx.execute_unprepared("COMMIT").await.map(drop) };
+        match backoff {
+            Some(backoff) => retry_if_locked(backoff, sleep, commit).await?,
+            None => commit().await?,
+        }
+        self.reopen(key, tx).await;
+        Ok(())
+    }
+
+    /// Roll back the transaction on `tx`, and hold its connection for `key`.
+    pub(crate) async fn rollback(
+        &self,
+        key: AffinityKey,
+        tx: DatabaseTransaction,
+    ) -> Result<(), DbErr> {
+        tx.execute_unprepared("ROLLBACK").await?;
+        self.reopen(key, tx).await;
+        Ok(())
+    }
+
+    /// Begin a new transaction on `tx`'s connection and hold it for `key`, dropping the connection
+    /// that's been held the longest if there are too many.
+    ///
+    /// Errors are not returned, since the request's transaction has already been resolved, and
+    /// the next request with `key` will simply begin its own transaction.
+    async fn reopen(&self, key: AffinityKey, tx: DatabaseTransaction) {
+        #[allow(unused_variables)]
+        if let Err(error) = tx.execute_unprepared("BEGIN").await {
+            #[cfg(feature = "tracing")]
+            tracing::warn!(%error, "failed to begin transaction on held connection");
+            return;
+        }
+
+        let evicted = {
+            let mut held = self.held.lock();
+            // There can be more than one connection for a key if its requests overlapped
+            held.push_back((key, tx));
+            (held.len() > self.size).then(|| held.pop_front()).flatten()
+        };
+        // SeaORM rolls back the transaction when it's dropped, and returns the connection to the pool
+        drop(evicted);
+    }
+}
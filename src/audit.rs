@@ -0,0 +1,101 @@
+//! An audit log of the statements each request's transaction executed.
+
+use std::sync::Arc;
+
+use parking_lot::Mutex;
+use sea_orm::Statement;
+
+use crate::TxOutcome;
+
+/// The statements a request executed through [`Tx`](crate::Tx), and how its transaction was
+/// resolved.
+///
+/// This is passed to the sink configured with [`Layer::audit`](crate::Layer::audit) once the
+/// request's transaction has been resolved.
+#[derive(Debug, Clone, PartialEq)]
+#[non_exhaustive]
+pub struct AuditRecord {
+    /// The statements executed, in order.
+    pub statements: Vec<AuditedStatement>,
+
+    /// How the transaction was resolved, if the [`Service`](crate::Service) middleware resolved it
+    /// (see [`TxOutcome`] for when it doesn't).
+    pub outcome: Option<TxOutcome>,
+}
+
+/// A statement executed through [`Tx`](crate::Tx).
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct AuditedStatement {
+    /// The SQL of the statement.
+    pub sql: String,
+
+    /// A summary of each bound value, or `"<redacted>"` if values are redacted (see
+    /// [`Layer::redact_audit_values`](crate::Layer::redact_audit_values)).
+    pub values: Vec<String>,
+}
+
+/// A function receiving the audit record of each request.
+pub(crate) type AuditSink = Arc<dyn Fn(AuditRecord) + Send + Sync>;
+
+/// The statements executed by a request, shared between the [`Service`](crate::Service) and
+/// [`Tx`](crate::Tx).
+#[derive(Clone)]
+pub(crate) struct AuditLog {
+    statements: Arc<Mutex<Vec<AuditedStatement>>>,
+    redact_values: bool,
+}
+
+impl AuditLog {
+    /// Start a new log for a request.
+    pub(crate) fn new(redact_values: bool) -> Self {
+        Self {
+            statements: Default::default(),
+            redact_values,
+        }
+    }
+
+    /// Send the recorded statements to `sink`, with the transaction's `outcome`.
+    ///
+    /// Nothing is sent if no transaction was begun.
+    pub(crate) fn emit(&self, sink: &AuditSink, outcome: Option<TxOutcome>) {
+        let statements = std::mem::take(&mut *self.statements.lock());
+        if statements.is_empty() && outcome.is_none() {
+            return;
+        }
+        sink(AuditRecord {
+            statements,
+            outcome,
+        });
+    }
+
+    /// Record that `stmt` was executed.
+    pub(crate) fn record(&self, stmt: &Statement) {
+        let values = stmt
+            .values
+            .iter()
+            .flat_map(|values| values.0.iter())
+            .map(|value| {
+                if self.redact_values {
+                    "<redacted>".to_string()
+                } else {
+                    format!("{value:?}")
+                }
+            })
+            .collect();
+        self.record_sql(stmt.sql.clone(), values);
+    }
+
+    /// Record that raw `sql` was executed.
+    pub(crate) fn record_sql(&self, sql: String, values: Vec<String>) {
+        self.statements
+            .lock()
+            .push(AuditedStatement { sql, values });
+    }
+}
+
+impl std::fmt::Debug for AuditLog {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("AuditLog").finish_non_exhaustive()
+    }
+}
@@ -0,0 +1,175 @@
+//! Delays between retries.
+
+use std::{
+    collections::hash_map::RandomState,
+    hash::{BuildHasher, Hasher},
+    time::Duration,
+};
+
+/// How many times, and how long to wait before, retrying a failed operation.
+///
+/// This is shared by the options that retry, e.g. [`Layer::retry_commit`](crate::Layer::retry_commit):
+///
+/// ```
+/// use std::time::Duration;
+/// use axum_sea_orm_tx::BackoffPolicy;
+///
+/// // Retry up to 3 times, waiting 10ms, 20ms and then 40ms
+/// let policy = BackoffPolicy::exponential(Duration::from_millis(10), Duration::from_secs(1), 3);
+/// assert_eq!(policy.next_delay(1), Some(Duration::from_millis(10)));
+/// assert_eq!(policy.next_delay(3), Some(Duration::from_millis(40)));
+/// assert_eq!(policy.next_delay(4), None);
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BackoffPolicy {
+    kind: Kind,
+    retries: u32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Kind {
+    Fixed(Duration),
+    Exponential {
+        base: Duration,
+        max: Duration,
+        jitter: bool,
+    },
+}
+
+impl BackoffPolicy {
+    /// Retry up to `retries` times, waiting `delay` before each retry.
+    pub fn fixed(delay: Duration, retries: u32) -> Self {
+        Self {
+            kind: Kind::Fixed(delay),
+            retries,
+        }
+    }
+
+    /// Retry up to `retries` times, waiting `base` before the first retry and doubling the delay
+    /// after each one, up to `max`.
+    pub fn exponential(base: Duration, max: Duration, retries: u32) -> Self {
+        Self {
+            kind: Kind::Exponential {
+                base,
+                max,
+                jitter: false,
+            },
+            retries,
+        }
+    }
+
+    /// Like [`exponential`](Self::exponential), but waiting a random time between zero and the
+    /// exponential delay, so that requests that failed together don't all retry together.
+    pub fn exponential_with_jitter(base: Duration, max: Duration, retries: u32) -> Self {
+        Self {
+            kind: Kind::Exponential {
+                base,
+                max,
+                jitter: true,
+            },
+            retries,
+        }
+    }
+
+    /// The delay before retrying after `attempt` attempts have failed, or `None` if there are no
+    /// retries left.
+    ///
+    /// `attempt` starts at 1, for the delay before the first retry.
+    pub fn next_delay(&self, attempt: u32) -> Option<Duration> {
+        if attempt > self.retries {
+            return None;
+        }
+
+        let delay = match self.kind {
+            Kind::Fixed(delay) => delay,
+            Kind::Exponential { base, max, jitter } => {
+                let delay = 2u32
+                    .checked_pow(attempt.saturating_sub(1))
+                    .and_then(|factor| base.checked_mul(factor))
+                    .map_or(max, |delay| delay.min(max));
+                if jitter {
+                    delay.mul_f64(random_fraction())
+                } else {
+                    delay
+                }
+            }
+        };
+        Some(delay)
+    }
+
+    /// Whether retries happen straight away, so don't need a runtime to wait.
+    pub(crate) fn is_immediate(&self) -> bool {
+        match self.kind {
+            Kind::Fixed(delay) => delay.is_zero(),
+            Kind::Exponential { base, .. } => base.is_zero(),
+        }
+    }
+}
+
+/// A random number between 0 and 1, for jitter.
+///
+/// This doesn't need to be good randomness, so it uses the random keys `std` generates for
+/// `HashMap`s rather than pulling in a dependency.
+fn random_fraction() -> f64 {
+    let random = RandomState::new().build_hasher().finish();
+    random as f64 / u64::MAX as f64
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::BackoffPolicy;
+
+    const MS: Duration = Duration::from_millis(1);
+
+    fn delays(policy: BackoffPolicy) -> Vec<Option<Duration>> {
+        (1..=5).map(|attempt| policy.next_delay(attempt)).collect()
+    }
+
+    #[test]
+    fn fixed() {
+        assert_eq!(
+            delays(BackoffPolicy::fixed(10 * MS, 3)),
+            [Some(10 * MS), Some(10 * MS), Some(10 * MS), None, None]
+        );
+        assert_eq!(delays(BackoffPolicy::fixed(10 * MS, 0)), [None; 5]);
+    }
+
+    #[test]
+    fn exponential() {
+        assert_eq!(
+            delays(BackoffPolicy::exponential(10 * MS, 50 * MS, 4)),
+            [
+                Some(10 * MS),
+                Some(20 * MS),
+                Some(40 * MS),
+                Some(50 * MS),
+                None
+            ]
+        );
+
+        // The delay is capped rather than overflowing
+        let policy = BackoffPolicy::exponential(Duration::from_secs(1), Duration::MAX, 100);
+        assert_eq!(policy.next_delay(100), Some(Duration::MAX));
+    }
+
+    #[test]
+    fn exponential_with_jitter() {
+        let policy = BackoffPolicy::exponential_with_jitter(10 * MS, 50 * MS, 4);
+        let caps = [10 * MS, 20 * MS, 40 * MS, 50 * MS];
+        let mut first = Vec::new();
+        for _ in 0..100 {
+            for (attempt, cap) in (1..).zip(caps) {
+                assert!(policy.next_delay(attempt).unwrap() <= cap);
+            }
+            assert_eq!(policy.next_delay(5), None);
+            first.push(policy.next_delay(1));
+        }
+
+        // The delays actually vary
+        first.sort();
+        first.dedup();
+        assert!(first.len() > 1);
+    }
+}
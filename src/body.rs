@@ -0,0 +1,242 @@
+//! Response bodies that resolve the request's transaction once they've been sent.
+
+use std::{
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+use bytes::{Buf, Bytes};
+use futures_core::{future::BoxFuture, ready};
+use http_body::{combinators::UnsyncBoxBody, Body};
+
+use crate::{tx::TxSlot, Error};
+
+/// The body of responses returned by the [`Service`](crate::Service) middleware.
+///
+/// This is the same as `axum::body::BoxBody`, so the middleware can be used anywhere in an `axum`
+/// app without mapping the body. Every response the middleware returns has this body type,
+/// whether it's the handler's (including streaming responses), a rejection from extracting
+/// [`Tx`](crate::Tx) (which axum builds as an `axum::response::Response`), or the middleware's own
+/// response for a failed commit.
+pub type ResponseBody = UnsyncBoxBody<Bytes, axum_core::Error>;
+
+/// Box any body with a [`Buf`] data type into a [`ResponseBody`].
+pub(crate) fn boxed<B>(body: B) -> ResponseBody
+where
+    B: Body + Send + 'static,
+    B::Error: Into<Box<dyn std::error::Error + Send + Sync + 'static>>,
+{
+    body.map_data(|mut data| data.copy_to_bytes(data.remaining()))
+        .map_err(axum_core::Error::new)
+        .boxed_unsync()
+}
+
+/// A body that commits the transaction after the inner body has been fully sent.
+///
+/// This is used when the transaction is still leased at the time the response is returned (e.g.
+/// because it's owned by a [`TxStream`](crate::TxStream) in the body). If the body is dropped
+/// before completion, or the inner body fails, the transaction is dropped and so rolled back.
+pub(crate) struct CommitOnEnd {
+    inner: ResponseBody,
+    state: State,
+}
+
+enum State {
+    Streaming(TxSlot),
+    Committing(BoxFuture<'static, Result<(), Error>>),
+    Done,
+}
+
+impl CommitOnEnd {
+    pub(crate) fn new(inner: ResponseBody, transaction: TxSlot) -> Self {
+        Self {
+            inner,
+            state: State::Streaming(transaction),
+        }
+    }
+}
+
+impl Body for CommitOnEnd {
+    type Data = Bytes;
+    type Error = axum_core::Error;
+
+    fn poll_data(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Self::Data, Self::Error>>> {
+        loop {
+            match &mut self.state {
+                State::Streaming(_) => match Pin::new(&mut self.inner).poll_data(cx) {
+                    Poll::Ready(None) => {
+                        let State::Streaming(transaction) =
+                            std::mem::replace(&mut self.state, State::Done)
+                        else {
+                            unreachable!()
+                        };
+                        self.state = State::Committing(Box::pin(async move {
+                            transaction.commit(None).await.map(|_| ())
+                        }));
+                    }
+                    Poll::Ready(Some(Err(error))) => {
+                        // Dropping the slot will roll back the transaction.
+                        self.state = State::Done;
+                        return Poll::Ready(Some(Err(error)));
+                    }
+                    poll => return poll,
+                },
+                State::Committing(commit) => {
+                    let result = futures_core::ready!(commit.as_mut().poll(cx));
+                    self.state = State::Done;
+                    return Poll::Ready(
+                        result.err().map(|error| Err(axum_core::Error::new(error))),
+                    );
+                }
+                State::Done => return Poll::Ready(None),
+            }
+        }
+    }
+
+    fn poll_trailers(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<Option<http::HeaderMap>, Self::Error>> {
+        Pin::new(&mut self.inner).poll_trailers(cx)
+    }
+
+    fn is_end_stream(&self) -> bool {
+        matches!(self.state, State::Done)
+    }
+
+    fn size_hint(&self) -> http_body::SizeHint {
+        self.inner.size_hint()
+    }
+}
+
+/// A body that commits or rolls back the transaction depending on the inner body's trailers.
+///
+/// This is used by [`Layer::commit_on_trailer`](crate::Layer::commit_on_trailer). Once the inner
+/// body's data has been sent, its trailers are read, and the transaction is committed if the
+/// trailer matches (or rolled back otherwise) before the end of the body is signalled. If the body
+/// is dropped before completion, or the inner body fails, the transaction is dropped and so rolled
+/// back.
+pub(crate) struct CommitOnTrailer {
+    inner: ResponseBody,
+    trailer: (http::HeaderName, http::HeaderValue),
+    state: TrailerState,
+}
+
+enum TrailerState {
+    Streaming(TxSlot),
+    Trailers(TxSlot),
+    Resolving(
+        BoxFuture<'static, Result<(), Error>>,
+        Option<http::HeaderMap>,
+    ),
+    Done(Option<http::HeaderMap>),
+}
+
+impl CommitOnTrailer {
+    pub(crate) fn new(
+        inner: ResponseBody,
+        transaction: TxSlot,
+        trailer: (http::HeaderName, http::HeaderValue),
+    ) -> Self {
+        Self {
+            inner,
+            trailer,
+            state: TrailerState::Streaming(transaction),
+        }
+    }
+}
+
+impl Body for CommitOnTrailer {
+    type Data = Bytes;
+    type Error = axum_core::Error;
+
+    fn poll_data(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Self::Data, Self::Error>>> {
+        let this = &mut *self;
+        loop {
+            match &mut this.state {
+                TrailerState::Streaming(_) => match Pin::new(&mut this.inner).poll_data(cx) {
+                    Poll::Ready(None) => {
+                        let TrailerState::Streaming(transaction) =
+                            std::mem::replace(&mut this.state, TrailerState::Done(None))
+                        else {
+                            unreachable!()
+                        };
+                        this.state = TrailerState::Trailers(transaction);
+                    }
+                    Poll::Ready(Some(Err(error))) => {
+                        // Dropping the slot will roll back the transaction.
+                        this.state = TrailerState::Done(None);
+                        return Poll::Ready(Some(Err(error)));
+                    }
+                    poll => return poll,
+                },
+                TrailerState::Trailers(_) => {
+                    let trailers = match ready!(Pin::new(&mut this.inner).poll_trailers(cx)) {
+                        Ok(trailers) => trailers,
+                        Err(error) => {
+                            this.state = TrailerState::Done(None);
+                            return Poll::Ready(Some(Err(error)));
+                        }
+                    };
+                    let TrailerState::Trailers(transaction) =
+                        std::mem::replace(&mut this.state, TrailerState::Done(None))
+                    else {
+                        unreachable!()
+                    };
+
+                    let (name, value) = &this.trailer;
+                    let commit = trailers
+                        .as_ref()
+                        .and_then(|trailers| trailers.get(name))
+                        .is_some_and(|trailer| trailer == value);
+                    let resolve: BoxFuture<'static, _> = if commit {
+                        Box::pin(async move { transaction.commit(None).await.map(|_| ()) })
+                    } else {
+                        Box::pin(async move {
+                            transaction.rollback().await;
+                            Ok(())
+                        })
+                    };
+                    this.state = TrailerState::Resolving(resolve, trailers);
+                }
+                TrailerState::Resolving(resolve, trailers) => {
+                    let result = ready!(resolve.as_mut().poll(cx));
+                    this.state = TrailerState::Done(trailers.take());
+                    return Poll::Ready(
+                        result.err().map(|error| Err(axum_core::Error::new(error))),
+                    );
+                }
+                TrailerState::Done(_) => return Poll::Ready(None),
+            }
+        }
+    }
+
+    fn poll_trailers(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<Option<http::HeaderMap>, Self::Error>> {
+        // The trailers are read (and the transaction resolved) at the end of the data
+        while let Some(data) = ready!(self.as_mut().poll_data(cx)) {
+            // Data left unread is discarded, as by any body's consumer that skips to the trailers
+            drop(data?);
+        }
+        match &mut self.state {
+            TrailerState::Done(trailers) => Poll::Ready(Ok(trailers.take())),
+            _ => unreachable!("BUG: body data ended before the transaction was resolved"),
+        }
+    }
+
+    fn is_end_stream(&self) -> bool {
+        matches!(self.state, TrailerState::Done(None))
+    }
+
+    fn size_hint(&self) -> http_body::SizeHint {
+        self.inner.size_hint()
+    }
+}
@@ -0,0 +1,104 @@
+//! A layer overriding how the request's transaction is begun, for the routes it's applied to.
+
+use std::task::{Context, Poll};
+
+use sea_orm::{AccessMode, IsolationLevel};
+
+use crate::tx::BeginOptions;
+
+/// A [`tower_layer::Layer`] that overrides the [`Layer`](crate::Layer)'s transaction settings for
+/// the routes it's applied to.
+///
+/// This is for routes that need different settings from the rest of the app, without changing
+/// their handlers' signatures (as with an [`IsolationMarker`](crate::IsolationMarker)):
+///
+/// ```
+/// # async fn foo() {
+/// # let pool: sea_orm::DatabaseConnection = todo!();
+/// use axum_sea_orm_tx::TxConfig;
+/// use sea_orm::IsolationLevel;
+///
+/// let app = axum::Router::new()
+///     .route("/transfers", axum::routing::post(|| async { /* ... */ }))
+///     .route_layer(TxConfig::new().isolation_level(IsolationLevel::Serializable))
+///     .layer(axum_sea_orm_tx::Layer::new(pool));
+/// # axum::Server::bind(todo!()).serve(app.into_make_service());
+/// # }
+/// ```
+///
+/// The settings are stored in the request extensions, and used when the transaction is begun by a
+/// [`Tx`](crate::Tx) extracted further in, so the `TxConfig` must be inside the `Layer` (e.g.
+/// added with [`Router::route_layer`](axum::Router::route_layer) while the `Layer` is added with
+/// [`Router::layer`](axum::Router::layer)). They take precedence over the `Layer`'s settings, but
+/// not over an `IsolationMarker`'s, and requests routed to the replica of a
+/// [`Layer::read_write_split`](crate::Layer::read_write_split) layer are always read-only.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct TxConfig {
+    isolation_level: Option<IsolationLevel>,
+    access_mode: Option<AccessMode>,
+}
+
+impl TxConfig {
+    /// A `TxConfig` that doesn't override anything.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Begin transactions with the isolation level `level`, see
+    /// [`Layer::isolation_level`](crate::Layer::isolation_level).
+    pub fn isolation_level(mut self, level: IsolationLevel) -> Self {
+        self.isolation_level = Some(level);
+        self
+    }
+
+    /// Begin transactions with the access mode `mode`, see
+    /// [`Layer::access_mode`](crate::Layer::access_mode).
+    pub fn access_mode(mut self, mode: AccessMode) -> Self {
+        self.access_mode = Some(mode);
+        self
+    }
+
+    /// Override the settings in `options` with these.
+    pub(crate) fn apply(&self, options: &mut BeginOptions) {
+        options.isolation_level = self.isolation_level.or(options.isolation_level);
+        options.access_mode = self.access_mode.or(options.access_mode);
+    }
+}
+
+impl<S> tower_layer::Layer<S> for TxConfig {
+    type Service = TxConfigService<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        TxConfigService {
+            inner,
+            config: *self,
+        }
+    }
+}
+
+/// A [`tower_service::Service`] that overrides the [`Layer`](crate::Layer)'s transaction settings.
+///
+/// See [`TxConfig`].
+#[derive(Debug, Clone)]
+pub struct TxConfigService<S> {
+    inner: S,
+    config: TxConfig,
+}
+
+impl<S, ReqBody> tower_service::Service<http::Request<ReqBody>> for TxConfigService<S>
+where
+    S: tower_service::Service<http::Request<ReqBody>>,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = S::Future;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, mut req: http::Request<ReqBody>) -> Self::Future {
+        req.extensions_mut().insert(self.config);
+        self.inner.call(req)
+    }
+}
@@ -0,0 +1,420 @@
+//! The connection leased to a [`Tx`](crate::Tx).
+
+use std::{pin::Pin, sync::Arc};
+
+use async_trait::async_trait;
+use futures_core::Future;
+use sea_orm::{
+    AccessMode, ConnectionTrait, DatabaseTransaction, DbBackend, DbErr, ExecResult, IsolationLevel,
+    QueryResult, Statement, StreamTrait, TransactionError, TransactionTrait,
+};
+
+use crate::{runtime::Sleep, BackoffPolicy, Error};
+
+/// A pool connection that statements can be run on directly, without a transaction.
+pub(crate) type AutocommitConnection = Arc<dyn ConnectionTrait + Send + Sync>;
+
+/// The connection that a request's statements are run on.
+///
+/// This is normally a transaction, but may be the pool itself when transactions are not supported
+/// and [`Layer::autocommit_fallback`](crate::Layer::autocommit_fallback) is enabled. It's closed if
+/// a transaction couldn't be begun in place of one the handler resolved early, see
+/// [`Error::Closed`].
+pub(crate) enum Connection {
+    Transaction(DatabaseTransaction),
+    Autocommit(AutocommitConnection),
+    Closed(DbBackend),
+}
+
+impl Connection {
+    /// Get the transaction.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the connection is closed. Only [`AutocommitTx`](crate::AutocommitTx) can hold an
+    /// autocommit connection, and it doesn't expose the transaction.
+    pub(crate) fn as_transaction(&self) -> &DatabaseTransaction {
+        match self {
+            Self::Transaction(tx) => tx,
+            Self::Autocommit(_) => {
+                unreachable!("BUG: autocommit connections are only for AutocommitTx")
+            }
+            Self::Closed(_) => panic!("{}", Error::Closed),
+        }
+    }
+
+    /// Get the transaction mutably.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the connection is closed. Only [`AutocommitTx`](crate::AutocommitTx) can hold an
+    /// autocommit connection, and it doesn't expose the transaction.
+    pub(crate) fn as_transaction_mut(&mut self) -> &mut DatabaseTransaction {
+        match self {
+            Self::Transaction(tx) => tx,
+            Self::Autocommit(_) => {
+                unreachable!("BUG: autocommit connections are only for AutocommitTx")
+            }
+            Self::Closed(_) => panic!("{}", Error::Closed),
+        }
+    }
+
+    /// Commit the transaction, if any.
+    ///
+    /// In autocommit mode every statement has already been committed, so this does nothing. A
+    /// closed connection has nothing left to commit, so fails.
+    pub(crate) async fn commit(self) -> Result<(), DbErr> {
+        match self {
+            Self::Transaction(tx) => tx.commit().await,
+            Self::Autocommit(_) => Ok(()),
+            Self::Closed(_) => Err(closed()),
+        }
+    }
+
+    /// Commit the transaction, retrying as long as `backoff` allows if attempts fail because the
+    /// database is locked.
+    ///
+    /// A failed `COMMIT` aborts the transaction on most backends, so there's nothing to retry. On
+    /// SQLite, though, a `COMMIT` that fails with `SQLITE_BUSY` leaves the transaction open. SeaORM
+    /// consumes the transaction when committing whether or not it succeeds, so on SQLite `COMMIT`
+    /// is run as a plain statement, and an empty transaction is begun afterwards for SeaORM to
+    /// commit.
+    ///
+    /// `sleep` waits between attempts, and may only be `None` if `backoff` retries immediately.
+    pub(crate) async fn commit_retrying(
+        self,
+        backoff: &BackoffPolicy,
+        sleep: Option<Sleep>,
+    ) -> Result<(), DbErr> {
+        let tx = match self {
+            Self::Transaction(tx) if tx.get_database_backend() == DbBackend::Sqlite => tx,
+            conn => return conn.commit().await,
+        };
+
+        retry_if_locked(backoff, sleep, || async {
+            tx.execute_unprepared("COMMIT").await.map(drop)
+        })
+        .await?;
+        tx.execute_unprepared("BEGIN").await?;
+        tx.commit().await
+    }
+
+    /// Prepare the transaction for two-phase commit with the given global transaction identifier,
+    /// instead of committing it.
+    ///
+    /// This is only supported on Postgres, and not in autocommit mode.
+    pub(crate) async fn prepare(self, gid: &str) -> Result<(), DbErr> {
+        let tx = match self {
+            Self::Transaction(tx) => tx,
+            Self::Autocommit(_) => return Err(autocommit_unsupported("two-phase commit")),
+            Self::Closed(_) => return Err(closed()),
+        };
+        if tx.get_database_backend() != DbBackend::Postgres {
+            return Err(DbErr::Custom(
+                "two-phase commit is only supported on Postgres".to_string(),
+            ));
+        }
+
+        tx.execute_unprepared(&format!("PREPARE TRANSACTION '{gid}'"))
+            .await?;
+        // The session is no longer in a transaction, so this only releases the connection (with
+        // a "no transaction in progress" warning from Postgres)
+        tx.commit().await
+    }
+
+    /// Roll back the transaction, if any.
+    ///
+    /// In autocommit mode every statement has already been committed, and a closed connection
+    /// has nothing left to roll back, so this does nothing.
+    pub(crate) async fn rollback(self) -> Result<(), DbErr> {
+        match self {
+            Self::Transaction(tx) => tx.rollback().await,
+            Self::Autocommit(_) | Self::Closed(_) => Ok(()),
+        }
+    }
+}
+
+/// Run `commit`, and run it again (after the delay given by `backoff`) if it fails because the
+/// database is locked.
+pub(crate) async fn retry_if_locked<F, Fut>(
+    backoff: &BackoffPolicy,
+    sleep: Option<Sleep>,
+    mut commit: F,
+) -> Result<(), DbErr>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<(), DbErr>>,
+{
+    let mut attempt = 1;
+    loop {
+        let error = match commit().await {
+            Err(error) if is_locked(&error) => error,
+            result => return result,
+        };
+        let Some(delay) = backoff.next_delay(attempt) else {
+            return Err(error);
+        };
+
+        #[cfg(feature = "tracing")]
+        tracing::warn!(%error, attempt, ?delay, "database locked, retrying request transaction commit");
+        if !delay.is_zero() {
+            let sleep = sleep.expect("BUG: commit backoff without a runtime");
+            sleep(delay).await;
+        }
+        attempt += 1;
+    }
+}
+
+/// Check whether `error` is SQLite's `SQLITE_BUSY` or `SQLITE_LOCKED`.
+///
+/// SeaORM doesn't expose the database's error codes, so this matches SQLite's messages.
+pub(crate) fn is_locked(error: &DbErr) -> bool {
+    let message = error.to_string();
+    message.contains("database is locked") || message.contains("database table is locked")
+}
+
+/// The error for statements run on a closed connection, see [`Error::Closed`].
+pub(crate) fn closed() -> DbErr {
+    DbErr::Custom(Error::Closed.to_string())
+}
+
+pub(crate) fn autocommit_unsupported(operation: &str) -> DbErr {
+    DbErr::Custom(format!(
+        "{operation} is not supported when axum_sea_orm_tx::Tx is in autocommit mode"
+    ))
+}
+
+impl std::fmt::Debug for Connection {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Transaction(tx) => f.debug_tuple("Transaction").field(tx).finish(),
+            Self::Autocommit(conn) => f
+                .debug_tuple("Autocommit")
+                .field(&conn.get_database_backend())
+                .finish(),
+            Self::Closed(backend) => f.debug_tuple("Closed").field(backend).finish(),
+        }
+    }
+}
+
+#[async_trait]
+impl ConnectionTrait for Connection {
+    fn get_database_backend(&self) -> DbBackend {
+        match self {
+            Self::Transaction(tx) => tx.get_database_backend(),
+            Self::Autocommit(conn) => conn.get_database_backend(),
+            Self::Closed(backend) => *backend,
+        }
+    }
+
+    fn support_returning(&self) -> bool {
+        match self {
+            Self::Transaction(tx) => tx.support_returning(),
+            Self::Autocommit(conn) => conn.support_returning(),
+            Self::Closed(_) => false,
+        }
+    }
+
+    fn is_mock_connection(&self) -> bool {
+        match self {
+            Self::Transaction(tx) => tx.is_mock_connection(),
+            Self::Autocommit(conn) => conn.is_mock_connection(),
+            Self::Closed(_) => false,
+        }
+    }
+
+    async fn execute(&self, stmt: Statement) -> Result<ExecResult, DbErr> {
+        match self {
+            Self::Transaction(tx) => tx.execute(stmt).await,
+            Self::Autocommit(conn) => conn.execute(stmt).await,
+            Self::Closed(_) => Err(closed()),
+        }
+    }
+
+    async fn execute_unprepared(&self, sql: &str) -> Result<ExecResult, DbErr> {
+        match self {
+            Self::Transaction(tx) => tx.execute_unprepared(sql).await,
+            Self::Autocommit(conn) => conn.execute_unprepared(sql).await,
+            Self::Closed(_) => Err(closed()),
+        }
+    }
+
+    async fn query_one(&self, stmt: Statement) -> Result<Option<QueryResult>, DbErr> {
+        match self {
+            Self::Transaction(tx) => tx.query_one(stmt).await,
+            Self::Autocommit(conn) => conn.query_one(stmt).await,
+            Self::Closed(_) => Err(closed()),
+        }
+    }
+
+    async fn query_all(&self, stmt: Statement) -> Result<Vec<QueryResult>, DbErr> {
+        match self {
+            Self::Transaction(tx) => tx.query_all(stmt).await,
+            Self::Autocommit(conn) => conn.query_all(stmt).await,
+            Self::Closed(_) => Err(closed()),
+        }
+    }
+}
+
+impl StreamTrait for Connection {
+    type Stream<'a> = <DatabaseTransaction as StreamTrait>::Stream<'a>;
+
+    fn stream<'a>(
+        &'a self,
+        stmt: Statement,
+    ) -> Pin<Box<dyn Future<Output = Result<Self::Stream<'a>, DbErr>> + 'a + Send>> {
+        match self {
+            Self::Transaction(tx) => tx.stream(stmt),
+            Self::Autocommit(_) => Box::pin(async { Err(autocommit_unsupported("streaming")) }),
+            Self::Closed(_) => Box::pin(async { Err(closed()) }),
+        }
+    }
+}
+
+#[async_trait]
+impl TransactionTrait for Connection {
+    async fn begin(&self) -> Result<DatabaseTransaction, DbErr> {
+        self.begin_with_config(None, None).await
+    }
+
+    async fn begin_with_config(
+        &self,
+        isolation_level: Option<IsolationLevel>,
+        access_mode: Option<AccessMode>,
+    ) -> Result<DatabaseTransaction, DbErr> {
+        match self {
+            Self::Transaction(tx) => tx.begin_with_config(isolation_level, access_mode).await,
+            Self::Autocommit(_) => Err(autocommit_unsupported("beginning a transaction")),
+            Self::Closed(_) => Err(closed()),
+        }
+    }
+
+    async fn transaction<F, T, E>(&self, callback: F) -> Result<T, TransactionError<E>>
+    where
+        F: for<'c> FnOnce(
+                &'c DatabaseTransaction,
+            ) -> Pin<Box<dyn Future<Output = Result<T, E>> + Send + 'c>>
+            + Send,
+        T: Send,
+        E: std::error::Error + Send,
+    {
+        self.transaction_with_config(callback, None, None).await
+    }
+
+    async fn transaction_with_config<F, T, E>(
+        &self,
+        callback: F,
+        isolation_level: Option<IsolationLevel>,
+        access_mode: Option<AccessMode>,
+    ) -> Result<T, TransactionError<E>>
+    where
+        F: for<'c> FnOnce(
+                &'c DatabaseTransaction,
+            ) -> Pin<Box<dyn Future<Output = Result<T, E>> + Send + 'c>>
+            + Send,
+        T: Send,
+        E: std::error::Error + Send,
+    {
+        match self {
+            Self::Transaction(tx) => {
+                tx.transaction_with_config(callback, isolation_level, access_mode)
+                    .await
+            }
+            Self::Autocommit(_) => Err(TransactionError::Connection(autocommit_unsupported(
+                "beginning a transaction",
+            ))),
+            Self::Closed(_) => Err(TransactionError::Connection(closed())),
+        }
+    }
+}
+
+/// Check whether `error`, returned when beginning a transaction, indicates that the connection
+/// doesn't support transactions at all.
+///
+/// SeaORM doesn't expose a structured error for this, so this matches the messages of known cases:
+///
+/// - PgBouncer in statement pooling mode rejects transaction blocks.
+/// - Postgres `feature_not_supported` errors that mention transactions.
+pub(crate) fn transactions_unsupported(error: &DbErr) -> bool {
+    let message = error.to_string().to_lowercase();
+    message.contains("transaction blocks not allowed")
+        || (message.contains("not supported") && message.contains("transaction"))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{sync::Mutex, time::Duration};
+
+    use sea_orm::{DbErr, RuntimeErr};
+
+    use super::retry_if_locked;
+    use crate::{runtime::default_sleep, BackoffPolicy};
+
+    /// Run `retry_if_locked` with a fake commit that returns `results` in order, returning its
+    /// result and how many times the commit was attempted.
+    async fn commit_with(results: Vec<Result<(), &str>>) -> (Result<(), DbErr>, usize) {
+        commit_with_backoff(BackoffPolicy::fixed(Duration::ZERO, 1), results).await
+    }
+
+    /// Like `commit_with`, but retrying according to `backoff`.
+    async fn commit_with_backoff(
+        backoff: BackoffPolicy,
+        results: Vec<Result<(), &str>>,
+    ) -> (Result<(), DbErr>, usize) {
+        let results = Mutex::new(results.into_iter());
+        let attempts = Mutex::new(0);
+        let result =
+            retry_if_locked(&backoff, default_sleep(), || {
+                *attempts.lock().unwrap() += 1;
+                let result = results.lock().unwrap().next().expect("too many attempts");
+                async move {
+                    result.map_err(|error| DbErr::Exec(RuntimeErr::Internal(error.to_string())))
+                }
+            })
+            .await;
+        (result, attempts.into_inner().unwrap())
+    }
+
+    #[tokio::test]
+    async fn retries_locked_commits() {
+        // The first commit fails transiently, and the retry succeeds
+        let (result, attempts) = commit_with(vec![Err("database is locked"), Ok(())]).await;
+        assert!(result.is_ok());
+        assert_eq!(attempts, 2);
+
+        // The retry fails too
+        let (result, attempts) =
+            commit_with(vec![Err("database is locked"), Err("database is locked")]).await;
+        assert!(result
+            .unwrap_err()
+            .to_string()
+            .contains("database is locked"));
+        assert_eq!(attempts, 2);
+
+        // Other errors abort the transaction, so aren't retried
+        let (result, attempts) = commit_with(vec![Err("FOREIGN KEY constraint failed")]).await;
+        assert!(result.is_err());
+        assert_eq!(attempts, 1);
+
+        let (result, attempts) = commit_with(vec![Ok(())]).await;
+        assert!(result.is_ok());
+        assert_eq!(attempts, 1);
+    }
+
+    // Sleeping between attempts needs a runtime
+    #[cfg(any(feature = "runtime-tokio", feature = "runtime-async-std"))]
+    #[tokio::test]
+    async fn retries_with_backoff() {
+        let backoff = BackoffPolicy::exponential(Duration::from_millis(1), Duration::MAX, 3);
+        let locked = Err("database is locked");
+
+        let (result, attempts) = commit_with_backoff(backoff, vec![locked, locked, Ok(())]).await;
+        assert!(result.is_ok());
+        assert_eq!(attempts, 3);
+
+        // Retries are exhausted
+        let (result, attempts) = commit_with_backoff(backoff, vec![locked; 4]).await;
+        assert!(result.is_err());
+        assert_eq!(attempts, 4);
+    }
+}
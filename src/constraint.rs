@@ -0,0 +1,181 @@
+//! Structured details of constraint violations.
+
+use sea_orm::DbErr;
+
+/// The kind of constraint that was violated.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum ConstraintKind {
+    /// A `UNIQUE` constraint or primary key.
+    Unique,
+
+    /// A `FOREIGN KEY` constraint.
+    ForeignKey,
+
+    /// A `CHECK` constraint.
+    Check,
+}
+
+/// A constraint violation, parsed from a database error.
+///
+/// See [`Error::ConstraintViolation`](crate::Error::ConstraintViolation).
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct ConstraintViolation {
+    /// The kind of constraint that was violated.
+    pub kind: ConstraintKind,
+
+    /// The name of the violated constraint, if the database reported it.
+    pub constraint_name: Option<String>,
+
+    /// The table the constraint belongs to, if the database reported it.
+    pub table: Option<String>,
+}
+
+impl ConstraintViolation {
+    /// Parse a constraint violation from `error`.
+    ///
+    /// SeaORM doesn't expose the database's structured error details, so this matches the messages
+    /// reported by Postgres and SQLite. Postgres doesn't include the table in unique violation
+    /// messages, and SQLite doesn't name foreign key constraints, so those fields are `None`.
+    pub(crate) fn parse(error: &DbErr) -> Option<Self> {
+        let message = error.to_string();
+        Self::parse_postgres(&message).or_else(|| Self::parse_sqlite(&message))
+    }
+
+    fn parse_postgres(message: &str) -> Option<Self> {
+        let (kind, table) = if message.contains("violates unique constraint") {
+            (ConstraintKind::Unique, None)
+        } else if message.contains("violates foreign key constraint") {
+            (
+                ConstraintKind::ForeignKey,
+                quoted_after(message, "on table "),
+            )
+        } else if message.contains("violates check constraint") {
+            (
+                ConstraintKind::Check,
+                quoted_after(message, "for relation "),
+            )
+        } else {
+            return None;
+        };
+
+        Some(Self {
+            kind,
+            constraint_name: quoted_after(message, "constraint "),
+            table,
+        })
+    }
+
+    fn parse_sqlite(message: &str) -> Option<Self> {
+        if let Some(columns) = after(message, "UNIQUE constraint failed: ") {
+            // e.g. "users.id, users.name"
+            let table = columns.split_once('.').map(|(table, _)| table.to_string());
+            Some(Self {
+                kind: ConstraintKind::Unique,
+                constraint_name: None,
+                table,
+            })
+        } else if message.contains("FOREIGN KEY constraint failed") {
+            Some(Self {
+                kind: ConstraintKind::ForeignKey,
+                constraint_name: None,
+                table: None,
+            })
+        } else {
+            after(message, "CHECK constraint failed: ").map(|name| Self {
+                kind: ConstraintKind::Check,
+                constraint_name: Some(name.to_string()),
+                table: None,
+            })
+        }
+    }
+}
+
+/// The rest of the line following `prefix` in `message`.
+fn after<'a>(message: &'a str, prefix: &str) -> Option<&'a str> {
+    let (_, rest) = message.split_once(prefix)?;
+    let rest = rest.lines().next().unwrap_or_default().trim();
+    (!rest.is_empty()).then_some(rest)
+}
+
+/// The double-quoted identifier following `prefix` in `message`.
+fn quoted_after(message: &str, prefix: &str) -> Option<String> {
+    let (_, rest) = message.split_once(&format!("{prefix}\""))?;
+    let (name, _) = rest.split_once('"')?;
+    Some(name.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use sea_orm::DbErr;
+
+    use super::{ConstraintKind, ConstraintViolation};
+
+    fn parse(message: &str) -> Option<ConstraintViolation> {
+        ConstraintViolation::parse(&DbErr::Custom(message.to_string()))
+    }
+
+    #[test]
+    fn postgres() {
+        assert_eq!(
+            parse(r#"duplicate key value violates unique constraint "users_pkey""#),
+            Some(ConstraintViolation {
+                kind: ConstraintKind::Unique,
+                constraint_name: Some("users_pkey".to_string()),
+                table: None,
+            })
+        );
+        assert_eq!(
+            parse(
+                r#"insert or update on table "comments" violates foreign key constraint "comments_user_id_fkey""#
+            ),
+            Some(ConstraintViolation {
+                kind: ConstraintKind::ForeignKey,
+                constraint_name: Some("comments_user_id_fkey".to_string()),
+                table: Some("comments".to_string()),
+            })
+        );
+        assert_eq!(
+            parse(r#"new row for relation "users" violates check constraint "name_not_empty""#),
+            Some(ConstraintViolation {
+                kind: ConstraintKind::Check,
+                constraint_name: Some("name_not_empty".to_string()),
+                table: Some("users".to_string()),
+            })
+        );
+    }
+
+    #[test]
+    fn sqlite() {
+        assert_eq!(
+            parse("UNIQUE constraint failed: users.id"),
+            Some(ConstraintViolation {
+                kind: ConstraintKind::Unique,
+                constraint_name: None,
+                table: Some("users".to_string()),
+            })
+        );
+        assert_eq!(
+            parse("FOREIGN KEY constraint failed"),
+            Some(ConstraintViolation {
+                kind: ConstraintKind::ForeignKey,
+                constraint_name: None,
+                table: None,
+            })
+        );
+        assert_eq!(
+            parse("CHECK constraint failed: name_not_empty"),
+            Some(ConstraintViolation {
+                kind: ConstraintKind::Check,
+                constraint_name: Some("name_not_empty".to_string()),
+                table: None,
+            })
+        );
+    }
+
+    #[test]
+    fn other() {
+        assert_eq!(parse("connection reset by peer"), None);
+    }
+}
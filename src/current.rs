@@ -0,0 +1,109 @@
+//! Access to the request's transaction from code it isn't passed to, see [`current_tx`].
+
+use std::{
+    any::Any,
+    cell::RefCell,
+    future::Future,
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll},
+};
+
+use pin_project_lite::pin_project;
+use sea_orm::TransactionTrait;
+
+use crate::SharedTx;
+
+type Current = Arc<dyn Any + Send + Sync>;
+
+thread_local! {
+    /// The [`SharedTx`] of the [`Scoped`] future being polled on this thread, if any.
+    static CURRENT: RefCell<Option<Current>> = RefCell::new(None);
+}
+
+pin_project! {
+    /// A future that makes a [`SharedTx`] the [`current_tx`] while it's polled, see
+    /// [`SharedTx::scope`].
+    pub(crate) struct Scoped<F> {
+        #[pin]
+        future: F,
+        tx: Option<Current>,
+    }
+}
+
+impl<F> Scoped<F> {
+    pub(crate) fn new<C, E, I>(tx: SharedTx<C, E, I>, future: F) -> Self
+    where
+        C: TransactionTrait + Send + 'static,
+        E: Send + 'static,
+        I: Send + 'static,
+    {
+        Self {
+            future,
+            tx: Some(Arc::new(tx)),
+        }
+    }
+}
+
+impl<F: Future> Future for Scoped<F> {
+    type Output = F::Output;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.project();
+        let previous = CURRENT.with(|current| current.replace(this.tx.clone()));
+        // Restored on drop, so a panicking future doesn't leave its transaction behind
+        let _restore = Restore(previous);
+
+        let poll = this.future.poll(cx);
+        if poll.is_ready() {
+            // Drop the handle straight away, since the transaction is held until every handle is
+            *this.tx = None;
+        }
+        poll
+    }
+}
+
+/// Restores the previous [`CURRENT`] transaction when dropped.
+struct Restore(Option<Current>);
+
+impl Drop for Restore {
+    fn drop(&mut self) {
+        CURRENT.with(|current| *current.borrow_mut() = self.0.take());
+    }
+}
+
+/// Get the request's transaction, from code running in [`SharedTx::scope`].
+///
+/// This is for code that's called deep down from a handler, and can't easily be changed to take
+/// the transaction as an argument:
+///
+/// ```
+/// use axum_sea_orm_tx::{current_tx, SharedTx, Tx};
+/// use sea_orm::{ConnectionTrait, DatabaseConnection, DbErr};
+///
+/// async fn handler(tx: Tx<DatabaseConnection>) -> Result<(), DbErr> {
+///     tx.into_shared().scope(legacy_code()).await
+/// }
+///
+/// async fn legacy_code() -> Result<(), DbErr> {
+///     let tx: SharedTx<DatabaseConnection> = current_tx().expect("not in a request");
+///     tx.execute_unprepared("INSERT INTO events DEFAULT VALUES").await?;
+///     Ok(())
+/// }
+/// ```
+///
+/// Returns `None` if the caller isn't running in [`SharedTx::scope`], or the transaction's type
+/// parameters don't match those given to `current_tx`. The transaction is only available to the
+/// future passed to `scope`, not to tasks it spawns, which must be given a handle explicitly.
+pub fn current_tx<C, E, I>() -> Option<SharedTx<C, E, I>>
+where
+    C: TransactionTrait + Send + 'static,
+    E: Send + 'static,
+    I: Send + 'static,
+{
+    CURRENT.with(|current| {
+        (current.borrow().as_ref()?)
+            .downcast_ref::<SharedTx<C, E, I>>()
+            .cloned()
+    })
+}
@@ -0,0 +1,87 @@
+//! A service that turns an inner service's errors into responses, so the [`Service`] middleware
+//! can resolve the transaction for them like any other response.
+//!
+//! [`Service`]: crate::Service
+
+use std::convert::Infallible;
+
+use axum_core::response::IntoResponse;
+use futures_core::future::BoxFuture;
+use futures_util::future::poll_fn;
+use http_body::Body;
+
+use crate::{
+    body::{boxed, ResponseBody},
+    response::Resolution,
+};
+
+/// A [`tower_layer::Layer`] that converts the inner service's errors into responses.
+///
+/// See [`Layer::handle_inner_errors`](crate::Layer::handle_inner_errors).
+#[derive(Debug, Clone, Copy, Default)]
+pub struct HandleInnerErrorsLayer;
+
+impl<S> tower_layer::Layer<S> for HandleInnerErrorsLayer {
+    type Service = HandleInnerErrors<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        HandleInnerErrors { inner }
+    }
+}
+
+/// A [`tower_service::Service`] that converts the inner service's errors into responses.
+///
+/// The error responses always roll back the request's transaction, whatever their status. See
+/// [`Layer::handle_inner_errors`](crate::Layer::handle_inner_errors).
+#[derive(Debug, Clone)]
+pub struct HandleInnerErrors<S> {
+    inner: S,
+}
+
+impl<S, ReqBody, ResBody> tower_service::Service<http::Request<ReqBody>> for HandleInnerErrors<S>
+where
+    S: tower_service::Service<http::Request<ReqBody>, Response = http::Response<ResBody>>
+        + Clone
+        + Send
+        + 'static,
+    S::Error: IntoResponse + Send,
+    S::Future: Send,
+    ReqBody: Send + 'static,
+    ResBody: Body + Send + 'static,
+    ResBody::Error: Into<Box<dyn std::error::Error + Send + Sync + 'static>>,
+{
+    type Response = http::Response<ResponseBody>;
+    type Error = Infallible;
+    type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(
+        &mut self,
+        _: &mut std::task::Context<'_>,
+    ) -> std::task::Poll<Result<(), Self::Error>> {
+        // Errors from `poll_ready` can't become responses until there's a request, so readiness is
+        // checked in `call` instead
+        std::task::Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, req: http::Request<ReqBody>) -> Self::Future {
+        // Take the service that was driven to readiness, as the `tower_service::Service` contract
+        // requires, leaving a clone in its place
+        let clone = self.inner.clone();
+        let mut inner = std::mem::replace(&mut self.inner, clone);
+
+        Box::pin(async move {
+            let res = match poll_fn(|cx| inner.poll_ready(cx)).await {
+                Ok(()) => inner.call(req).await,
+                Err(error) => Err(error),
+            };
+            Ok(match res {
+                Ok(res) => res.map(boxed),
+                Err(error) => {
+                    let mut res = error.into_response();
+                    res.extensions_mut().insert(Resolution::InnerError);
+                    res
+                }
+            })
+        })
+    }
+}
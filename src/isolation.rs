@@ -0,0 +1,112 @@
+//! Marker types choosing how the request's transaction is begun.
+
+use sea_orm::{AccessMode, IsolationLevel};
+
+/// Chooses the isolation level and access mode of the request's transaction, from the type of the
+/// [`Tx`](crate::Tx) extractor.
+///
+/// This lets handlers ask for different settings purely through their signature, without
+/// configuring the [`Layer`](crate::Layer):
+///
+/// ```
+/// use axum_sea_orm_tx::{Error, Serializable, Tx};
+/// use sea_orm::DatabaseConnection;
+///
+/// type SerializableTx = Tx<DatabaseConnection, Error, Serializable>;
+///
+/// async fn handler(tx: SerializableTx) {
+///     /* ... */
+/// }
+/// ```
+///
+/// A marker's settings take precedence over the layer's (see
+/// [`Layer::isolation_level`](crate::Layer::isolation_level) and
+/// [`Layer::access_mode`](crate::Layer::access_mode)), and are used when the transaction is
+/// begun, i.e. by the first extractor for the request. Since the transaction is shared,
+/// extracting a `Tx` whose marker asks for different settings later in the same request fails
+/// with [`Error::ConflictingIsolation`]. Settings a marker leaves as `None` aren't checked, so
+/// [`DefaultIsolation`] can be used with any transaction.
+///
+/// Note that SQLite doesn't support per-transaction settings, so they're ignored there.
+///
+/// [`Error::ConflictingIsolation`]: crate::Error::ConflictingIsolation
+pub trait IsolationMarker: Send + Sync + 'static {
+    /// The isolation level to begin the transaction with, or `None` for the database's default.
+    const ISOLATION_LEVEL: Option<IsolationLevel> = None;
+
+    /// The access mode to begin the transaction with, or `None` for the database's default.
+    const ACCESS_MODE: Option<AccessMode> = None;
+}
+
+/// Begin the transaction with the [`Layer`](crate::Layer)'s settings (see
+/// [`Layer::isolation_level`](crate::Layer::isolation_level)), or else the database's defaults.
+///
+/// This is the default for [`Tx`](crate::Tx).
+#[derive(Debug, Clone, Copy, Default)]
+pub struct DefaultIsolation;
+
+impl IsolationMarker for DefaultIsolation {}
+
+/// Begin the transaction with the `READ UNCOMMITTED` isolation level.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ReadUncommitted;
+
+impl IsolationMarker for ReadUncommitted {
+    const ISOLATION_LEVEL: Option<IsolationLevel> = Some(IsolationLevel::ReadUncommitted);
+}
+
+/// Begin the transaction with the `READ COMMITTED` isolation level.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ReadCommitted;
+
+impl IsolationMarker for ReadCommitted {
+    const ISOLATION_LEVEL: Option<IsolationLevel> = Some(IsolationLevel::ReadCommitted);
+}
+
+/// Begin the transaction with the `REPEATABLE READ` isolation level.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct RepeatableRead;
+
+impl IsolationMarker for RepeatableRead {
+    const ISOLATION_LEVEL: Option<IsolationLevel> = Some(IsolationLevel::RepeatableRead);
+}
+
+/// Begin the transaction with the `SERIALIZABLE` isolation level.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Serializable;
+
+impl IsolationMarker for Serializable {
+    const ISOLATION_LEVEL: Option<IsolationLevel> = Some(IsolationLevel::Serializable);
+}
+
+/// Begin the transaction read-only, with the database's default isolation level.
+///
+/// This lets handlers that shouldn't write declare it in their signature, and have the database
+/// enforce it: statements that write fail (e.g. with "cannot execute INSERT in a read-only
+/// transaction" on Postgres), and the response is an error as usual.
+///
+/// ```
+/// use axum_sea_orm_tx::{Error, ReadOnly, Tx};
+/// use sea_orm::DatabaseConnection;
+///
+/// async fn handler(tx: Tx<DatabaseConnection, Error, ReadOnly>) {
+///     /* ... */
+/// }
+/// ```
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ReadOnly;
+
+impl IsolationMarker for ReadOnly {
+    const ACCESS_MODE: Option<AccessMode> = Some(AccessMode::ReadOnly);
+}
+
+/// Begin the transaction with the `REPEATABLE READ` isolation level, read-only.
+///
+/// This is the marker of [`SnapshotTx`](crate::SnapshotTx).
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Snapshot;
+
+impl IsolationMarker for Snapshot {
+    const ISOLATION_LEVEL: Option<IsolationLevel> = Some(IsolationLevel::RepeatableRead);
+    const ACCESS_MODE: Option<AccessMode> = Some(AccessMode::ReadOnly);
+}
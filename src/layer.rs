@@ -1,14 +1,47 @@
 //! A [`tower_layer::Layer`] that enables the [`Tx`](crate::Tx) extractor.
 
-use std::marker::PhantomData;
+use std::{
+    future::Future,
+    marker::PhantomData,
+    ops::{Range, RangeInclusive},
+    pin::Pin,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    task::{Context, Poll},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
 
-use axum_core::response::IntoResponse;
-use bytes::Bytes;
-use futures_core::future::BoxFuture;
-use http_body::{combinators::UnsyncBoxBody, Body};
-use sea_orm::{DatabaseConnection, TransactionTrait};
+use futures_core::{future::BoxFuture, ready};
+use futures_util::FutureExt;
+use http::{request::Parts, StatusCode};
+use http_body::Body;
+use pin_project_lite::pin_project;
+use sea_orm::{
+    AccessMode, ConnectOptions, ConnectionTrait, DatabaseConnection, DbErr, IsolationLevel,
+    TransactionTrait,
+};
 
-use crate::{tx::TxSlot, Error};
+#[cfg(feature = "audit")]
+use crate::audit::{AuditLog, AuditSink};
+use crate::{
+    affinity::{Affinity, AffinityKey},
+    body::{boxed, CommitOnEnd, CommitOnTrailer, ResponseBody},
+    connection::AutocommitConnection,
+    handle_error::HandleInnerErrorsLayer,
+    meta::TxMeta,
+    prewarm::Prewarmed,
+    readiness::ReadinessProbe,
+    registry::TxRegistry,
+    response::{
+        Deferred, LongTransaction, PreparedTransaction, Resolution, RollbackReason, StatementCount,
+        TxOutcome,
+    },
+    runtime::{self, Runtime, Sleep},
+    tx::{BeginOptions, StatementRewriter, TxSlot},
+    BackoffPolicy, Error, IntoResponseFor,
+};
 
 /// A [`tower_layer::Layer`] that enables the [`Tx`] extractor.
 ///
@@ -17,12 +50,35 @@ use crate::{tx::TxSlot, Error};
 /// [`sea_orm::DatabaseConnection`] and a transaction is started on it. The same transaction will be returned for
 /// subsequent uses of [`Tx`] on the same request. The inner service is then called as normal. Once
 /// the inner service responds, the transaction is committed or rolled back depending on the status
-/// code of the response.
+/// code of the response, unless the handler decided explicitly with [`Commit`] or [`Rollback`].
+///
+/// Adding the layer with [`Router::layer`] wraps the router's fallback as well as its routes, so
+/// [`Tx`] can be extracted in a [`Router::fallback`] handler too (but not with
+/// [`Router::route_layer`], which only wraps matched routes). Fallback responses are usually
+/// `404 Not Found`, so a fallback that should keep its writes (e.g. to log unmatched requests)
+/// must return a [`Commit`] response.
+///
+/// The inner service may be fallible, so the layer can be composed with services like
+/// [`tower::timeout::Timeout`]. If the inner service returns an error, the transaction is rolled
+/// back and the error is passed through unchanged, to be handled by an outer layer (e.g.
+/// [`axum::error_handling::HandleErrorLayer`]), unless it's converted into a response with
+/// [`Layer::handle_inner_errors`]. Similarly, if the request is cancelled before the
+/// inner service responds (e.g. because the client disconnected), the transaction is rolled back
+/// straight away.
 ///
 /// [`Tx`]: crate::Tx
+/// [`Router::layer`]: axum::Router::layer
+/// [`Router::fallback`]: axum::Router::fallback
+/// [`Router::route_layer`]: axum::Router::route_layer
+/// [`tower::timeout::Timeout`]: https://docs.rs/tower/latest/tower/timeout/struct.Timeout.html
+/// [`axum::error_handling::HandleErrorLayer`]: https://docs.rs/axum/latest/axum/error_handling/struct.HandleErrorLayer.html
+/// [`Commit`]: crate::Commit
+/// [`Rollback`]: crate::Rollback
 /// [request extensions]: https://docs.rs/http/latest/http/struct.Extensions.html
 pub struct Layer<C: TransactionTrait + Clone = DatabaseConnection, E = Error> {
     pool: C,
+    replica: Option<C>,
+    config: Arc<Config>,
     _error: PhantomData<E>,
 }
 
@@ -30,11 +86,180 @@ impl<C: TransactionTrait + Clone, E> Clone for Layer<C, E> {
     fn clone(&self) -> Self {
         Self {
             pool: self.pool.clone(),
+            replica: self.replica.clone(),
+            config: self.config.clone(),
             _error: self._error,
         }
     }
 }
 
+/// The response statuses that commit the transaction, see [`Layer::commit_status_range`].
+///
+/// This is built from a range of status codes (e.g. `200..400` or `200..=299`), or a list of
+/// them (e.g. `vec![StatusCode::OK, StatusCode::CREATED]`).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CommitStatuses(Statuses);
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Statuses {
+    Range(RangeInclusive<u16>),
+    List(Vec<StatusCode>),
+}
+
+impl CommitStatuses {
+    /// Whether `status` commits the transaction.
+    pub fn contains(&self, status: StatusCode) -> bool {
+        match &self.0 {
+            Statuses::Range(range) => range.contains(&status.as_u16()),
+            Statuses::List(list) => list.contains(&status),
+        }
+    }
+}
+
+impl From<RangeInclusive<u16>> for CommitStatuses {
+    fn from(range: RangeInclusive<u16>) -> Self {
+        Self(Statuses::Range(range))
+    }
+}
+
+impl From<Range<u16>> for CommitStatuses {
+    fn from(range: Range<u16>) -> Self {
+        match range.end.checked_sub(1) {
+            Some(end) => Self(Statuses::Range(range.start..=end)),
+            None => Self(Statuses::List(Vec::new())),
+        }
+    }
+}
+
+impl From<Vec<StatusCode>> for CommitStatuses {
+    fn from(list: Vec<StatusCode>) -> Self {
+        Self(Statuses::List(list))
+    }
+}
+
+impl From<&[StatusCode]> for CommitStatuses {
+    fn from(list: &[StatusCode]) -> Self {
+        Self(Statuses::List(list.to_vec()))
+    }
+}
+
+/// A function computing a per-request value from the request head.
+type PartsFn<T> = Arc<dyn Fn(&Parts) -> T + Send + Sync>;
+
+/// A function deciding something from the response head.
+type ResponseFn = Arc<dyn Fn(&http::response::Parts) -> bool + Send + Sync>;
+
+/// Options shared by [`Layer`] and [`Service`].
+#[derive(Clone, Default)]
+pub(crate) struct Config {
+    commit_failure_status: Option<StatusCode>,
+    application_name: Option<PartsFn<String>>,
+    search_path: Option<PartsFn<Option<String>>>,
+    is_read: Option<PartsFn<bool>>,
+    skip_paths: Vec<String>,
+    pub(crate) key: Option<String>,
+    autocommit_fallback: Option<AutocommitConnection>,
+    isolation_level: Option<IsolationLevel>,
+    access_mode: Option<AccessMode>,
+    max_open_duration: Option<Duration>,
+    explain_threshold: Option<Duration>,
+    commit_deadline: Option<Duration>,
+    commit_statuses: Option<CommitStatuses>,
+    commit_when: Option<ResponseFn>,
+    commit_trailer: Option<(http::HeaderName, http::HeaderValue)>,
+    sleep: Option<Sleep>,
+    explicit_commit_only: bool,
+    rollback_readonly: bool,
+    annotate_outcome: bool,
+    dry_run: bool,
+    strict_nesting: bool,
+    two_phase: bool,
+    commit_backoff: Option<BackoffPolicy>,
+    meta: Option<Arc<TxMeta>>,
+    registry: Option<TxRegistry>,
+    pub(crate) leak_check: bool,
+    access_log: bool,
+    max_statements: Option<usize>,
+    prewarmed: Option<Prewarmed>,
+    affinity: Option<(Affinity, PartsFn<Option<AffinityKey>>)>,
+    readiness_probe: Option<Arc<dyn ReadinessProbe>>,
+    rewriter: Option<StatementRewriter>,
+    #[cfg(feature = "audit")]
+    audit: Option<AuditSink>,
+    #[cfg(feature = "audit")]
+    redact_audit_values: bool,
+}
+
+impl Config {
+    /// Whether a response with head `res` commits the transaction, if the handler didn't say.
+    ///
+    /// By default, successful (`2XX`) responses commit.
+    fn commits(&self, res: &http::response::Parts) -> bool {
+        if let Some(f) = &self.commit_when {
+            return f(res);
+        }
+        match &self.commit_statuses {
+            Some(statuses) => statuses.contains(res.status),
+            None => res.status.is_success(),
+        }
+    }
+
+    /// The configured runtime's `sleep`, or the default runtime's (see [`Layer::runtime`]).
+    fn sleep(&self) -> Option<Sleep> {
+        self.sleep.or_else(runtime::default_sleep)
+    }
+
+    /// Whether the request should be routed to the replica (see [`Layer::read_write_split`]).
+    ///
+    /// By default, requests with a safe method (`GET`, `HEAD`, `OPTIONS` or `TRACE`) are reads.
+    fn is_read(&self, parts: &Parts) -> bool {
+        match &self.is_read {
+            Some(f) => f(parts),
+            None => parts.method.is_safe(),
+        }
+    }
+
+    /// Whether requests for `path` shouldn't have a transaction (see [`Layer::skip_paths`]).
+    fn is_skipped(&self, path: &str) -> bool {
+        self.skip_paths
+            .iter()
+            .any(|skip| match skip.strip_suffix('*') {
+                Some(prefix) => path.starts_with(prefix),
+                None => path == skip,
+            })
+    }
+
+    /// Compute the options for beginning the transaction for a request.
+    pub(crate) fn begin_options(&self, parts: &Parts) -> BeginOptions {
+        BeginOptions {
+            application_name: self.application_name.as_ref().map(|f| f(parts)),
+            search_path: self.search_path.as_ref().and_then(|f| f(parts)),
+            // Statements run in autocommit mode can't be rolled back
+            autocommit_fallback: self.autocommit_fallback.clone().filter(|_| !self.dry_run),
+            prewarmed: self.prewarmed.clone(),
+            affinity: (self.affinity.as_ref())
+                .and_then(|(affinity, f)| Some((affinity.clone(), f(parts)?))),
+            rewriter: self.rewriter.clone(),
+            isolation_level: self.isolation_level,
+            access_mode: self.access_mode,
+            commit_backoff: self.commit_backoff,
+            sleep: self.sleep(),
+            meta: self.meta.clone(),
+            registry: self.registry.clone().zip(
+                (parts.headers.get("x-request-id"))
+                    .and_then(|id| id.to_str().ok())
+                    .map(str::to_string),
+            ),
+            max_statements: self.max_statements,
+            explain_threshold: self.explain_threshold,
+            dry_run: self.dry_run,
+            strict_nesting: self.strict_nesting,
+            #[cfg(feature = "audit")]
+            audit: None,
+        }
+    }
+}
+
 impl<C: TransactionTrait + Clone> Layer<C> {
     /// Construct a new layer with the given `pool`.
     ///
@@ -52,24 +277,905 @@ impl<C: TransactionTrait + Clone> Layer<C> {
         Self::new_with_error(pool)
     }
 
+    /// Construct a new layer that keeps `n` transactions begun ahead of time.
+    ///
+    /// This is shorthand for `Layer::new(pool).prewarm(n)`, see [`prewarm`](Self::prewarm).
+    pub fn prewarmed(pool: C, n: usize) -> Self {
+        Self::new(pool).prewarm(n)
+    }
+
+    /// Construct a new layer that only commits when the handler explicitly asks it to.
+    ///
+    /// This is shorthand for `Layer::new(pool).auto_commit(false)`, see
+    /// [`auto_commit`](Self::auto_commit).
+    pub fn explicit_commit_only(pool: C) -> Self {
+        Self::new(pool).auto_commit(false)
+    }
+
+    /// Construct a new layer that prepares transactions for two-phase commit instead of
+    /// committing them.
+    ///
+    /// This is shorthand for `Layer::new(pool).two_phase_commit(true)`, see
+    /// [`two_phase_commit`](Self::two_phase_commit).
+    pub fn two_phase(pool: C) -> Self {
+        Self::new(pool).two_phase_commit(true)
+    }
+
+    /// Construct a new layer that begins transactions for reads on a replica.
+    ///
+    /// Requests with a safe method (`GET`, `HEAD`, `OPTIONS` or `TRACE`) are reads, unless
+    /// configured otherwise with [`route_reads`](Layer::route_reads). Their transactions are
+    /// begun read-only on the `replica` pool, and everything else is begun on the `primary`:
+    ///
+    /// ```
+    /// # async fn foo() {
+    /// # let (primary, replica): (sea_orm::DatabaseConnection, sea_orm::DatabaseConnection) = todo!();
+    /// let app = axum::Router::new()
+    ///     // .route(...)s
+    ///     .layer(axum_sea_orm_tx::Layer::read_write_split(primary, replica));
+    /// # axum::Server::bind(todo!()).serve(app.into_make_service());
+    /// # }
+    /// ```
+    ///
+    /// Replicas usually lag behind the primary, so a read may not see what was written by a
+    /// previous request, even from the same client (e.g. a `GET` straight after the `POST` that
+    /// created the resource). Routes that must read their own writes should be routed to the
+    /// primary with [`route_reads`](Layer::route_reads).
+    ///
+    /// Options like [`prewarm`](Layer::prewarm) and
+    /// [`autocommit_fallback`](Layer::autocommit_fallback) only apply to the primary.
+    pub fn read_write_split(primary: C, replica: C) -> Self {
+        Layer {
+            replica: Some(replica),
+            ..Self::new(primary)
+        }
+    }
+
+    /// Construct a new layer whose transactions are looked up by `key`.
+    ///
+    /// Normally there can only be one layer per type of pool, since [`Tx`](crate::Tx) finds its
+    /// transaction by the type of the pool. Layers with different keys are independent, so there
+    /// can be any number of them (e.g. one per database in the application's configuration), and
+    /// their transactions are resolved separately. The handler gets each transaction with
+    /// [`Tx::keyed`](crate::Tx::keyed):
+    ///
+    /// ```
+    /// # async fn foo() {
+    /// # let (pool, analytics_pool): (sea_orm::DatabaseConnection, sea_orm::DatabaseConnection) = todo!();
+    /// let app = axum::Router::new()
+    ///     // .route(...)s
+    ///     .layer(axum_sea_orm_tx::Layer::new(pool))
+    ///     .layer(axum_sea_orm_tx::Layer::with_key("analytics", analytics_pool));
+    /// # axum::Server::bind(todo!()).serve(app.into_make_service());
+    /// # }
+    /// ```
+    ///
+    /// A keyed layer's transaction can't be extracted as [`Tx`](crate::Tx) directly, which only
+    /// finds the unkeyed layer's.
+    pub fn with_key(key: impl Into<String>, pool: C) -> Self {
+        let mut layer = Self::new(pool);
+        layer.config_mut().key = Some(key.into());
+        layer
+    }
+
+    /// Construct a new layer with the `options` the `pool` was connected with.
+    ///
+    /// SeaORM doesn't keep most of the options in the pool, so this is how handlers can find out
+    /// about the database (e.g. its name, or the size of the pool), through the [`TxMeta`]
+    /// extractor or [`Tx::meta`](crate::Tx::meta):
+    ///
+    /// ```
+    /// # async fn foo() -> Result<(), sea_orm::DbErr> {
+    /// let mut options = sea_orm::ConnectOptions::new("postgres://app:secret@db/app".to_string());
+    /// options.max_connections(10);
+    /// let pool = sea_orm::Database::connect(options.clone()).await?;
+    /// let layer = axum_sea_orm_tx::Layer::new_with_options(pool, &options);
+    /// # Ok(())
+    /// # }
+    /// ```
+    ///
+    /// The options themselves aren't kept, only the details in [`TxMeta`] (without credentials).
+    pub fn new_with_options(pool: C, options: &ConnectOptions) -> Self {
+        let mut layer = Self::new(pool);
+        layer.config_mut().meta = Some(Arc::new(TxMeta::new(options)));
+        layer
+    }
+
     /// Construct a new layer with a specific error type.
     ///
     /// See [`Layer::new`] for more information.
     pub fn new_with_error<E>(pool: C) -> Layer<C, E> {
         Layer {
             pool,
+            replica: None,
+            config: Default::default(),
             _error: PhantomData,
         }
     }
 }
 
+impl<C: TransactionTrait + Clone, E> Layer<C, E> {
+    /// The config to update from a builder method.
+    ///
+    /// The config is shared with every [`Service`] the layer creates, so that creating a service
+    /// (which axum may do per request) doesn't copy it.
+    fn config_mut(&mut self) -> &mut Config {
+        Arc::make_mut(&mut self.config)
+    }
+
+    /// Override the status code of the response returned when committing the transaction fails.
+    ///
+    /// By default the response is whatever `E::from(error).into_response_for(..)` produces for the
+    /// [`Error::Database`] or [`Error::ConstraintViolation`] (HTTP 500 for [`Error`]). With this
+    /// set, the response is still produced by `E`, but its status is replaced with `status`. For
+    /// example, `409 Conflict` may be more appropriate if commits usually fail due to constraint or
+    /// serialization conflicts.
+    pub fn commit_failure_status(mut self, status: StatusCode) -> Self {
+        self.config_mut().commit_failure_status = Some(status);
+        self
+    }
+
+    /// Tag each request's transaction with an `application_name` computed from the request.
+    ///
+    /// Right after the transaction begins, `f` is called with the request head and its result is
+    /// set as the `application_name` for the duration of the transaction (equivalent to
+    /// `SET LOCAL application_name = ...`). This makes it possible to see which endpoint holds
+    /// which transaction in `pg_stat_activity`:
+    ///
+    /// ```
+    /// # async fn foo() {
+    /// # let pool: sea_orm::DatabaseConnection = todo!();
+    /// let layer = axum_sea_orm_tx::Layer::new(pool)
+    ///     .application_name_from(|parts| format!("{} {}", parts.method, parts.uri.path()));
+    /// # }
+    /// ```
+    ///
+    /// `application_name` is specific to Postgres, so this has no effect on other backends.
+    pub fn application_name_from<F>(mut self, f: F) -> Self
+    where
+        F: Fn(&Parts) -> String + Send + Sync + 'static,
+    {
+        self.config_mut().application_name = Some(Arc::new(f));
+        self
+    }
+
+    /// Set each request's `search_path` to a value computed from the request.
+    ///
+    /// Right after the transaction begins, `f` is called with the request head and, if it returns
+    /// `Some`, the result is set as the `search_path` for the duration of the transaction
+    /// (equivalent to `SET LOCAL search_path TO ...`). This supports schema-per-tenant setups,
+    /// where unqualified table names should resolve to the request's tenant:
+    ///
+    /// ```
+    /// # async fn foo() {
+    /// # let pool: sea_orm::DatabaseConnection = todo!();
+    /// let layer = axum_sea_orm_tx::Layer::new(pool).search_path_from(|parts| {
+    ///     let tenant = parts.headers.get("x-tenant")?.to_str().ok()?;
+    ///     Some(format!("tenant_{tenant}"))
+    /// });
+    /// # }
+    /// ```
+    ///
+    /// If `f` returns `None`, the default `search_path` is left alone. The value is used verbatim,
+    /// so any schema names that need it must be quoted, and untrusted input should be validated.
+    ///
+    /// `search_path` is specific to Postgres, so this has no effect on other backends.
+    pub fn search_path_from<F>(mut self, f: F) -> Self
+    where
+        F: Fn(&Parts) -> Option<String> + Send + Sync + 'static,
+    {
+        self.config_mut().search_path = Some(Arc::new(f));
+        self
+    }
+
+    /// Rewrite each statement run through [`Tx`](crate::Tx) before it reaches the database.
+    ///
+    /// `f` is applied to the statements passed to the [`sea_orm::ConnectionTrait`] and
+    /// [`sea_orm::StreamTrait`] methods of `Tx` (including everything SeaORM's query builders run
+    /// through it). This can be used to e.g. qualify table names for schema-per-tenant setups, or
+    /// to annotate queries with a comment:
+    ///
+    /// ```
+    /// # async fn foo() {
+    /// # let pool: sea_orm::DatabaseConnection = todo!();
+    /// let layer = axum_sea_orm_tx::Layer::new(pool)
+    ///     .with_statement_rewriter(|stmt| stmt.sql.insert_str(0, "/* my-app */ "));
+    /// # }
+    /// ```
+    ///
+    /// Raw SQL passed to `execute_unprepared` isn't a [`sea_orm::Statement`], so it isn't
+    /// rewritten.
+    pub fn with_statement_rewriter<F>(mut self, f: F) -> Self
+    where
+        F: Fn(&mut sea_orm::Statement) + Send + Sync + 'static,
+    {
+        self.config_mut().rewriter = Some(StatementRewriter(Arc::new(f)));
+        self
+    }
+
+    /// Record the statements each request runs through [`Tx`](crate::Tx), and pass them to `sink`
+    /// along with the outcome of the transaction.
+    ///
+    /// `sink` is called once the transaction has been resolved, for every request that began a
+    /// transaction. Statements are recorded after any
+    /// [statement rewriter](Self::with_statement_rewriter) has been applied. Bound values are
+    /// included by default, see [`redact_audit_values`](Self::redact_audit_values) to leave them
+    /// out.
+    ///
+    /// ```
+    /// # async fn foo() {
+    /// # let pool: sea_orm::DatabaseConnection = todo!();
+    /// let layer = axum_sea_orm_tx::Layer::new(pool).audit(|record| {
+    ///     for statement in &record.statements {
+    ///         println!("{} {:?}", statement.sql, record.outcome);
+    ///     }
+    /// });
+    /// # }
+    /// ```
+    #[cfg(feature = "audit")]
+    pub fn audit<F>(mut self, sink: F) -> Self
+    where
+        F: Fn(crate::AuditRecord) + Send + Sync + 'static,
+    {
+        self.config_mut().audit = Some(Arc::new(sink));
+        self
+    }
+
+    /// Replace bound values in the [audit log](Self::audit) with `"<redacted>"`.
+    #[cfg(feature = "audit")]
+    pub fn redact_audit_values(mut self, redact: bool) -> Self {
+        self.config_mut().redact_audit_values = redact;
+        self
+    }
+
+    /// Flag transactions that stay open longer than `threshold`.
+    ///
+    /// When the transaction is resolved, if more than `threshold` has passed since it was begun, a
+    /// warning is logged (with the `tracing` feature) and a [`LongTransaction`] is inserted into
+    /// the response extensions. This is purely diagnostic – the transaction is still committed or
+    /// rolled back as usual.
+    ///
+    /// [`LongTransaction`]: crate::LongTransaction
+    pub fn warn_if_open_longer_than(mut self, threshold: Duration) -> Self {
+        self.config_mut().max_open_duration = Some(threshold);
+        self
+    }
+
+    /// Log the plan of statements that take longer than `threshold` to run through
+    /// [`Tx`](crate::Tx).
+    ///
+    /// Each statement run with the [`ConnectionTrait`] methods is timed, and if it's slower than
+    /// `threshold`, its plan is fetched with `EXPLAIN` on the same transaction (`EXPLAIN QUERY
+    /// PLAN` on SQLite, `EXPLAIN FORMAT=TREE` on MySQL) and logged as a `warn` event with
+    /// `kind = "tx.slow_query"`, the SQL and the plan. The plan is only estimated (there's no
+    /// `ANALYZE`), so explaining a write doesn't run it again. Only `SELECT`, `INSERT`, `UPDATE`,
+    /// `DELETE` and `WITH` statements are explained, and on Postgres `EXPLAIN` is run in a
+    /// savepoint, so a statement that can't be explained doesn't abort the transaction.
+    ///
+    /// This is meant for debugging: fetching the plan costs extra round-trips (on top of the slow
+    /// statement) in the middle of the request. Streams aren't timed, since how long they take
+    /// depends on how they're consumed. This requires the `tracing` feature, and does nothing
+    /// without it.
+    pub fn explain_slow_queries(mut self, threshold: Duration) -> Self {
+        self.config_mut().explain_threshold = Some(threshold);
+        self
+    }
+
+    /// Don't provide a transaction for requests to `paths`.
+    ///
+    /// This is for endpoints that must never touch the database, like metrics or health checks,
+    /// when the layer is applied to the whole router. Requests to these paths are passed straight
+    /// to the inner service, so no transaction can be begun, and extracting [`Tx`](crate::Tx)
+    /// fails with [`Error::MissingExtension`]:
+    ///
+    /// ```
+    /// # async fn foo() {
+    /// # let pool: sea_orm::DatabaseConnection = todo!();
+    /// let layer = axum_sea_orm_tx::Layer::new(pool).skip_paths(["/metrics", "/health/*"]);
+    /// # }
+    /// ```
+    ///
+    /// Paths are matched against the request path exactly, or as a prefix if they end with `*`
+    /// (so `/health/*` matches `/health/live` and `/health/ready`, but not `/health`).
+    pub fn skip_paths<I>(mut self, paths: I) -> Self
+    where
+        I: IntoIterator,
+        I::Item: Into<String>,
+    {
+        (self.config_mut().skip_paths).extend(paths.into_iter().map(Into::into));
+        self
+    }
+
+    /// Choose which requests are reads, to be routed to the replica by a
+    /// [`read_write_split`](Layer::read_write_split) layer.
+    ///
+    /// `f` is called with the request head, and returns whether it's a read. For example, to send
+    /// an endpoint that reads its own writes to the primary:
+    ///
+    /// ```
+    /// # async fn foo() {
+    /// # let (primary, replica): (sea_orm::DatabaseConnection, sea_orm::DatabaseConnection) = todo!();
+    /// let layer = axum_sea_orm_tx::Layer::read_write_split(primary, replica).route_reads(|parts| {
+    ///     parts.method.is_safe() && !parts.uri.path().starts_with("/orders/")
+    /// });
+    /// # }
+    /// ```
+    ///
+    /// This has no effect without a replica.
+    pub fn route_reads<F>(mut self, f: F) -> Self
+    where
+        F: Fn(&Parts) -> bool + Send + Sync + 'static,
+    {
+        self.config_mut().is_read = Some(Arc::new(f));
+        self
+    }
+
+    /// Begin transactions with the isolation level `level`, instead of the database's default.
+    ///
+    /// This applies to every transaction begun by the layer, except those extracted with an
+    /// [`IsolationMarker`](crate::IsolationMarker) that asks for a level itself (e.g.
+    /// [`Serializable`](crate::Serializable)), which takes precedence:
+    ///
+    /// ```
+    /// # fn foo(pool: sea_orm::DatabaseConnection) {
+    /// use sea_orm::IsolationLevel;
+    ///
+    /// let layer = axum_sea_orm_tx::Layer::new(pool).isolation_level(IsolationLevel::RepeatableRead);
+    /// # }
+    /// ```
+    ///
+    /// Transactions with a non-default isolation level are begun on demand, so they don't use
+    /// [`prewarm`](Self::prewarm)ed transactions or connections held for
+    /// [`connection_affinity`](Self::connection_affinity). Note that SQLite doesn't support
+    /// per-transaction settings, so this is ignored there.
+    pub fn isolation_level(mut self, level: IsolationLevel) -> Self {
+        self.config_mut().isolation_level = Some(level);
+        self
+    }
+
+    /// Begin transactions with the access mode `mode`, instead of the database's default.
+    ///
+    /// As with [`isolation_level`](Self::isolation_level), an
+    /// [`IsolationMarker`](crate::IsolationMarker) that asks for an access mode itself (e.g.
+    /// [`ReadOnly`](crate::ReadOnly)) takes precedence. Requests routed to the replica of a
+    /// [`read_write_split`](Self::read_write_split) layer are always read-only.
+    pub fn access_mode(mut self, mode: AccessMode) -> Self {
+        self.config_mut().access_mode = Some(mode);
+        self
+    }
+
+    /// Give up on committing the transaction if it takes longer than `deadline`.
+    ///
+    /// A `COMMIT` can block for a long time even after the handler has finished quickly, e.g. with
+    /// synchronous replication waiting for a replica. With a deadline, a commit that hasn't
+    /// finished in time is abandoned: the transaction is rolled back (when its connection is next
+    /// used, as for any dropped transaction) and the response is replaced with
+    /// [`Error::CommitTimeout`] (see [`commit_failure_status`](Self::commit_failure_status)).
+    /// Note the database may have committed the transaction anyway, so the client can't assume
+    /// that it wasn't.
+    ///
+    /// The deadline only covers the middleware's `COMMIT`, not the handler, so it's separate from
+    /// a timeout on the whole request (e.g. [`tower::timeout::Timeout`]). Commits made by the
+    /// handler with [`Tx::commit`](crate::Tx::commit), and of streamed responses, aren't subject
+    /// to it.
+    ///
+    /// The deadline is timed with the layer's [`runtime`](Self::runtime).
+    ///
+    /// # Panics
+    ///
+    /// Applying the layer panics if a deadline is set without a runtime, i.e. with neither the
+    /// `runtime-tokio` nor the `runtime-async-std` feature enabled.
+    ///
+    /// [`tower::timeout::Timeout`]: https://docs.rs/tower/latest/tower/timeout/struct.Timeout.html
+    pub fn commit_deadline(mut self, deadline: Duration) -> Self {
+        self.config_mut().commit_deadline = Some(deadline);
+        self
+    }
+
+    /// Use the async runtime `R` for timers, such as the [`commit_deadline`](Self::commit_deadline).
+    ///
+    /// By default, this is [`Tokio`](crate::Tokio) if the `runtime-tokio` feature is enabled (as it
+    /// is by default), or else `AsyncStd` if the `runtime-async-std` feature
+    /// is:
+    ///
+    /// ```
+    /// # #[cfg(feature = "runtime-async-std")]
+    /// # fn foo(pool: sea_orm::DatabaseConnection) {
+    /// use axum_sea_orm_tx::{AsyncStd, Layer};
+    ///
+    /// let layer = Layer::new(pool)
+    ///     .runtime::<AsyncStd>()
+    ///     .commit_deadline(std::time::Duration::from_secs(5));
+    /// # }
+    /// ```
+    pub fn runtime<R: Runtime>(mut self) -> Self {
+        self.config_mut().sleep = Some(R::sleep);
+        self
+    }
+
+    /// Choose whether successful responses commit the transaction automatically (the default).
+    ///
+    /// With auto-commit disabled, the transaction is always rolled back unless the handler commits
+    /// it explicitly, with [`Tx::commit`](crate::Tx::commit) or by returning a
+    /// [`Commit`](crate::Commit) response. This makes forgetting to commit loud (nothing is
+    /// persisted) rather than relying on the response status.
+    pub fn auto_commit(mut self, enabled: bool) -> Self {
+        self.config_mut().explicit_commit_only = !enabled;
+        self
+    }
+
+    /// Choose the response statuses that commit the transaction, instead of only successful
+    /// (`2XX`) ones.
+    ///
+    /// `statuses` can be a range, e.g. to also commit redirects, or a list of status codes:
+    ///
+    /// ```
+    /// # fn foo(pool: sea_orm::DatabaseConnection) {
+    /// use axum_sea_orm_tx::Layer;
+    /// use http::StatusCode;
+    ///
+    /// let commit_redirects = Layer::new(pool.clone()).commit_status_range(200..400);
+    /// let commit_some = Layer::new(pool).commit_status_range(vec![
+    ///     StatusCode::OK,
+    ///     StatusCode::CREATED,
+    ///     StatusCode::NO_CONTENT,
+    /// ]);
+    /// # }
+    /// ```
+    ///
+    /// Responses with any other status roll back, with [`RollbackReason::Status`]. The handler can
+    /// still override this with [`Commit`](crate::Commit) and [`Rollback`](crate::Rollback), and
+    /// it has no effect when [`auto_commit`](Self::auto_commit) is disabled.
+    ///
+    /// This replaces any predicate set with [`commit_when`](Self::commit_when).
+    pub fn commit_status_range(mut self, statuses: impl Into<CommitStatuses>) -> Self {
+        let config = self.config_mut();
+        config.commit_statuses = Some(statuses.into());
+        config.commit_when = None;
+        self
+    }
+
+    /// Decide which responses commit the transaction with `f`, instead of only committing
+    /// successful (`2XX`) ones.
+    ///
+    /// `f` is given the head of the response (its status, headers and extensions), for rules that
+    /// [`commit_status_range`](Self::commit_status_range) can't express, e.g. to keep the audit
+    /// rows written by a request that ended in a conflict, or to roll back responses the handler
+    /// marked as a dry run:
+    ///
+    /// ```
+    /// # fn foo(pool: sea_orm::DatabaseConnection) {
+    /// use http::StatusCode;
+    ///
+    /// let layer = axum_sea_orm_tx::Layer::new(pool).commit_when(|res| {
+    ///     (res.status.is_success() || res.status == StatusCode::CONFLICT)
+    ///         && res.headers.get("x-dry-run").map_or(true, |value| value != "true")
+    /// });
+    /// # }
+    /// ```
+    ///
+    /// Responses for which `f` returns `false` roll back, with [`RollbackReason::Status`]. As with
+    /// `commit_status_range`, the handler can still override this with [`Commit`](crate::Commit)
+    /// and [`Rollback`](crate::Rollback), and it has no effect when
+    /// [`auto_commit`](Self::auto_commit) is disabled. This replaces any statuses set with
+    /// `commit_status_range`.
+    pub fn commit_when<F>(mut self, f: F) -> Self
+    where
+        F: Fn(&http::response::Parts) -> bool + Send + Sync + 'static,
+    {
+        let config = self.config_mut();
+        config.commit_when = Some(Arc::new(f));
+        config.commit_statuses = None;
+        self
+    }
+
+    /// Decide whether to commit from a trailer of the response, rather than its status.
+    ///
+    /// Some protocols send the outcome of a request in the trailers after the body, e.g. gRPC's
+    /// `grpc-status`, so the status line is `200 OK` even for failures. With this set, the
+    /// transaction of a response that would be decided by its status is instead committed if the
+    /// response has a trailer `name` with `value`, and rolled back otherwise:
+    ///
+    /// ```
+    /// # fn foo(pool: sea_orm::DatabaseConnection) {
+    /// let layer = axum_sea_orm_tx::Layer::new(pool).commit_on_trailer("grpc-status", "0");
+    /// # }
+    /// ```
+    ///
+    /// The trailers are only available once the whole body has been produced, so the transaction
+    /// stays open (holding its connection and locks) while the body is sent, and is resolved just
+    /// before the end of the body is signalled (when the trailers are read, or the body's data
+    /// ends, whichever is first). If committing fails, the body fails with the error. Since the
+    /// response has been sent by then, no [`TxOutcome`](crate::TxOutcome) is inserted, and the
+    /// options that act on a committing transaction, like
+    /// [`rollback_readonly`](Self::rollback_readonly) and [`two_phase_commit`](Self::two_phase_commit), don't
+    /// apply.
+    ///
+    /// [`Commit`](crate::Commit) and [`Rollback`](crate::Rollback) responses, and
+    /// [`Tx::commit_anyway`](crate::Tx::commit_anyway), still decide without waiting for the
+    /// trailers, and it has no effect when [`auto_commit`](Self::auto_commit) is disabled.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `name` or `value` aren't a valid header name or value.
+    pub fn commit_on_trailer<N, V>(mut self, name: N, value: V) -> Self
+    where
+        N: TryInto<http::HeaderName>,
+        N::Error: std::fmt::Debug,
+        V: TryInto<http::HeaderValue>,
+        V::Error: std::fmt::Debug,
+    {
+        let name = name.try_into().expect("invalid trailer name");
+        let value = value.try_into().expect("invalid trailer value");
+        self.config_mut().commit_trailer = Some((name, value));
+        self
+    }
+
+    /// Limit how many statements a request can run through [`Tx`](crate::Tx).
+    ///
+    /// This is a guard against N+1 query patterns. Once a request has run `n` statements, further
+    /// statements fail without being run, with a [`DbErr::Custom`] holding the message of
+    /// [`Error::TooManyStatements`] (and a warning is logged). The handler's error response then
+    /// rolls back the transaction as usual. Statements are counted whether or not this is set, see
+    /// [`StatementCount`](crate::StatementCount).
+    pub fn max_statements(mut self, n: usize) -> Self {
+        self.config_mut().max_statements = Some(n);
+        self
+    }
+
+    /// Annotate each transaction with the request ID and its outcome, just before it's resolved.
+    ///
+    /// This helps to correlate the database's logs (e.g. of slow queries or lock waits) with the
+    /// requests that caused them. With this enabled, the middleware runs a no-op statement with a
+    /// comment before committing or rolling back the transaction:
+    ///
+    /// ```sql
+    /// SELECT /* request_id=abc123 outcome=commit */ 1
+    /// ```
+    ///
+    /// The request ID is taken from the `x-request-id` header (e.g. as set by
+    /// [`tower_http::request_id`]), keeping only ASCII letters, digits, `-`, `_`, `.` and `:`, and
+    /// is left out if the header is missing. The outcome is `commit` or `rollback`.
+    ///
+    /// This only applies to Postgres, and is a no-op for other backends. Transactions committed
+    /// by the handler (e.g. with [`Tx::commit`](crate::Tx::commit)) or held by a streaming
+    /// response body aren't annotated. Failing to run the annotation is logged, and doesn't affect
+    /// the outcome.
+    ///
+    /// [`tower_http::request_id`]: https://docs.rs/tower-http/latest/tower_http/request_id/index.html
+    pub fn annotate_outcome(mut self, enabled: bool) -> Self {
+        self.config_mut().annotate_outcome = enabled;
+        self
+    }
+
+    /// Roll back every transaction, whatever the response, without persisting anything.
+    ///
+    /// This is for running handlers against real data without changing it, e.g. to validate a
+    /// canary deployment. Handlers run as usual, and see their own writes, but the middleware
+    /// always rolls the transaction back, with
+    /// [`RollbackReason::DryRun`](crate::RollbackReason::DryRun). Explicit commits are neutralized
+    /// too: [`Tx::commit`](crate::Tx::commit), [`Tx::commit_and_reopen`](crate::Tx::commit_and_reopen)
+    /// and [`Tx::commit_and_pin`](crate::Tx::commit_and_pin) leave the transaction open (like
+    /// releasing a savepoint would), so their writes are rolled back with the rest. As nothing is
+    /// committed, [`Tx::on_commit`](crate::Tx::on_commit) futures are never run.
+    ///
+    /// This takes precedence over all the other options deciding whether to commit, including
+    /// [`Commit`](crate::Commit) responses and [`Tx::commit_anyway`](crate::Tx::commit_anyway),
+    /// and disables the [`autocommit_fallback`](Self::autocommit_fallback) (statements run
+    /// outside a transaction can't be rolled back). Only statements run in the request's
+    /// transaction are covered: anything run directly on the pool is still persisted.
+    pub fn dry_run(mut self, enabled: bool) -> Self {
+        self.config_mut().dry_run = enabled;
+        self
+    }
+
+    /// Disallow nested transactions on [`Tx`](crate::Tx).
+    ///
+    /// `Tx` implements [`TransactionTrait`], so `tx.begin()` begins a nested transaction (a
+    /// savepoint), which is easy to call by accident and then use alongside the request's
+    /// transaction. With strict nesting, [`TransactionTrait`]'s methods fail instead, with a
+    /// [`DbErr::Custom`] holding the message of [`Error::NestedTransaction`] (and a warning is
+    /// logged), pointing at [`Tx::with_savepoint`](crate::Tx::with_savepoint) for when partial
+    /// rollback is really wanted.
+    ///
+    /// Nested transactions begun on the underlying `DatabaseTransaction` (through `Deref` etc.)
+    /// aren't affected.
+    pub fn strict_nesting(mut self, enabled: bool) -> Self {
+        self.config_mut().strict_nesting = enabled;
+        self
+    }
+
+    /// Register each request's transaction in `registry`, so it can be aborted from outside the
+    /// request.
+    ///
+    /// Transactions are registered under the request's `x-request-id` header, and requests without
+    /// one aren't registered. On Postgres, registering a transaction also looks up its backend's
+    /// PID (an extra round-trip when it's begun), so that its statements can be cancelled. See
+    /// [`TxRegistry`] for more information.
+    pub fn registry(mut self, registry: TxRegistry) -> Self {
+        self.config_mut().registry = Some(registry);
+        self
+    }
+
+    /// Check that every request's transaction is resolved by the end of the request, logging an
+    /// error if not.
+    ///
+    /// This is a diagnostic for development and tests, to catch a [`Tx`](crate::Tx) that's kept
+    /// beyond the handler (e.g. moved into a spawned task, stored somewhere, or forgotten), which
+    /// holds on to a connection that the middleware can neither commit nor roll back. When the
+    /// middleware comes to resolve the transaction (after the body has been sent, for streaming
+    /// responses), a transaction that's still held by a `Tx` is logged as an `error` event with
+    /// `kind = "tx.leaked"` and the matched route (if any). Transactions resolved by the handler
+    /// (e.g. with [`Tx::commit`](crate::Tx::commit)) aren't reported.
+    ///
+    /// A transaction held by a streaming body when the response is an error is reported too, since
+    /// the middleware can't roll it back either (it's rolled back once the body is dropped). This
+    /// requires the `tracing` feature, and does nothing without it.
+    pub fn leak_check(mut self, enabled: bool) -> Self {
+        self.config_mut().leak_check = enabled;
+        self
+    }
+
+    /// Log a line for each request, with its transaction's outcome.
+    ///
+    /// This brings the details the middleware knows about a request together, for ops who'd rather
+    /// not assemble them from the response extensions. Once the transaction is resolved, an `info`
+    /// event is logged with `kind = "tx.access"` and the fields:
+    ///
+    /// - `method`, `path` and `status`: from the request and response.
+    /// - `outcome`: `commit`, `rollback` or `none`. It's `none` if the request didn't begin a
+    ///   transaction, or the middleware didn't resolve it before returning the response (e.g. the
+    ///   handler committed it, or it's held by a streaming body).
+    /// - `reason`: the [`RollbackReason`](crate::RollbackReason), if it was rolled back.
+    /// - `statements`: the number of statements run through [`Tx`](crate::Tx), if the transaction
+    ///   was begun.
+    /// - `duration`: how long the transaction was open, if it was begun.
+    ///
+    /// The fields are structured, so they can be formatted by any `tracing` subscriber. Requests
+    /// whose inner service fails (rather than returning an error response) aren't logged. This
+    /// requires the `tracing` feature, and does nothing without it.
+    pub fn access_log(mut self, enabled: bool) -> Self {
+        self.config_mut().access_log = enabled;
+        self
+    }
+
+    /// Roll back transactions that only ran reads, instead of committing them.
+    ///
+    /// Committing a transaction that didn't write anything has no effect on the data, but rolling
+    /// it back is cheaper (e.g. Postgres won't assign it a transaction ID). With this enabled, a
+    /// transaction that would be committed is rolled back instead if every statement run through
+    /// [`Tx`](crate::Tx) was a plain `SELECT`, and the [`TxOutcome`](crate::TxOutcome) reason is
+    /// [`RollbackReason::ReadOnly`](crate::RollbackReason::ReadOnly).
+    ///
+    /// Statements are classified by their SQL alone, so this is opt-in: don't enable it if a
+    /// `SELECT` can have side effects that must be committed (e.g. calling a function that writes,
+    /// or `nextval`). Using the underlying `DatabaseTransaction` directly (through `Deref`, `AsRef`
+    /// etc.) or beginning a nested transaction counts as a write, since the statements can't be
+    /// seen. Transactions held by a streaming response body are always committed.
+    pub fn rollback_readonly(mut self, enabled: bool) -> Self {
+        self.config_mut().rollback_readonly = enabled;
+        self
+    }
+
+    /// Prepare transactions for two-phase commit, instead of committing them (Postgres only).
+    ///
+    /// With this enabled, a transaction that would be committed is instead prepared with
+    /// `PREPARE TRANSACTION '<gid>'`, using a unique global transaction identifier, and a
+    /// [`PreparedTransaction`](crate::PreparedTransaction) holding the gid is inserted into the
+    /// response extensions. An external coordinator is then responsible for finishing it with
+    /// `COMMIT PREPARED '<gid>'` or `ROLLBACK PREPARED '<gid>'`. Transactions that would be rolled
+    /// back are still rolled back as normal.
+    ///
+    /// Note the recovery responsibilities this brings:
+    ///
+    /// - A prepared transaction survives the connection, and even a database restart. Until it's
+    ///   finished it holds its locks and prevents `VACUUM` from cleaning up, so a coordinator must
+    ///   resolve every gid it's given, and should periodically look for orphaned transactions in
+    ///   [`pg_prepared_xacts`] (e.g. after a crash between preparing and recording the gid).
+    /// - Postgres disables prepared transactions by default; `max_prepared_transactions` must be
+    ///   set to at least the number of transactions that may be prepared at once.
+    /// - [`Tx::on_commit`](crate::Tx::on_commit) futures are not run, since the transaction isn't
+    ///   committed by this crate.
+    ///
+    /// Preparing fails (and the transaction is rolled back, as with any failed commit) on other
+    /// backends, in autocommit mode, and for transactions held by a streaming response body, whose
+    /// gid couldn't be added to the response.
+    ///
+    /// [`pg_prepared_xacts`]: https://www.postgresql.org/docs/current/view-pg-prepared-xacts.html
+    pub fn two_phase_commit(mut self, enabled: bool) -> Self {
+        self.config_mut().two_phase = enabled;
+        self
+    }
+
+    /// Retry committing once, straight away, if the first attempt fails transiently.
+    ///
+    /// This is shorthand for `retry_commit(BackoffPolicy::fixed(Duration::ZERO, 1))` (or not
+    /// retrying at all if `enabled` is `false`), see [`retry_commit`](Self::retry_commit).
+    pub fn retry_commit_once(mut self, enabled: bool) -> Self {
+        self.config_mut().commit_backoff = enabled.then(|| BackoffPolicy::fixed(Duration::ZERO, 1));
+        self
+    }
+
+    /// Retry committing according to `policy`, if attempts fail transiently.
+    ///
+    /// The work is already staged in the transaction, so a commit that fails because of a
+    /// momentary condition may succeed if it's simply re-issued, without replaying the handler.
+    /// This is best-effort: most failed commits abort the transaction, in which case there's
+    /// nothing to retry and the error is returned as usual. Currently the only failure that's
+    /// retried is SQLite reporting that the database is locked (`SQLITE_BUSY`), which leaves the
+    /// transaction open. This applies to commits made by the [`Service`] middleware as well as
+    /// [`Tx::commit`](crate::Tx::commit).
+    ///
+    /// ```
+    /// use std::time::Duration;
+    /// use axum_sea_orm_tx::{BackoffPolicy, Layer};
+    ///
+    /// # fn foo(pool: sea_orm::DatabaseConnection) {
+    /// let policy =
+    ///     BackoffPolicy::exponential_with_jitter(Duration::from_millis(5), Duration::from_millis(100), 5);
+    /// let layer = Layer::new(pool).retry_commit(policy);
+    /// # }
+    /// ```
+    ///
+    /// Waiting between attempts needs a [`runtime`](Self::runtime), unless `policy` retries
+    /// straight away.
+    pub fn retry_commit(mut self, policy: BackoffPolicy) -> Self {
+        self.config_mut().commit_backoff = Some(policy);
+        self
+    }
+
+    /// **Experimental:** keep up to `n` transactions begun ahead of the requests that use them.
+    ///
+    /// Extracting [`Tx`](crate::Tx) takes a transaction from the queue instead of calling
+    /// `begin()`, which saves a round-trip for latency sensitive endpoints. Once a request's
+    /// transaction has been resolved, the queue is topped back up before the response is returned.
+    /// The queue starts empty, so the first request will begin its own transaction, as will any
+    /// request that finds the queue empty.
+    ///
+    /// Note that this has some correctness caveats:
+    ///
+    /// - Each prewarmed transaction holds a pool connection, so the pool must be larger than `n`,
+    ///   otherwise refilling the queue will wait for the pool's acquire timeout. Note that SeaORM
+    ///   limits SQLite pools to a single connection by default.
+    /// - Depending on the backend and isolation level, a transaction's snapshot may be taken when
+    ///   it begins, so a request may not see data committed between the transaction beginning and
+    ///   the request arriving.
+    /// - Per-request options like [`application_name_from`](Self::application_name_from) are
+    ///   still applied when the transaction is taken from the queue, but anything that must happen
+    ///   before `BEGIN` does not.
+    pub fn prewarm(mut self, n: usize) -> Self {
+        self.config_mut().prewarmed = Some(Prewarmed::new(n));
+        self
+    }
+
+    /// Reuse the same pool connection for requests that share a key computed from the request.
+    ///
+    /// Databases cache prepared statements (and, on some backends, their plans) per connection, so
+    /// requests that run the same queries (e.g. for the same tenant) can benefit from running on the
+    /// same connection. `f` is called with the request head when its transaction begins, and if it
+    /// returns `Some` key, the connection last used by a request with that key is reused if it's
+    /// free:
+    ///
+    /// ```
+    /// use axum_sea_orm_tx::AffinityKey;
+    ///
+    /// # async fn foo() {
+    /// # let pool: sea_orm::DatabaseConnection = todo!();
+    /// let layer = axum_sea_orm_tx::Layer::new(pool).connection_affinity(8, |parts| {
+    ///     let tenant = parts.headers.get("x-tenant")?.to_str().ok()?;
+    ///     Some(AffinityKey::new(tenant))
+    /// });
+    /// # }
+    /// ```
+    ///
+    /// Since SeaORM can't ask the pool for a particular connection, connections are instead held
+    /// back from the pool between requests, up to `max_held` of them (dropping the one held the
+    /// longest to make room). This has some tradeoffs:
+    ///
+    /// - Held connections can't be used by other requests, so the pool must be larger than
+    ///   `max_held`, otherwise requests will wait for the pool's acquire timeout. Note that SeaORM
+    ///   limits SQLite pools to a single connection by default.
+    /// - Each connection is held with a transaction open (SeaORM can only keep a connection out of
+    ///   the pool as a transaction), and the request's transaction is resolved with plain `COMMIT`
+    ///   or `ROLLBACK` statements. So a held connection shows as `idle in transaction` on Postgres,
+    ///   and is closed if it stays that way longer than `idle_in_transaction_session_timeout`. As
+    ///   with [`prewarm`](Self::prewarm), a request may not see data committed between the
+    ///   transaction beginning and the request arriving, depending on the backend and isolation
+    ///   level.
+    /// - Transactions that need different settings (e.g. an [`IsolationMarker`]) don't use held
+    ///   connections, and nor do read-only requests routed to a replica by
+    ///   [`read_write_split`](Layer::read_write_split).
+    ///
+    /// [`IsolationMarker`]: crate::IsolationMarker
+    pub fn connection_affinity<F>(mut self, max_held: usize, f: F) -> Self
+    where
+        F: Fn(&Parts) -> Option<AffinityKey> + Send + Sync + 'static,
+    {
+        self.config_mut().affinity = Some((Affinity::new(max_held), Arc::new(f)));
+        self
+    }
+}
+
+impl<C, E> Layer<C, E>
+where
+    C: TransactionTrait + ConnectionTrait + Clone + Send + Sync + 'static,
+{
+    /// Fall back to running statements directly on the pool if it doesn't support transactions.
+    ///
+    /// Some deployments (e.g. PgBouncer in statement pooling mode) reject `BEGIN`. When this is
+    /// enabled and beginning the transaction fails with such an error, statements are instead run
+    /// directly on the pool, and "committing" does nothing.
+    ///
+    /// **Warning:** this disables the main feature of this crate! In autocommit mode, every
+    /// statement is committed as soon as it runs, so nothing is rolled back when a handler fails.
+    /// Furthermore, there's no [`sea_orm::DatabaseTransaction`] for [`Tx`](crate::Tx) to
+    /// dereference to, so extracting `Tx` fails with
+    /// [`Error::Autocommit`](crate::Error::Autocommit): handlers must extract
+    /// [`AutocommitTx`](crate::AutocommitTx) instead, which only supports the
+    /// [`sea_orm::ConnectionTrait`] methods.
+    pub fn autocommit_fallback(mut self, enabled: bool) -> Self {
+        self.config_mut().autocommit_fallback = if enabled {
+            Some(Arc::new(self.pool.clone()))
+        } else {
+            None
+        };
+        self
+    }
+
+    /// Only report the [`Service`] as ready when `probe` is.
+    ///
+    /// See [`ReadinessProbe`] for how this can be used to shed load when the database is at
+    /// capacity.
+    pub fn readiness_probe(mut self, probe: impl ReadinessProbe) -> Self {
+        self.config_mut().readiness_probe = Some(Arc::new(probe));
+        self
+    }
+
+    /// Convert the inner service's errors into responses, rolling back the transaction for them.
+    ///
+    /// By default the inner service's errors are passed through unchanged, so another layer must
+    /// handle them (and the [`Service`] is only infallible if the inner service is). If the errors
+    /// implement [`IntoResponse`](axum_core::response::IntoResponse), this composes the layer with
+    /// [`HandleInnerErrorsLayer`], which converts them into responses beneath the [`Service`], so
+    /// the resulting service is infallible and can be used anywhere in a `tower` stack:
+    ///
+    /// ```
+    /// use axum::Router;
+    ///
+    /// # async fn foo() {
+    /// let pool = sea_orm::Database::connect("sqlite::memory:").await.unwrap();
+    /// let app = Router::new()
+    ///     // .route(...)s
+    ///     .layer(axum_sea_orm_tx::Layer::new(pool).handle_inner_errors());
+    /// # let _: Router = app;
+    /// # }
+    /// ```
+    ///
+    /// The transaction is rolled back for error responses whatever their status, and the
+    /// [`TxOutcome`] has [`RollbackReason::InnerError`].
+    pub fn handle_inner_errors(self) -> tower_layer::Stack<HandleInnerErrorsLayer, Self> {
+        tower_layer::Stack::new(HandleInnerErrorsLayer, self)
+    }
+}
+
 impl<S, C: TransactionTrait + Clone, E> tower_layer::Layer<S> for Layer<C, E> {
     type Service = Service<S, C, E>;
 
     fn layer(&self, inner: S) -> Self::Service {
+        assert!(
+            self.config.commit_deadline.is_none() || self.config.sleep().is_some(),
+            "a commit deadline requires the `runtime-tokio` or `runtime-async-std` feature"
+        );
+        let waits = (self.config.commit_backoff).is_some_and(|policy| !policy.is_immediate());
+        assert!(
+            !waits || self.config.sleep().is_some(),
+            "a commit backoff requires the `runtime-tokio` or `runtime-async-std` feature"
+        );
         Service {
             pool: self.pool.clone(),
+            replica: self.replica.clone(),
             inner,
+            config: self.config.clone(),
             _error: self._error,
         }
     }
@@ -80,7 +1186,9 @@ impl<S, C: TransactionTrait + Clone, E> tower_layer::Layer<S> for Layer<C, E> {
 /// See [`Layer`] for more information.
 pub struct Service<S, C: TransactionTrait = DatabaseConnection, E = Error> {
     pool: C,
+    replica: Option<C>,
     inner: S,
+    config: Arc<Config>,
     _error: PhantomData<E>,
 }
 
@@ -89,7 +1197,9 @@ impl<S: Clone, C: TransactionTrait + Clone, E> Clone for Service<S, C, E> {
     fn clone(&self) -> Self {
         Self {
             pool: self.pool.clone(),
+            replica: self.replica.clone(),
             inner: self.inner.clone(),
+            config: self.config.clone(),
             _error: self._error,
         }
     }
@@ -98,17 +1208,14 @@ impl<S: Clone, C: TransactionTrait + Clone, E> Clone for Service<S, C, E> {
 impl<S, C: TransactionTrait + Clone + Send + Sync + 'static, E, ReqBody, ResBody>
     tower_service::Service<http::Request<ReqBody>> for Service<S, C, E>
 where
-    S: tower_service::Service<
-        http::Request<ReqBody>,
-        Response = http::Response<ResBody>,
-        Error = std::convert::Infallible,
-    >,
+    S: tower_service::Service<http::Request<ReqBody>, Response = http::Response<ResBody>>,
     S::Future: Send + 'static,
-    E: From<Error> + IntoResponse,
-    ResBody: Body<Data = Bytes> + Send + 'static,
+    S::Error: Send + 'static,
+    E: From<Error> + IntoResponseFor,
+    ResBody: Body + Send + 'static,
     ResBody::Error: Into<Box<dyn std::error::Error + Send + Sync + 'static>>,
 {
-    type Response = http::Response<UnsyncBoxBody<ResBody::Data, axum_core::Error>>;
+    type Response = http::Response<ResponseBody>;
     type Error = S::Error;
     type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
 
@@ -116,33 +1223,384 @@ where
         &mut self,
         cx: &mut std::task::Context<'_>,
     ) -> std::task::Poll<Result<(), Self::Error>> {
-        self.inner.poll_ready(cx).map_err(|err| match err {})
+        if let Some(probe) = &self.config.readiness_probe {
+            ready!(probe.poll_ready(cx));
+        }
+        self.inner.poll_ready(cx)
     }
 
-    fn call(&mut self, mut req: http::Request<ReqBody>) -> Self::Future {
-        let transaction = TxSlot::bind(req.extensions_mut(), self.pool.clone());
+    fn call(&mut self, req: http::Request<ReqBody>) -> Self::Future {
+        let (mut parts, body) = req.into_parts();
 
-        let res = self.inner.call(req);
+        let skipped = self.config.is_skipped(parts.uri.path());
+        if let Some(meta) = &self.config.meta {
+            parts.extensions.insert(meta.clone());
+        }
 
-        Box::pin(async move {
-            let res = res.await.unwrap(); // inner service is infallible
+        #[cfg(feature = "audit")]
+        let audit = self.config.audit.clone().filter(|_| !skipped).map(|sink| {
+            let log = AuditLog::new(self.config.redact_audit_values);
+            (sink, log)
+        });
 
-            if res.status().is_success() {
-                if let Err(error) = transaction.commit().await {
-                    return Ok(E::from(Error::Database { error }).into_response());
+        let replica = (self.replica.as_ref()).filter(|_| self.config.is_read(&parts));
+        let transaction = if skipped {
+            None
+        } else {
+            TxSlot::bind(
+                &mut parts.extensions,
+                replica.unwrap_or(&self.pool).clone(),
+                replica.is_some(),
+                self.config.clone(),
+                #[cfg(feature = "audit")]
+                audit.as_ref().map(|(_, log)| log.clone()),
+            )
+        };
+        let request_id = self.config.annotate_outcome.then(|| request_id(&parts));
+        #[cfg(feature = "tracing")]
+        let access_log =
+            (self.config.access_log).then(|| (parts.method.clone(), parts.uri.path().to_string()));
+        let req = http::Request::from_parts(parts, body);
+
+        let commit_failure_status = self.config.commit_failure_status;
+        let max_open_duration = self.config.max_open_duration;
+        let commit_deadline = self.config.commit_deadline.map(|deadline| {
+            let sleep = self
+                .config
+                .sleep()
+                .expect("BUG: commit deadline without a runtime");
+            (deadline, sleep)
+        });
+        let explicit_commit_only = self.config.explicit_commit_only;
+        let rollback_readonly = self.config.rollback_readonly;
+        let two_phase = self.config.two_phase;
+        let dry_run = self.config.dry_run;
+        let config = self.config.clone();
+        let pending = Pending {
+            inner: Some(self.inner.call(req)),
+            transaction,
+        };
+
+        let resolve = pending.then(move |(res, transaction)| async move {
+            let res = match res {
+                Ok(res) => res,
+                Err(error) => {
+                    // The inner service failed (e.g. timed out), so there's no response to judge
+                    if let Some(transaction) = transaction {
+                        transaction.rollback().await;
+                    }
+                    return Err(error);
                 }
+            };
+
+            let Some(mut transaction) = transaction else {
+                // The path is skipped, or an outer layer is responsible for the transaction
+                return Ok(res.map(boxed));
+            };
+
+            // Render a `TxResponse` while the transaction is still open, so the outcome is decided
+            // on the rendered response
+            let mut res = res.map(boxed);
+            if let Some(deferred) = res.extensions_mut().remove::<Deferred>() {
+                res = deferred.render(res).await;
+            }
+
+            #[cfg(feature = "tracing")]
+            if let Some(begun_at) = transaction.begun_at().filter(|_| config.access_log) {
+                // Carried to the access log, whichever way the transaction is resolved
+                res.extensions_mut().insert(BegunAt(begun_at));
+            }
+
+            if let Some(threshold) = max_open_duration {
+                let elapsed = transaction.begun_at().map(|begun_at| begun_at.elapsed());
+                if let Some(elapsed) = elapsed.filter(|elapsed| *elapsed > threshold) {
+                    #[cfg(feature = "tracing")]
+                    tracing::warn!(?elapsed, ?threshold, "request transaction open too long");
+                    res.extensions_mut()
+                        .insert(LongTransaction { elapsed, threshold });
+                }
+            }
+
+            if let Some(count) = transaction.statements() {
+                res.extensions_mut().insert(StatementCount { count });
             }
 
-            Ok(res.map(|body| body.map_err(axum_core::Error::new).boxed_unsync()))
-        })
+            let resolution = Resolution::take(res.extensions_mut());
+            let snapshot = transaction.is_snapshot();
+            let aborted = transaction.is_aborted();
+            let failed = transaction.is_failed();
+
+            if let Some(trailer) = config.commit_trailer.clone() {
+                let decided = resolution.is_some()
+                    || snapshot
+                    || aborted
+                    || failed
+                    || dry_run
+                    || explicit_commit_only
+                    || transaction.is_commit_anyway();
+                if !decided && transaction.begun_at().is_some() {
+                    // The trailers come after the body, so decide once it's been sent
+                    return Ok(res.map(|body| {
+                        CommitOnTrailer::new(body, transaction, trailer).boxed_unsync()
+                    }));
+                }
+            }
+
+            // The commit predicate is given the response head, so take the response apart
+            let (head, body) = res.into_parts();
+            let commit = match resolution {
+                _ if snapshot || dry_run || aborted || failed => false,
+                Some(resolution) => resolution == Resolution::Commit,
+                None if transaction.is_commit_anyway() => true,
+                None => !explicit_commit_only && config.commits(&head),
+            };
+            let mut res = http::Response::from_parts(head, body);
+
+            if commit {
+                if two_phase && transaction.is_leased() {
+                    // The gid can't be returned once the response has been sent, so fail now (the
+                    // transaction is rolled back once the response body is dropped)
+                    let error = DbErr::Custom(
+                        "two-phase commit is not supported for streaming responses".to_string(),
+                    );
+                    let error = Error::commit_failed(error);
+                    return Ok(commit_failed::<E>(&res, error, commit_failure_status));
+                }
+
+                if transaction.is_leased() {
+                    // The transaction is held by the response body, so commit once it's been sent
+                    return Ok(res.map(|body| CommitOnEnd::new(body, transaction).boxed_unsync()));
+                }
+
+                if rollback_readonly && !transaction.is_written() {
+                    if let Some(request_id) = &request_id {
+                        let sql = outcome_annotation(request_id.as_deref(), "rollback");
+                        transaction.annotate(sql).await;
+                    }
+                    if transaction.rollback().await {
+                        res.extensions_mut()
+                            .insert(TxOutcome::rolled_back(RollbackReason::ReadOnly));
+                    }
+                    return Ok(res);
+                }
+
+                if two_phase {
+                    let gid = prepared_transaction_id();
+                    match transaction.prepare(&gid).await {
+                        Ok(true) => {
+                            res.extensions_mut().insert(PreparedTransaction { gid });
+                        }
+                        Ok(false) => {}
+                        Err(error) => {
+                            let error = Error::commit_failed(error);
+                            return Ok(commit_failed::<E>(&res, error, commit_failure_status));
+                        }
+                    }
+                    return Ok(res);
+                }
+
+                if let Some(request_id) = &request_id {
+                    let sql = outcome_annotation(request_id.as_deref(), "commit");
+                    transaction.annotate(sql).await;
+                }
+                match transaction.commit(commit_deadline).await {
+                    Ok(true) => {
+                        res.extensions_mut().insert(TxOutcome::committed());
+                    }
+                    Ok(false) => {}
+                    Err(error) => {
+                        return Ok(commit_failed::<E>(&res, error, commit_failure_status));
+                    }
+                }
+            } else {
+                if let Some(request_id) = &request_id {
+                    let sql = outcome_annotation(request_id.as_deref(), "rollback");
+                    transaction.annotate(sql).await;
+                }
+                if transaction.rollback().await {
+                    let reason = match resolution {
+                        _ if aborted => RollbackReason::Aborted,
+                        _ if failed => RollbackReason::StatementFailed,
+                        _ if dry_run => RollbackReason::DryRun,
+                        _ if snapshot => RollbackReason::Snapshot,
+                        Some(Resolution::InnerError) => RollbackReason::InnerError,
+                        Some(_) => RollbackReason::Requested,
+                        None if explicit_commit_only => RollbackReason::NotCommitted,
+                        None => RollbackReason::Status,
+                    };
+                    res.extensions_mut().insert(TxOutcome::rolled_back(reason));
+                }
+            }
+
+            Ok(res)
+        });
+
+        let prewarmed =
+            (self.config.prewarmed.clone()).map(|prewarmed| (prewarmed, self.pool.clone()));
+        Box::pin(resolve.then(move |res| async move {
+            #[cfg(feature = "audit")]
+            if let Some((sink, log)) = audit {
+                let outcome = res
+                    .as_ref()
+                    .ok()
+                    .and_then(|res| res.extensions().get().copied());
+                log.emit(&sink, outcome);
+            }
+
+            #[cfg(feature = "tracing")]
+            let res = res.map(|mut res| {
+                if let Some((method, path)) = access_log {
+                    log_access(&method, &path, &mut res);
+                }
+                res
+            });
+
+            if let Some((prewarmed, pool)) = prewarmed {
+                prewarmed.refill(&pool).await;
+            }
+            res
+        }))
+    }
+}
+
+/// When the transaction was begun, stored in the response extensions for [`Layer::access_log`].
+#[cfg(feature = "tracing")]
+#[derive(Clone, Copy)]
+struct BegunAt(std::time::Instant);
+
+/// Log the access log line for a request (see [`Layer::access_log`]).
+#[cfg(feature = "tracing")]
+fn log_access(method: &http::Method, path: &str, res: &mut http::Response<ResponseBody>) {
+    let begun_at = res.extensions_mut().remove::<BegunAt>();
+    let outcome = res.extensions().get::<TxOutcome>();
+    let statements = res.extensions().get::<StatementCount>();
+    tracing::info!(
+        kind = "tx.access",
+        %method,
+        path,
+        status = res.status().as_u16(),
+        outcome = match outcome {
+            Some(outcome) if outcome.committed => "commit",
+            Some(_) => "rollback",
+            None => "none",
+        },
+        reason = outcome.and_then(|outcome| outcome.reason).map(tracing::field::debug),
+        statements = statements.map(|statements| statements.count),
+        duration = begun_at.map(|BegunAt(begun_at)| tracing::field::debug(begun_at.elapsed())),
+        "request"
+    );
+}
+
+/// The response to return when committing (or preparing) the transaction fails.
+fn commit_failed<E: From<Error> + IntoResponseFor>(
+    res: &http::Response<impl Body>,
+    error: Error,
+    status: Option<StatusCode>,
+) -> http::Response<ResponseBody> {
+    let content_type = res.headers().get(http::header::CONTENT_TYPE);
+    let mut res = E::from(error).into_response_for(content_type);
+    if let Some(status) = status {
+        *res.status_mut() = status;
+    }
+    res.extensions_mut()
+        .insert(TxOutcome::rolled_back(RollbackReason::CommitFailed));
+    res
+}
+
+/// The ID of the request, for [`Layer::annotate_outcome`].
+///
+/// The ID ends up in a SQL comment, so only characters that can't end the comment are kept.
+fn request_id(parts: &Parts) -> Option<String> {
+    let id = parts.headers.get("x-request-id")?.to_str().ok()?;
+    let id: String = id
+        .chars()
+        .filter(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.' | ':'))
+        .collect();
+    (!id.is_empty()).then_some(id)
+}
+
+/// The statement annotating a transaction with its `outcome`, for [`Layer::annotate_outcome`].
+fn outcome_annotation(request_id: Option<&str>, outcome: &str) -> String {
+    match request_id {
+        Some(request_id) => format!("SELECT /* request_id={request_id} outcome={outcome} */ 1"),
+        None => format!("SELECT /* outcome={outcome} */ 1"),
+    }
+}
+
+/// Generate a global transaction identifier for two-phase commit.
+///
+/// Prepared transactions outlive the process, so the identifier includes the time as well as the
+/// process ID and a counter, to avoid clashing with one left over by a previous process.
+fn prepared_transaction_id() -> String {
+    static COUNTER: AtomicU64 = AtomicU64::new(0);
+
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_nanos();
+    let n = COUNTER.fetch_add(1, Ordering::Relaxed);
+    format!("axum_sea_orm_tx_{}_{nanos}_{n}", std::process::id())
+}
+
+pin_project! {
+    /// The inner service's response future, and the request's transaction.
+    ///
+    /// If the request is cancelled (i.e. this is dropped) before the response is ready, e.g.
+    /// because the client disconnected, the transaction is rolled back straight away rather than
+    /// whenever the request extensions happen to be dropped.
+    struct Pending<F> {
+        #[pin]
+        inner: Option<F>,
+        transaction: Option<TxSlot>,
+    }
+
+    impl<F> PinnedDrop for Pending<F> {
+        fn drop(this: Pin<&mut Self>) {
+            let mut this = this.project();
+
+            // Drop the inner future first, so that any transaction it holds returns to the slot
+            this.inner.set(None);
+
+            if let Some(transaction) = this.transaction.take() {
+                if transaction.abandon() {
+                    #[cfg(feature = "tracing")]
+                    tracing::debug!("request cancelled, rolling back transaction");
+                }
+            }
+        }
+    }
+}
+
+impl<F: Future> Future for Pending<F> {
+    /// The inner service's response, and the transaction (unless an outer layer is responsible
+    /// for it).
+    type Output = (F::Output, Option<TxSlot>);
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let mut this = self.project();
+        let inner = this.inner.as_mut().as_pin_mut();
+        let res = ready!(inner.expect("BUG: polled after completion").poll(cx));
+
+        // Drop the inner future first, so that any transaction it holds returns to the slot
+        this.inner.set(None);
+        Poll::Ready((res, this.transaction.take()))
     }
 }
 
 #[cfg(test)]
 mod tests {
+    use std::{
+        convert::Infallible,
+        pin::Pin,
+        task::{Context, Poll},
+    };
+
+    use bytes::Bytes;
+    use http_body::combinators::UnsyncBoxBody;
     use sea_orm::DatabaseConnection;
 
     use super::Layer;
+    use crate::ResponseBody;
 
     // The trait shenanigans required by axum for layers are significant, so this "test" ensures
     // we've got it right.
@@ -152,8 +1610,55 @@ mod tests {
 
         let app = axum::Router::new()
             .route("/", axum::routing::get(|| async { "hello" }))
-            .layer(&Layer::new(pool));
+            .layer(Layer::new(pool));
 
         axum::Server::bind(todo!()).serve(app.into_make_service());
     }
+
+    // Check that the middleware accepts a variety of inner response bodies, and always responds
+    // with `ResponseBody`.
+    #[allow(unused, unreachable_code, clippy::diverging_sub_expression)]
+    fn body_types_compile() {
+        fn service_for<B>() -> impl tower_service::Service<
+            http::Request<axum::body::Body>,
+            Response = http::Response<ResponseBody>,
+            Error = Infallible,
+        >
+        where
+            B: http_body::Body + Send + 'static,
+            B::Error: Into<Box<dyn std::error::Error + Send + Sync + 'static>>,
+        {
+            let pool: DatabaseConnection = todo!();
+            let inner = tower::service_fn(|_: http::Request<axum::body::Body>| async {
+                Ok::<_, Infallible>(http::Response::<B>::new(todo!()))
+            });
+            tower_layer::Layer::layer(&Layer::new(pool), inner)
+        }
+
+        service_for::<axum::body::Body>();
+        service_for::<UnsyncBoxBody<Bytes, axum_core::Error>>();
+        service_for::<CustomBody>();
+    }
+
+    /// A body with non-`Bytes` data.
+    struct CustomBody;
+
+    impl http_body::Body for CustomBody {
+        type Data = &'static [u8];
+        type Error = Infallible;
+
+        fn poll_data(
+            self: Pin<&mut Self>,
+            _cx: &mut Context<'_>,
+        ) -> Poll<Option<Result<Self::Data, Self::Error>>> {
+            Poll::Ready(None)
+        }
+
+        fn poll_trailers(
+            self: Pin<&mut Self>,
+            _cx: &mut Context<'_>,
+        ) -> Poll<Result<Option<http::HeaderMap>, Self::Error>> {
+            Poll::Ready(Ok(None))
+        }
+    }
 }
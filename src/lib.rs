@@ -12,6 +12,9 @@
 //!
 //! This behaviour is often a sensible default, and using the extractor (e.g. rather than directly
 //! using [`sea_orm::DatabaseTransaction`]s) means you can't forget to commit the transactions!
+//! Where the status code doesn't reflect whether the work should be kept, handlers can wrap their
+//! response in [`Commit`] or [`Rollback`] to decide explicitly, or be adapted with [`tx_handler`]
+//! to decide by returning `Ok` or `Err`.
 //!
 //! [axum extractors]: https://docs.rs/axum/latest/axum/#extractors
 //! [request extensions]: https://docs.rs/http/latest/http/struct.Extensions.html
@@ -55,14 +58,16 @@
 //! ```
 //!
 //! If you forget to add the middleware you'll get [`Error::MissingExtension`] (internal server
-//! error) when using the extractor. You'll also get an error ([`Error::OverlappingExtractors`]) if
+//! error) when using the extractor, and debug builds log an error pointing at the missing
+//! middleware (with the `tracing` feature). You'll also get an error ([`Error::OverlappingExtractors`]) if
 //! you have multiple `Tx` arguments in a single handler, or call `Tx::from_request` multiple times
-//! in a single middleware.
+//! in a single middleware, which is logged along with the matched route (with the `tracing`
+//! feature).
 //!
 //! ## Error handling
 //!
 //! `axum` requires that middleware do not return errors, and that the errors returned by extractors
-//! implement `IntoResponse`. By default, [`Error`](Error) is used by [`Layer`] and [`Tx`] to
+//! implement `IntoResponse`. By default, [`Error`] is used by [`Layer`] and [`Tx`] to
 //! convert errors into HTTP 500 responses, with the error's `Display` value as the response body,
 //! however it's generally not a good practice to return internal error details to clients!
 //!
@@ -91,6 +96,10 @@
 //!     }
 //! }
 //!
+//! // Errors used by the layer must also implement IntoResponseFor, which can optionally render
+//! // commit failures in the same content type as the handler's response
+//! impl axum_sea_orm_tx::IntoResponseFor for MyError {}
+//!
 //! // Change the layer error type
 //! # async fn foo() {
 //! # let pool: sea_orm::DatabaseConnection = todo!();
@@ -114,15 +123,68 @@
 
 #![cfg_attr(doc, deny(warnings))]
 
+mod affinity;
+#[cfg(feature = "audit")]
+mod audit;
+mod backoff;
+mod body;
+mod config;
+mod connection;
+mod constraint;
+mod current;
+mod handle_error;
+mod isolation;
 mod layer;
+mod meta;
+mod prewarm;
+mod readiness;
+mod registry;
+mod response;
+mod runtime;
 mod slot;
+mod stream;
+#[cfg(feature = "testing")]
+mod testing;
 mod tx;
 
+use std::sync::Arc;
+
 use sea_orm::DbErr;
 
+#[cfg(feature = "audit")]
+pub use crate::audit::{AuditRecord, AuditedStatement};
+#[cfg(feature = "runtime-async-std")]
+pub use crate::runtime::AsyncStd;
+#[cfg(feature = "runtime-tokio")]
+pub use crate::runtime::Tokio;
+#[cfg(feature = "testing")]
+pub use crate::testing::TxFixture;
 pub use crate::{
-    layer::{Layer, Service},
-    tx::Tx,
+    affinity::AffinityKey,
+    backoff::BackoffPolicy,
+    body::ResponseBody,
+    config::{TxConfig, TxConfigService},
+    constraint::{ConstraintKind, ConstraintViolation},
+    current::current_tx,
+    handle_error::{HandleInnerErrors, HandleInnerErrorsLayer},
+    isolation::{
+        DefaultIsolation, IsolationMarker, ReadCommitted, ReadOnly, ReadUncommitted,
+        RepeatableRead, Serializable, Snapshot,
+    },
+    layer::{CommitStatuses, Layer, Service},
+    meta::TxMeta,
+    readiness::ReadinessProbe,
+    registry::TxRegistry,
+    response::{
+        tx_handler, Commit, ForceCommit, ForceRollback, LongTransaction, PreparedTransaction,
+        Rollback, RollbackReason, StatementCount, TxOutcome, TxResponse,
+    },
+    runtime::Runtime,
+    stream::{PartialRows, TxStream},
+    tx::{
+        AutocommitTx, EagerTx, OptionalTx, SharedTx, SnapshotTx, Tx, TxBorrow, TxJson,
+        TxJsonRejection, TxWithRoute,
+    },
 };
 
 /// Possible errors when extracting [`Tx`] from a request.
@@ -156,9 +218,12 @@ pub use crate::{
 ///     /* ... */
 /// }
 /// ```
-#[derive(Debug, thiserror::Error)]
+///
+/// `Error` is `Clone`, so it can be stored (e.g. in response extensions) as well as reported.
+/// Database errors are shared behind an [`Arc`] for this.
+#[derive(Debug, Clone, thiserror::Error)]
 pub enum Error {
-    /// Indicates that the [`Layer`](crate::Layer) middleware was not installed.
+    /// Indicates that the [`Layer`] middleware was not installed.
     #[error(
         "required extension not registered; did you add the axum_sea_orm_tx::Layer middleware?"
     )]
@@ -168,16 +233,200 @@ pub enum Error {
     #[error("axum_sea_orm_tx::Tx extractor used multiple times in the same handler/middleware")]
     OverlappingExtractors,
 
+    /// Indicates that [`TxWithRoute`] was extracted for a request that didn't match a route.
+    #[error("no matched route path for axum_sea_orm_tx::TxWithRoute")]
+    MissingMatchedPath,
+
+    /// Indicates that [`Tx`] was extracted with an [`IsolationMarker`] asking for different
+    /// settings than the request's transaction was begun with.
+    #[error(
+        "axum_sea_orm_tx::Tx extracted with a different isolation level or access mode than the \
+        request's transaction was begun with"
+    )]
+    ConflictingIsolation,
+
+    /// Indicates that a request ran more statements through [`Tx`] than allowed by
+    /// [`Layer::max_statements`].
+    ///
+    /// Statements over the limit fail with a [`DbErr::Custom`] holding this error's message.
+    #[error("request transaction ran more than the maximum of {max} statements")]
+    TooManyStatements {
+        /// The configured maximum.
+        max: usize,
+    },
+
+    /// Indicates that a nested transaction was begun on [`Tx`] with [`Layer::strict_nesting`]
+    /// enabled.
+    ///
+    /// `Tx::begin` (and the other [`TransactionTrait`](sea_orm::TransactionTrait) methods) fail
+    /// with a [`DbErr::Custom`] holding this error's message.
+    #[error(
+        "nested transactions are disabled for axum_sea_orm_tx::Tx; use Tx::with_savepoint instead"
+    )]
+    NestedTransaction,
+
+    /// Indicates that [`Tx::flush`] was called in a transaction, whose writes can't be made
+    /// visible without committing it.
+    ///
+    /// `Tx::flush` fails with a [`DbErr::Custom`] holding this error's message.
+    #[error(
+        "Tx::flush can't make writes visible without ending the request transaction; use \
+        Tx::commit_and_pin first"
+    )]
+    FlushInTransaction,
+
+    /// Indicates that the request's transaction was aborted through a [`TxRegistry`].
+    ///
+    /// Statements run through [`Tx`] after the transaction was aborted fail with a
+    /// [`DbErr::Custom`] holding this error's message, as does [`Tx::commit`] if the transaction
+    /// was aborted by a failed statement (see [`Tx::is_aborted`]).
+    #[error("the request transaction was aborted")]
+    Aborted,
+
+    /// Indicates that the request's transaction was closed, because [`Tx::commit_and_begin`] or
+    /// [`Tx::rollback_and_reopen`] failed before a new transaction was begun in its place.
+    ///
+    /// Statements run through [`Tx`] after that fail with a [`DbErr::Custom`] holding this error's
+    /// message, as does committing the transaction. `Tx::rollback_and_reopen` can be used to try
+    /// beginning a new transaction again.
+    #[error("the request transaction was closed, since a new one couldn't be begun in its place")]
+    Closed,
+
+    /// Indicates that [`Tx`] was extracted for a request whose transaction fell back to autocommit
+    /// mode (see [`Layer::autocommit_fallback`]), which only [`AutocommitTx`] supports.
+    #[error(
+        "the request transaction fell back to autocommit mode; use axum_sea_orm_tx::AutocommitTx \
+        to extract it"
+    )]
+    Autocommit,
+
+    /// Indicates that committing the transaction took longer than
+    /// [`Layer::commit_deadline`].
+    ///
+    /// The transaction is rolled back if the commit hadn't finished, but the database may already
+    /// have committed it.
+    #[error("committing the request transaction took longer than {deadline:?}")]
+    CommitTimeout {
+        /// The configured deadline.
+        deadline: std::time::Duration,
+    },
+
     /// A database error occurred when starting the transaction.
     #[error(transparent)]
-    Database {
-        #[from]
-        error: DbErr,
+    Database { error: Arc<DbErr> },
+
+    /// Committing the transaction failed because it violated a constraint.
+    ///
+    /// This is used instead of [`Error::Database`] when the commit error could be recognised as a
+    /// unique, foreign key, or check constraint violation (e.g. with deferred constraints).
+    #[error("{error}")]
+    ConstraintViolation {
+        /// The details of the violated constraint.
+        violation: ConstraintViolation,
+
+        /// The underlying database error.
+        #[source]
+        error: Arc<DbErr>,
     },
 }
 
+impl From<DbErr> for Error {
+    fn from(error: DbErr) -> Self {
+        Self::Database {
+            error: Arc::new(error),
+        }
+    }
+}
+
+impl Error {
+    /// Wrap an error returned when committing the transaction.
+    pub(crate) fn commit_failed(error: DbErr) -> Self {
+        match ConstraintViolation::parse(&error) {
+            Some(violation) => Self::ConstraintViolation {
+                violation,
+                error: Arc::new(error),
+            },
+            None => error.into(),
+        }
+    }
+
+    /// The status code of the error's response.
+    ///
+    /// This is the status used by the `IntoResponse` implementation, so custom responses can be
+    /// built around it:
+    ///
+    /// ```
+    /// use axum::response::IntoResponse;
+    ///
+    /// fn respond(error: axum_sea_orm_tx::Error) -> axum::response::Response {
+    ///     (error.status_code(), "oops").into_response()
+    /// }
+    /// ```
+    ///
+    /// Every error is currently an internal server error, since they're caused by the application
+    /// or the database rather than the request.
+    pub fn status_code(&self) -> http::StatusCode {
+        match self {
+            Self::MissingExtension
+            | Self::OverlappingExtractors
+            | Self::MissingMatchedPath
+            | Self::ConflictingIsolation
+            | Self::TooManyStatements { .. }
+            | Self::NestedTransaction
+            | Self::FlushInTransaction
+            | Self::Aborted
+            | Self::Closed
+            | Self::Autocommit
+            | Self::CommitTimeout { .. }
+            | Self::Database { .. }
+            | Self::ConstraintViolation { .. } => http::StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+}
+
+impl From<&Error> for http::StatusCode {
+    fn from(error: &Error) -> Self {
+        error.status_code()
+    }
+}
+
+impl From<Error> for http::StatusCode {
+    fn from(error: Error) -> Self {
+        error.status_code()
+    }
+}
+
 impl axum_core::response::IntoResponse for Error {
     fn into_response(self) -> axum_core::response::Response {
-        (http::StatusCode::INTERNAL_SERVER_ERROR, self.to_string()).into_response()
+        (self.status_code(), self.to_string()).into_response()
+    }
+}
+
+/// Error types that can render a response in a particular content type.
+///
+/// [`Layer`] uses this when committing the transaction fails, to replace the handler's response
+/// with an error response. The `Content-Type` of the handler's response is passed along, so that
+/// e.g. a JSON API can return a JSON error. The default implementation ignores the content type
+/// and uses [`IntoResponse`](axum_core::response::IntoResponse), so custom error types used with
+/// [`Layer`] can opt in with an empty `impl`:
+///
+/// ```
+/// # use axum::response::IntoResponse;
+/// # struct MyError(axum_sea_orm_tx::Error);
+/// # impl IntoResponse for MyError {
+/// #     fn into_response(self) -> axum::response::Response { todo!() }
+/// # }
+/// impl axum_sea_orm_tx::IntoResponseFor for MyError {}
+/// ```
+pub trait IntoResponseFor: axum_core::response::IntoResponse + Sized {
+    /// Create a response, preferably of the given `content_type`.
+    fn into_response_for(
+        self,
+        content_type: Option<&http::HeaderValue>,
+    ) -> axum_core::response::Response {
+        let _ = content_type;
+        self.into_response()
     }
 }
+
+impl IntoResponseFor for Error {}
@@ -0,0 +1,151 @@
+//! Details of the database the pool connects to.
+
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use axum::extract::FromRequestParts;
+use http::request::Parts;
+use sea_orm::{ConnectOptions, DbBackend};
+
+use crate::Error;
+
+/// Details of the database the [`Layer`](crate::Layer)'s pool connects to, from the
+/// [`ConnectOptions`] it was built with.
+///
+/// A `DatabaseConnection` doesn't keep most of its options, so they must be given to the layer with
+/// [`Layer::new_with_options`](crate::Layer::new_with_options). `TxMeta` can then be extracted in
+/// handlers, or read from [`Tx::meta`](crate::Tx::meta):
+///
+/// ```
+/// use axum_sea_orm_tx::TxMeta;
+///
+/// async fn handler(meta: TxMeta) -> String {
+///     format!("connected to {:?} on {:?}", meta.database, meta.host)
+/// }
+/// ```
+///
+/// Extracting `TxMeta` fails with [`Error::MissingExtension`] if the layer wasn't given the
+/// options. Credentials are never kept: the password in the URL (and any query parameter that looks
+/// like one) is replaced with `***`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct TxMeta {
+    /// The database backend, from the URL's scheme (`None` if it's not one SeaORM knows).
+    pub backend: Option<DbBackend>,
+
+    /// The URL, with any password redacted.
+    pub url: String,
+
+    /// The host, if the URL has one (SQLite URLs don't).
+    pub host: Option<String>,
+
+    /// The database name (or file, for SQLite), if the URL has one.
+    pub database: Option<String>,
+
+    /// The maximum number of connections in the pool, if configured.
+    pub max_connections: Option<u32>,
+
+    /// Whether SQLx logs statements.
+    pub sqlx_logging: bool,
+}
+
+impl TxMeta {
+    pub(crate) fn new(options: &ConnectOptions) -> Self {
+        let url = options.get_url();
+        let (scheme, rest) = url.split_once(':').unwrap_or((url, ""));
+        let backend = match scheme {
+            "postgres" | "postgresql" => Some(DbBackend::Postgres),
+            "mysql" => Some(DbBackend::MySql),
+            "sqlite" => Some(DbBackend::Sqlite),
+            _ => None,
+        };
+
+        let Some(rest) = rest.strip_prefix("//") else {
+            // e.g. `sqlite::memory:`, which has nothing to redact
+            return Self {
+                backend,
+                url: url.to_string(),
+                host: None,
+                database: (!rest.is_empty()).then(|| rest.to_string()),
+                max_connections: options.get_max_connections(),
+                sqlx_logging: options.get_sqlx_logging(),
+            };
+        };
+
+        let (rest, query) = match rest.split_once('?') {
+            Some((rest, query)) => (rest, Some(redact_query(query))),
+            None => (rest, None),
+        };
+        let (authority, path) = rest.split_at(rest.find('/').unwrap_or(rest.len()));
+        let (userinfo, host_port) = match authority.rsplit_once('@') {
+            Some((userinfo, host_port)) => (Some(userinfo), host_port),
+            None => (None, authority),
+        };
+
+        let mut redacted = format!("{scheme}://");
+        if let Some(userinfo) = userinfo {
+            match userinfo.split_once(':') {
+                Some((user, _)) => redacted.push_str(&format!("{user}:***@")),
+                None => redacted.push_str(&format!("{userinfo}@")),
+            }
+        }
+        redacted.push_str(host_port);
+        redacted.push_str(path);
+        if let Some(query) = query {
+            redacted.push('?');
+            redacted.push_str(&query);
+        }
+
+        let (host, database) = if backend == Some(DbBackend::Sqlite) {
+            // The "authority" is really the start of the file's path
+            (None, Some(rest.to_string()))
+        } else {
+            (
+                host(host_port),
+                Some(path.trim_start_matches('/').to_string()),
+            )
+        };
+
+        Self {
+            backend,
+            url: redacted,
+            host,
+            database: database.filter(|database| !database.is_empty()),
+            max_connections: options.get_max_connections(),
+            sqlx_logging: options.get_sqlx_logging(),
+        }
+    }
+}
+
+/// The host in `host_port`, without the port.
+fn host(host_port: &str) -> Option<String> {
+    let host = match host_port.strip_prefix('[') {
+        // An IPv6 address, which contains colons
+        Some(address) => address.split(']').next().unwrap_or(address),
+        None => host_port.split(':').next().unwrap_or(host_port),
+    };
+    (!host.is_empty()).then(|| host.to_string())
+}
+
+/// Redact the values of query parameters that look like passwords (e.g. `password` or `pass`).
+fn redact_query(query: &str) -> String {
+    query
+        .split('&')
+        .map(|param| match param.split_once('=') {
+            Some((name, _)) if name.to_ascii_lowercase().contains("pass") => format!("{name}=***"),
+            _ => param.to_string(),
+        })
+        .collect::<Vec<_>>()
+        .join("&")
+}
+
+#[async_trait]
+impl<S: Sync> FromRequestParts<S> for TxMeta {
+    type Rejection = Error;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        let meta = parts.extensions.get::<Arc<TxMeta>>();
+        meta.map(|meta| TxMeta::clone(meta))
+            .ok_or(Error::MissingExtension)
+    }
+}
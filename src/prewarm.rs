@@ -0,0 +1,48 @@
+//! A queue of transactions begun ahead of the requests that will use them.
+
+use std::{collections::VecDeque, sync::Arc};
+
+use parking_lot::Mutex;
+use sea_orm::{DatabaseTransaction, TransactionTrait};
+
+/// Transactions begun ahead of time, shared by every request handled by a [`Layer`].
+///
+/// [`Layer`]: crate::Layer
+#[derive(Clone)]
+pub(crate) struct Prewarmed {
+    queue: Arc<Mutex<VecDeque<DatabaseTransaction>>>,
+    size: usize,
+}
+
+impl Prewarmed {
+    /// Create an empty queue that will hold up to `size` transactions once refilled.
+    pub(crate) fn new(size: usize) -> Self {
+        Self {
+            queue: Arc::new(Mutex::new(VecDeque::with_capacity(size))),
+            size,
+        }
+    }
+
+    /// Take a transaction from the queue, if there are any.
+    pub(crate) fn take(&self) -> Option<DatabaseTransaction> {
+        self.queue.lock().pop_front()
+    }
+
+    /// Begin transactions until the queue is full.
+    ///
+    /// Errors are not returned, since a request that finds the queue empty will simply begin its
+    /// own transaction.
+    pub(crate) async fn refill<C: TransactionTrait>(&self, pool: &C) {
+        while self.queue.lock().len() < self.size {
+            match pool.begin().await {
+                Ok(tx) => self.queue.lock().push_back(tx),
+                #[allow(unused_variables)]
+                Err(error) => {
+                    #[cfg(feature = "tracing")]
+                    tracing::warn!(%error, "failed to begin prewarmed transaction");
+                    break;
+                }
+            }
+        }
+    }
+}
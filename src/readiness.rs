@@ -0,0 +1,46 @@
+//! Reporting whether the pool can take more requests, for backpressure.
+
+use std::task::{Context, Poll};
+
+/// A check of whether a transaction could be begun without waiting for a connection.
+///
+/// SeaORM doesn't expose the state of its pool, so the [`Service`](crate::Service) middleware
+/// can't tell whether beginning a transaction will have to wait. A probe configured with
+/// [`Layer::readiness_probe`](crate::Layer::readiness_probe) is consulted by the service's
+/// `poll_ready`, so that it's only ready when the probe is. Combined with load shedding (e.g.
+/// [`tower::load_shed`]), this rejects requests straight away when the database is at capacity,
+/// rather than letting them queue up in `BEGIN`.
+///
+/// For example, with a count of idle connections maintained by the application:
+///
+/// ```
+/// use std::{
+///     sync::{atomic::{AtomicUsize, Ordering}, Arc},
+///     task::{Context, Poll},
+/// };
+///
+/// struct IdleConnections(Arc<AtomicUsize>);
+///
+/// impl axum_sea_orm_tx::ReadinessProbe for IdleConnections {
+///     fn poll_ready(&self, cx: &mut Context<'_>) -> Poll<()> {
+///         if self.0.load(Ordering::Relaxed) > 0 {
+///             Poll::Ready(())
+///         } else {
+///             // Check again soon (a real probe could register `cx`'s waker instead)
+///             cx.waker().wake_by_ref();
+///             Poll::Pending
+///         }
+///     }
+/// }
+/// ```
+///
+/// [`tower::load_shed`]: https://docs.rs/tower/latest/tower/load_shed/index.html
+pub trait ReadinessProbe: Send + Sync + 'static {
+    /// Check whether a transaction could be begun now.
+    ///
+    /// Like [`Future::poll`](std::future::Future::poll), returning `Poll::Pending` must arrange
+    /// for `cx`'s waker to be woken once the probe should be checked again, or the service may
+    /// never become ready (unless it's only polled by load shedding middleware, which doesn't
+    /// wait).
+    fn poll_ready(&self, cx: &mut Context<'_>) -> Poll<()>;
+}
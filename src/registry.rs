@@ -0,0 +1,144 @@
+//! A registry of open transactions, for aborting them from outside their request.
+
+use std::{
+    collections::HashMap,
+    sync::{Arc, Weak},
+};
+
+use parking_lot::Mutex;
+use sea_orm::{ConnectionTrait, DbBackend, DbErr, Statement};
+
+use crate::tx::TxState;
+
+/// A registry of the open request transactions, by request ID, so they can be aborted from
+/// elsewhere in the application (e.g. an admin endpoint).
+///
+/// Give a registry to the [`Layer`](crate::Layer) with [`Layer::registry`](crate::Layer::registry),
+/// and keep a clone of it to abort transactions with. Transactions are registered when they're
+/// begun, under the request's `x-request-id` header (requests without one aren't registered), and
+/// leave the registry once they've been resolved:
+///
+/// ```
+/// use axum::{extract::Path, Extension};
+/// use axum_sea_orm_tx::{Layer, TxRegistry};
+///
+/// async fn abort(Extension(registry): Extension<TxRegistry>, Path(id): Path<String>) -> String {
+///     format!("aborted: {}", registry.abort(&id))
+/// }
+///
+/// # fn foo(pool: sea_orm::DatabaseConnection) {
+/// let registry = TxRegistry::new();
+/// let app = axum::Router::new()
+///     .route("/admin/abort/:id", axum::routing::post(abort))
+///     // .route(...)s
+///     .layer(Layer::new(pool).registry(registry.clone()))
+///     .layer(Extension(registry));
+/// # let _: axum::Router = app;
+/// # }
+/// ```
+///
+/// Aborting is best-effort. An aborted transaction is poisoned: the next statement its handler
+/// runs through [`Tx`](crate::Tx) fails (as does [`Tx::commit`](crate::Tx::commit)), and the
+/// [`Service`](crate::Service) middleware rolls it back (with [`RollbackReason::Aborted`]) whatever
+/// the response. A statement that's already running isn't interrupted, except on Postgres with
+/// [`abort_and_cancel`](Self::abort_and_cancel). Statements run directly on the
+/// `DatabaseTransaction` (through `Deref` etc.) aren't stopped either, but are still rolled back.
+///
+/// [`RollbackReason::Aborted`]: crate::RollbackReason::Aborted
+#[derive(Clone, Default)]
+pub struct TxRegistry(Arc<Mutex<HashMap<String, Registered>>>);
+
+/// A transaction in a [`TxRegistry`].
+struct Registered {
+    state: Weak<TxState>,
+
+    /// The Postgres backend running the transaction, to cancel its statements.
+    backend_pid: Option<i32>,
+}
+
+impl TxRegistry {
+    /// Create an empty registry.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The IDs of the requests with open transactions.
+    pub fn request_ids(&self) -> Vec<String> {
+        let registered = self.0.lock();
+        registered
+            .iter()
+            .filter(|(_, registered)| registered.state.strong_count() > 0)
+            .map(|(id, _)| id.clone())
+            .collect()
+    }
+
+    /// Abort the transaction of the request with `request_id`, returning whether there was one.
+    ///
+    /// See the [type-level documentation](Self) for what aborting does.
+    pub fn abort(&self, request_id: &str) -> bool {
+        self.take(request_id).is_some()
+    }
+
+    /// Abort the transaction of the request with `request_id` (see [`abort`](Self::abort)), and
+    /// cancel the statement it's running, if any, returning whether there was a transaction.
+    ///
+    /// Cancelling is only supported on Postgres (with `pg_cancel_backend`), and is run on `conn`,
+    /// which must be a connection to the same database (e.g. the pool). On other backends this is
+    /// the same as `abort`.
+    pub async fn abort_and_cancel<C: ConnectionTrait>(
+        &self,
+        request_id: &str,
+        conn: &C,
+    ) -> Result<bool, DbErr> {
+        let Some(backend_pid) = self.take(request_id) else {
+            return Ok(false);
+        };
+        if let (Some(pid), DbBackend::Postgres) = (backend_pid, conn.get_database_backend()) {
+            conn.execute(Statement::from_sql_and_values(
+                DbBackend::Postgres,
+                "SELECT pg_cancel_backend($1)",
+                [pid.into()],
+            ))
+            .await?;
+        }
+        Ok(true)
+    }
+
+    /// Register the transaction with `state` under `request_id`.
+    pub(crate) fn register(
+        &self,
+        request_id: &str,
+        state: &Arc<TxState>,
+        backend_pid: Option<i32>,
+    ) {
+        let mut registered = self.0.lock();
+        // Resolved transactions are only noticed here, rather than removing them as they resolve
+        registered.retain(|_, registered| registered.state.strong_count() > 0);
+        registered.insert(
+            request_id.to_string(),
+            Registered {
+                state: Arc::downgrade(state),
+                backend_pid,
+            },
+        );
+    }
+
+    /// Remove and abort the open transaction of the request with `request_id`, returning its
+    /// backend's PID (if known).
+    fn take(&self, request_id: &str) -> Option<Option<i32>> {
+        let registered = self.0.lock().remove(request_id)?;
+        let state = registered.state.upgrade()?;
+        state.abort();
+        #[cfg(feature = "tracing")]
+        tracing::warn!(request_id, "request transaction aborted");
+        Some(registered.backend_pid)
+    }
+}
+
+impl std::fmt::Debug for TxRegistry {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_tuple("TxRegistry")
+            .field(&self.request_ids())
+            .finish()
+    }
+}
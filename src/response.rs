@@ -0,0 +1,369 @@
+//! Responses that explicitly decide how the request's transaction is resolved.
+
+use std::{convert::Infallible, future::Future, time::Duration};
+
+use axum_core::response::{IntoResponse, IntoResponseParts, Response, ResponseParts};
+use futures_core::future::BoxFuture;
+use parking_lot::Mutex;
+use sea_orm::TransactionTrait;
+
+use crate::Tx;
+
+/// Commit the request's transaction, regardless of the response status.
+///
+/// Normally the transaction is committed only for successful (HTTP `2XX`) responses. Wrapping a
+/// response in `Commit` forces the transaction to be committed whatever the status:
+///
+/// ```
+/// use axum_sea_orm_tx::{Commit, Tx};
+/// use http::StatusCode;
+///
+/// async fn handler(tx: Tx<sea_orm::DatabaseConnection>) -> Commit<StatusCode> {
+///     /* record the failed attempt */
+///     Commit(StatusCode::UNPROCESSABLE_ENTITY)
+/// }
+/// ```
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Commit<T>(pub T);
+
+/// Roll back the request's transaction, regardless of the response status.
+///
+/// Normally the transaction is committed for successful (HTTP `2XX`) responses. Wrapping a
+/// response in `Rollback` forces the transaction to be rolled back whatever the status:
+///
+/// ```
+/// use axum_sea_orm_tx::{Rollback, Tx};
+/// use http::StatusCode;
+///
+/// async fn handler(tx: Tx<sea_orm::DatabaseConnection>) -> Rollback<StatusCode> {
+///     /* validate some writes, without keeping them */
+///     Rollback(StatusCode::OK)
+/// }
+/// ```
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Rollback<T>(pub T);
+
+/// A response extension that commits the request's transaction, regardless of the response
+/// status.
+///
+/// This does the same as wrapping the response in [`Commit`], for code that has a response to
+/// mark rather than one to wrap, e.g. middleware between the handler and the
+/// [`Layer`](crate::Layer). It can be inserted into the response's extensions, or returned as
+/// part of a response:
+///
+/// ```
+/// use axum::response::IntoResponse;
+/// use axum_sea_orm_tx::{ForceCommit, Tx};
+/// use http::StatusCode;
+///
+/// async fn handler(tx: Tx<sea_orm::DatabaseConnection>) -> impl IntoResponse {
+///     /* record the failed attempt */
+///     let mut res = StatusCode::UNPROCESSABLE_ENTITY.into_response();
+///     res.extensions_mut().insert(ForceCommit);
+///     res
+/// }
+///
+/// async fn other_handler(tx: Tx<sea_orm::DatabaseConnection>) -> impl IntoResponse {
+///     (StatusCode::CONFLICT, ForceCommit, "conflict")
+/// }
+/// ```
+///
+/// If the response has both `ForceCommit` and [`ForceRollback`], the transaction is rolled back.
+/// Wrapping the response in [`Commit`] or [`Rollback`] takes precedence over either.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ForceCommit;
+
+/// A response extension that rolls back the request's transaction, regardless of the response
+/// status.
+///
+/// This does the same as wrapping the response in [`Rollback`], see [`ForceCommit`]:
+///
+/// ```
+/// use axum::response::IntoResponse;
+/// use axum_sea_orm_tx::{ForceRollback, Tx};
+///
+/// async fn handler(tx: Tx<sea_orm::DatabaseConnection>) -> impl IntoResponse {
+///     /* validate some writes, without keeping them */
+///     (ForceRollback, "valid")
+/// }
+/// ```
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ForceRollback;
+
+/// Adapt a handler returning a `Result` so the request's transaction is committed if it returns
+/// `Ok`, and rolled back if it returns `Err`.
+///
+/// Both variants are turned into responses as usual, but the transaction's outcome is decided by
+/// the variant rather than the response status, as if the handler returned [`Commit`] or
+/// [`Rollback`]. So an `Ok` response that happens to be a `4XX` is still committed:
+///
+/// ```
+/// use axum::routing::post;
+/// use axum_sea_orm_tx::{tx_handler, Tx};
+/// use http::StatusCode;
+///
+/// # fn foo() -> axum::Router {
+/// axum::Router::new().route(
+///     "/attempts",
+///     post(tx_handler(|tx: Tx<sea_orm::DatabaseConnection>| async move {
+///         /* record the attempt */
+///         Ok::<_, StatusCode>(StatusCode::TOO_MANY_REQUESTS)
+///     })),
+/// )
+/// # }
+/// ```
+///
+/// The adapted handler only extracts the [`Tx`]. Resolving the transaction in the handler (e.g.
+/// with [`Tx::commit`]) takes precedence, as usual.
+pub fn tx_handler<C, E, I, F, Fut, T, Er>(
+    handler: F,
+) -> impl FnOnce(Tx<C, E, I>) -> BoxFuture<'static, Response> + Clone + Send + 'static
+where
+    C: TransactionTrait,
+    F: FnOnce(Tx<C, E, I>) -> Fut + Clone + Send + 'static,
+    Fut: Future<Output = Result<T, Er>> + Send + 'static,
+    T: IntoResponse,
+    Er: IntoResponse,
+{
+    move |tx| {
+        let result = handler(tx);
+        Box::pin(async move {
+            match result.await {
+                Ok(res) => Commit(res).into_response(),
+                Err(error) => Rollback(error).into_response(),
+            }
+        })
+    }
+}
+
+/// How the request's transaction was resolved.
+///
+/// The [`Service`](crate::Service) middleware inserts this into the response extensions once the
+/// transaction has been committed or rolled back, so outer middleware (and tests) can see what
+/// happened without inspecting the database:
+///
+/// ```
+/// use axum_sea_orm_tx::TxOutcome;
+///
+/// fn log_outcome(res: &axum::response::Response) {
+///     match res.extensions().get::<TxOutcome>() {
+///         Some(outcome) if outcome.committed => println!("committed"),
+///         Some(outcome) => println!("rolled back: {:?}", outcome.reason),
+///         None => println!("no transaction"),
+///     }
+/// }
+/// ```
+///
+/// It's not inserted if no transaction was begun, if the handler resolved the transaction itself
+/// (e.g. with [`Tx::commit`](crate::Tx::commit)), or if resolution is deferred until the response
+/// body has been sent (see [`Tx::stream_response`](crate::Tx::stream_response)). With
+/// [`Layer::two_phase`](crate::Layer::two_phase), a transaction that would be committed is
+/// prepared instead, and [`PreparedTransaction`] is inserted in place of a `TxOutcome`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct TxOutcome {
+    /// Whether the transaction was committed.
+    pub committed: bool,
+
+    /// Why the transaction was rolled back, if it was.
+    pub reason: Option<RollbackReason>,
+}
+
+impl TxOutcome {
+    pub(crate) fn committed() -> Self {
+        Self {
+            committed: true,
+            reason: None,
+        }
+    }
+
+    pub(crate) fn rolled_back(reason: RollbackReason) -> Self {
+        Self {
+            committed: false,
+            reason: Some(reason),
+        }
+    }
+}
+
+/// Why a request's transaction was rolled back.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum RollbackReason {
+    /// The response status was not successful (or not one of the
+    /// [`Layer::commit_status_range`](crate::Layer::commit_status_range)).
+    Status,
+
+    /// The handler requested a rollback with [`Rollback`].
+    Requested,
+
+    /// Committing the transaction failed.
+    CommitFailed,
+
+    /// Auto-commit is disabled and the handler didn't commit explicitly.
+    ///
+    /// See [`Layer::auto_commit`](crate::Layer::auto_commit).
+    NotCommitted,
+
+    /// The transaction would have been committed, but only ran reads.
+    ///
+    /// See [`Layer::rollback_readonly`](crate::Layer::rollback_readonly).
+    ReadOnly,
+
+    /// The inner service returned an error, which was converted into the response.
+    ///
+    /// See [`Layer::handle_inner_errors`](crate::Layer::handle_inner_errors).
+    InnerError,
+
+    /// The transaction was a read-only snapshot, which is never committed.
+    ///
+    /// See [`SnapshotTx`](crate::SnapshotTx).
+    Snapshot,
+
+    /// The layer is in dry-run mode, so never commits.
+    ///
+    /// See [`Layer::dry_run`](crate::Layer::dry_run).
+    DryRun,
+
+    /// The transaction was aborted from outside the request.
+    ///
+    /// See [`TxRegistry`](crate::TxRegistry).
+    Aborted,
+
+    /// A statement failed, aborting the transaction.
+    ///
+    /// See [`Tx::is_aborted`](crate::Tx::is_aborted).
+    StatementFailed,
+}
+
+/// A transaction that was prepared for two-phase commit, rather than committed.
+///
+/// Inserted into the response extensions by the [`Service`](crate::Service) middleware when
+/// [`Layer::two_phase`](crate::Layer::two_phase) is enabled. The transaction is no longer tied to
+/// a connection: it must be finished with `COMMIT PREPARED '<gid>'` or `ROLLBACK PREPARED '<gid>'`
+/// (on any connection to the same database).
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct PreparedTransaction {
+    /// The global transaction identifier the transaction was prepared with.
+    pub gid: String,
+}
+
+/// A transaction that stayed open longer than the configured threshold.
+///
+/// Inserted into the response extensions by the [`Service`](crate::Service) middleware when
+/// [`Layer::warn_if_open_longer_than`](crate::Layer::warn_if_open_longer_than) is set and exceeded.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct LongTransaction {
+    /// How long the transaction had been open when it was resolved.
+    pub elapsed: Duration,
+
+    /// The configured threshold.
+    pub threshold: Duration,
+}
+
+/// How many statements the request ran through [`Tx`](crate::Tx).
+///
+/// Inserted into the response extensions by the [`Service`](crate::Service) middleware if the
+/// request began a transaction, e.g. to log requests that run a suspicious number of queries. See
+/// also [`Layer::max_statements`](crate::Layer::max_statements).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct StatementCount {
+    /// The number of statements run.
+    pub count: usize,
+}
+
+/// A response rendered with the request's transaction, once the handler has returned.
+///
+/// See [`Tx::respond_with`](crate::Tx::respond_with) for more information.
+pub struct TxResponse(BoxFuture<'static, Response>);
+
+impl TxResponse {
+    pub(crate) fn new(render: BoxFuture<'static, Response>) -> Self {
+        Self(render)
+    }
+}
+
+impl std::fmt::Debug for TxResponse {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_tuple("TxResponse").finish_non_exhaustive()
+    }
+}
+
+impl IntoResponse for TxResponse {
+    fn into_response(self) -> Response {
+        // The real response is rendered by the `Service`, this is just a placeholder to carry it
+        let mut res = Response::default();
+        res.extensions_mut().insert(Deferred(Mutex::new(self.0)));
+        res
+    }
+}
+
+/// The rendering of a [`TxResponse`], stored in the response extensions.
+pub(crate) struct Deferred(Mutex<BoxFuture<'static, Response>>);
+
+impl Deferred {
+    /// Render the response, keeping the extensions of the placeholder `res` (e.g. the
+    /// [`Resolution`] requested by wrapping the `TxResponse` in [`Commit`]).
+    pub(crate) async fn render(self, mut res: Response) -> Response {
+        let mut rendered = self.0.into_inner().await;
+        let extensions = std::mem::take(res.extensions_mut());
+        rendered.extensions_mut().extend(extensions);
+        rendered
+    }
+}
+
+/// The resolution requested by [`Commit`] or [`Rollback`], stored in the response extensions.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Resolution {
+    Commit,
+    Rollback,
+    InnerError,
+}
+
+impl Resolution {
+    /// Take the resolution requested by the response with `extensions`, if any.
+    pub(crate) fn take(extensions: &mut http::Extensions) -> Option<Self> {
+        let resolution = extensions.remove::<Self>();
+        let commit = extensions.remove::<ForceCommit>();
+        let rollback = extensions.remove::<ForceRollback>();
+        resolution
+            .or(rollback.map(|_| Self::Rollback))
+            .or(commit.map(|_| Self::Commit))
+    }
+}
+
+impl IntoResponseParts for ForceCommit {
+    type Error = Infallible;
+
+    fn into_response_parts(self, mut res: ResponseParts) -> Result<ResponseParts, Self::Error> {
+        res.extensions_mut().insert(self);
+        Ok(res)
+    }
+}
+
+impl IntoResponseParts for ForceRollback {
+    type Error = Infallible;
+
+    fn into_response_parts(self, mut res: ResponseParts) -> Result<ResponseParts, Self::Error> {
+        res.extensions_mut().insert(self);
+        Ok(res)
+    }
+}
+
+impl<T: IntoResponse> IntoResponse for Commit<T> {
+    fn into_response(self) -> Response {
+        let mut res = self.0.into_response();
+        res.extensions_mut().insert(Resolution::Commit);
+        res
+    }
+}
+
+impl<T: IntoResponse> IntoResponse for Rollback<T> {
+    fn into_response(self) -> Response {
+        let mut res = self.0.into_response();
+        res.extensions_mut().insert(Resolution::Rollback);
+        res
+    }
+}
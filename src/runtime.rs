@@ -0,0 +1,70 @@
+//! The async runtime used for timers.
+
+use std::{future::Future, time::Duration};
+
+use futures_core::future::BoxFuture;
+use futures_util::future::{self, Either};
+
+/// An async runtime, providing the timers used by options like
+/// [`Layer::commit_deadline`](crate::Layer::commit_deadline).
+///
+/// Implementations for Tokio ([`Tokio`], with the `runtime-tokio` feature, enabled by default) and
+/// async-std (`AsyncStd`, with the `runtime-async-std` feature) are provided. The runtime is
+/// chosen with [`Layer::runtime`](crate::Layer::runtime), and defaults to Tokio if its feature is
+/// enabled, or async-std otherwise.
+pub trait Runtime {
+    /// Wait until `duration` has passed.
+    fn sleep(duration: Duration) -> BoxFuture<'static, ()>;
+}
+
+/// The Tokio runtime.
+#[cfg(feature = "runtime-tokio")]
+#[derive(Debug, Clone, Copy)]
+pub struct Tokio;
+
+#[cfg(feature = "runtime-tokio")]
+impl Runtime for Tokio {
+    fn sleep(duration: Duration) -> BoxFuture<'static, ()> {
+        Box::pin(tokio::time::sleep(duration))
+    }
+}
+
+/// The async-std runtime.
+#[cfg(feature = "runtime-async-std")]
+#[derive(Debug, Clone, Copy)]
+pub struct AsyncStd;
+
+#[cfg(feature = "runtime-async-std")]
+impl Runtime for AsyncStd {
+    fn sleep(duration: Duration) -> BoxFuture<'static, ()> {
+        Box::pin(async_std::task::sleep(duration))
+    }
+}
+
+/// A runtime's [`Runtime::sleep`].
+pub(crate) type Sleep = fn(Duration) -> BoxFuture<'static, ()>;
+
+/// The `sleep` of the default runtime, if a runtime feature is enabled.
+pub(crate) fn default_sleep() -> Option<Sleep> {
+    #[cfg(feature = "runtime-tokio")]
+    return Some(Tokio::sleep);
+
+    #[cfg(all(feature = "runtime-async-std", not(feature = "runtime-tokio")))]
+    return Some(AsyncStd::sleep);
+
+    #[cfg(not(any(feature = "runtime-tokio", feature = "runtime-async-std")))]
+    return None;
+}
+
+/// Run `future`, giving up with `None` if it's not done once `duration` has passed.
+pub(crate) async fn timeout<F: Future>(
+    sleep: Sleep,
+    duration: Duration,
+    future: F,
+) -> Option<F::Output> {
+    futures_util::pin_mut!(future);
+    match future::select(future, sleep(duration)).await {
+        Either::Left((output, _)) => Some(output),
+        Either::Right(((), _)) => None,
+    }
+}
@@ -48,6 +48,14 @@ impl<T> Slot<T> {
         }
     }
 
+    /// Check whether the value is currently leased.
+    ///
+    /// This is `false` if the value is in the slot, or if it was stolen.
+    pub(crate) fn is_leased(&self) -> bool {
+        // `Lease`s hold a weak reference to the slot until they're dropped or stolen.
+        Arc::weak_count(&self.0) > 0
+    }
+
     /// Get the inner value from the slot, if any.
     ///
     /// Note that if this returns `Some`, there are no oustanding leases. If it returns `None` then
@@ -190,6 +198,7 @@ mod tests {
         .unwrap();
 
         // The value is now back in the slot
+        assert!(!slot.is_leased());
         assert_eq!(
             slot.lease().as_deref().map(|s| s.as_str()),
             Some("Hello, world!")
@@ -204,6 +213,7 @@ mod tests {
         let mut slot = Slot::new("Hello".to_string());
 
         let lease = slot.lease().unwrap();
+        assert!(slot.is_leased());
         std::thread::spawn(move || {
             // We can steal ownership of the resource, leaving the slot permanently empty
             let _: String = lease.steal();
@@ -212,6 +222,7 @@ mod tests {
         .unwrap();
 
         // The slot is now permanently empty
+        assert!(!slot.is_leased());
         assert!(slot.lease().is_none());
     }
 }
@@ -0,0 +1,100 @@
+//! A [`Stream`] of query results that owns the request's transaction.
+
+use std::{
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+use futures_channel::mpsc;
+use futures_core::{future::BoxFuture, Stream};
+use futures_util::{SinkExt, StreamExt};
+use sea_orm::{DbErr, FromQueryResult, Statement, StreamTrait, TransactionTrait};
+
+use crate::Tx;
+
+/// A stream of rows from a query run on the request's transaction.
+///
+/// See [`Tx::stream_response`](crate::Tx::stream_response) for more information.
+pub struct TxStream<T> {
+    producer: Option<BoxFuture<'static, Result<(), DbErr>>>,
+    rows: mpsc::Receiver<Result<T, DbErr>>,
+}
+
+impl<T: FromQueryResult + Send + 'static> TxStream<T> {
+    pub(crate) fn new<C, E, I>(tx: Tx<C, E, I>, stmt: Statement) -> Self
+    where
+        C: TransactionTrait + Send + Sync + 'static,
+        E: Send + Sync + 'static,
+        I: Send + Sync + 'static,
+    {
+        // A zero-capacity channel means at most one row is buffered ahead of the consumer, so the
+        // database cursor is only advanced as fast as rows are taken from the stream.
+        let (mut sender, rows) = mpsc::channel(0);
+
+        let producer = Box::pin(async move {
+            let mut stream = tx.stream(stmt).await?;
+            while let Some(row) = stream.next().await {
+                let row = row.and_then(|row| T::from_query_result(&row, ""));
+                if sender.send(row).await.is_err() {
+                    // The consumer went away, there's no point continuing.
+                    break;
+                }
+            }
+            Ok(())
+        });
+
+        Self {
+            producer: Some(producer),
+            rows,
+        }
+    }
+}
+
+impl<T> Stream for TxStream<T> {
+    type Item = Result<T, DbErr>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        loop {
+            if let Poll::Ready(Some(row)) = self.rows.poll_next_unpin(cx) {
+                return Poll::Ready(Some(row));
+            }
+
+            let Some(producer) = self.producer.as_mut() else {
+                return self.rows.poll_next_unpin(cx);
+            };
+
+            match producer.as_mut().poll(cx) {
+                Poll::Ready(result) => {
+                    // Dropping the producer releases the transaction back to the request's slot.
+                    self.producer = None;
+                    if let Err(error) = result {
+                        return Poll::Ready(Some(Err(error)));
+                    }
+                }
+                Poll::Pending => return Poll::Pending,
+            }
+        }
+    }
+}
+
+impl<T> std::fmt::Debug for TxStream<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("TxStream")
+            .field("finished", &self.producer.is_none())
+            .finish_non_exhaustive()
+    }
+}
+
+/// The rows a query returned before its deadline.
+///
+/// See [`SnapshotTx::stream_best_effort`](crate::SnapshotTx::stream_best_effort) for more
+/// information.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct PartialRows<T> {
+    /// The rows fetched, in the order the query returned them.
+    pub rows: Vec<T>,
+
+    /// Whether the deadline passed before all the rows were fetched.
+    pub truncated: bool,
+}
@@ -0,0 +1,162 @@
+//! A harness for testing handlers that use [`Tx`](crate::Tx) against any supported database.
+
+use std::convert::Infallible;
+
+use bytes::{Bytes, BytesMut};
+use futures_util::future::poll_fn;
+use http_body::Body;
+use sea_orm::{ConnectionTrait, Database, DatabaseConnection, DbBackend, DbErr, Statement, Value};
+use tower_service::Service;
+
+use crate::Layer;
+
+/// A database with a known schema, and helpers to run handlers against it.
+///
+/// This lets the same assertions be run against each backend the application supports, e.g. with
+/// the URLs of a SQLite, Postgres and MySQL database:
+///
+/// ```
+/// use axum_sea_orm_tx::{Tx, TxFixture};
+/// use sea_orm::DatabaseConnection;
+///
+/// async fn check(url: &str) {
+///     let fixture = TxFixture::connect(url).await.unwrap();
+///
+///     let response = fixture
+///         .run(|tx: Tx<DatabaseConnection>| async move {
+///             TxFixture::insert_user(&tx, 1, "huge hackerman").await.unwrap();
+///         })
+///         .await;
+///
+///     assert!(response.status().is_success());
+///     assert_eq!(
+///         fixture.users().await.unwrap(),
+///         vec![(1, "huge hackerman".to_string())]
+///     );
+/// }
+/// ```
+///
+/// The schema is a single `tx_fixture_users (id INT PRIMARY KEY, name TEXT)` table, which is
+/// dropped and recreated by [`connect`](Self::connect), so point it at a database used only for
+/// tests. Handlers are run one request at a time, so a single-connection pool (e.g. SQLite in
+/// memory) works as long as handlers don't use the pool directly while their transaction is open.
+///
+/// Requires the `testing` feature.
+#[derive(Debug, Clone)]
+pub struct TxFixture {
+    pool: DatabaseConnection,
+}
+
+impl TxFixture {
+    /// Connect to the database at `url`, and (re)create the fixture's schema.
+    pub async fn connect(url: &str) -> Result<Self, DbErr> {
+        let pool = Database::connect(url).await?;
+        pool.execute_unprepared("DROP TABLE IF EXISTS tx_fixture_users")
+            .await?;
+        pool.execute_unprepared("CREATE TABLE tx_fixture_users (id INT PRIMARY KEY, name TEXT)")
+            .await?;
+        Ok(Self { pool })
+    }
+
+    /// The fixture's connection pool.
+    pub fn pool(&self) -> &DatabaseConnection {
+        &self.pool
+    }
+
+    /// A [`Layer`] for the fixture's pool, e.g. to configure for
+    /// [`run_with_layer`](Self::run_with_layer).
+    pub fn layer(&self) -> Layer<DatabaseConnection> {
+        Layer::new(self.pool.clone())
+    }
+
+    /// Run `handler` for a `GET /` request, behind the default [`Layer`].
+    ///
+    /// The response body is collected, so the transaction has been resolved (even for streaming
+    /// responses) by the time this returns.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the response body fails.
+    pub async fn run<H, T>(&self, handler: H) -> http::Response<Bytes>
+    where
+        H: axum::handler::Handler<T, (), axum::body::Body>,
+        T: 'static,
+    {
+        self.run_with_layer(self.layer(), handler).await
+    }
+
+    /// Run `handler` for a `GET /` request, behind `layer`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the response body fails.
+    pub async fn run_with_layer<H, T>(
+        &self,
+        layer: Layer<DatabaseConnection>,
+        handler: H,
+    ) -> http::Response<Bytes>
+    where
+        H: axum::handler::Handler<T, (), axum::body::Body>,
+        T: 'static,
+    {
+        let mut app = axum::Router::new()
+            .route("/", axum::routing::get(handler))
+            .layer(layer);
+
+        let req = http::Request::builder()
+            .uri("/")
+            .body(axum::body::Body::empty())
+            .expect("BUG: invalid request");
+        let ready: Result<(), Infallible> =
+            poll_fn(|cx| Service::<http::Request<_>>::poll_ready(&mut app, cx)).await;
+        let res = match ready {
+            Ok(()) => app.call(req).await,
+            Err(error) => match error {},
+        };
+        let res = match res {
+            Ok(res) => res,
+            Err(error) => match error {},
+        };
+
+        let (parts, mut body) = res.into_parts();
+        let mut bytes = BytesMut::new();
+        while let Some(chunk) = body.data().await {
+            bytes.extend_from_slice(&chunk.expect("response body failed"));
+        }
+        http::Response::from_parts(parts, bytes.freeze())
+    }
+
+    /// Insert a user into the fixture's table on `conn`, e.g. a handler's [`Tx`](crate::Tx).
+    pub async fn insert_user(
+        conn: &impl ConnectionTrait,
+        id: i32,
+        name: &str,
+    ) -> Result<(), DbErr> {
+        let backend = conn.get_database_backend();
+        let sql = match backend {
+            DbBackend::Postgres => "INSERT INTO tx_fixture_users VALUES ($1, $2)",
+            DbBackend::MySql | DbBackend::Sqlite => "INSERT INTO tx_fixture_users VALUES (?, ?)",
+        };
+        let values = vec![
+            Value::Int(Some(id)),
+            Value::String(Some(Box::new(name.to_string()))),
+        ];
+        conn.execute(Statement::from_sql_and_values(backend, sql, values))
+            .await?;
+        Ok(())
+    }
+
+    /// The users committed to the fixture's table, ordered by ID.
+    pub async fn users(&self) -> Result<Vec<(i32, String)>, DbErr> {
+        let rows = self
+            .pool
+            .query_all(Statement::from_string(
+                self.pool.get_database_backend(),
+                "SELECT id, name FROM tx_fixture_users ORDER BY id".to_string(),
+            ))
+            .await?;
+        rows.into_iter()
+            .map(|row| Ok((row.try_get("", "id")?, row.try_get("", "name")?)))
+            .collect()
+    }
+}
@@ -1,18 +1,52 @@
 //! A request extension that enables the [`Tx`](crate::Tx) extractor.
 
-use std::marker::PhantomData;
+use std::{
+    collections::HashMap,
+    marker::PhantomData,
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
 
 use async_trait::async_trait;
-use axum::extract::FromRequestParts;
+use axum::{
+    extract::{rejection::JsonRejection, FromRequest, FromRequestParts, MatchedPath},
+    Json,
+};
 use axum_core::response::IntoResponse;
+use futures_core::future::BoxFuture;
+use futures_core::Future;
+use futures_util::{
+    future::{self, Either},
+    StreamExt,
+};
 use http::request::Parts;
+use parking_lot::Mutex;
 use sea_orm::{
-    ConnectionTrait, DatabaseConnection, DatabaseTransaction, DbErr, StreamTrait, TransactionTrait,
+    AccessMode, ConnectionTrait, DatabaseConnection, DatabaseTransaction, DbBackend, DbErr,
+    ExecResult, FromQueryResult, IsolationLevel, QueryResult, Statement, StreamTrait,
+    TransactionTrait,
 };
 
+#[cfg(feature = "audit")]
+use crate::audit::AuditLog;
 use crate::{
+    affinity::{Affinity, AffinityKey},
+    connection::{
+        autocommit_unsupported, closed, is_locked, transactions_unsupported, AutocommitConnection,
+        Connection,
+    },
+    current::Scoped,
+    isolation::{DefaultIsolation, IsolationMarker, Snapshot},
+    layer::Config,
+    meta::TxMeta,
+    prewarm::Prewarmed,
+    registry::TxRegistry,
+    runtime::{self, Sleep},
     slot::{Lease, Slot},
-    Error,
+    BackoffPolicy, Error, PartialRows, TxConfig, TxResponse, TxStream,
 };
 
 /// An `axum` extractor for a database transaction.
@@ -31,6 +65,9 @@ use crate::{
 /// }
 /// ```
 ///
+/// This also means `&Tx` can be passed anywhere SeaORM expects a connection, such as
+/// `ActiveModelTrait::save(&tx)` or `EntityTrait::find().all(&tx)`.
+///
 /// It also implements `Deref<Target = `[`sea_orm::DatabaseTransaction`]`>` and `DerefMut`, so you can call
 /// methods from `DatabaseTransaction` and its traits:
 ///
@@ -72,271 +109,3144 @@ use crate::{
 ///     /* ... */
 /// }
 /// ```
-#[derive(Debug)]
-pub struct Tx<C: TransactionTrait, E = Error>(Lease<DatabaseTransaction>, PhantomData<(C, E)>);
+///
+/// The `I` generic parameter chooses the isolation level and access mode the transaction is begun
+/// with, see [`IsolationMarker`].
+///
+/// The `Debug` representation shows the state of the transaction (e.g. its backend and how many
+/// statements have been run), but not the connection itself.
+pub struct Tx<C: TransactionTrait, E = Error, I = DefaultIsolation> {
+    tx: Lease<Connection>,
+    backend: DbBackend,
+    pool: C,
+    options: BeginOptions,
+    state: Arc<TxState>,
+    begun_at: Instant,
+    savepoint_depth: usize,
+    /// The savepoints created with [`Tx::savepoint_named`], with the depth they were created at.
+    savepoints: Vec<(String, usize)>,
+    _marker: PhantomData<(E, I)>,
+}
 
-impl<C: TransactionTrait, E> Tx<C, E> {
-    /// Explicitly commit the transaction.
-    ///
-    /// By default, the transaction will be committed when a successful response is returned
-    /// (specifically, when the [`Service`](crate::Service) middleware intercepts an HTTP `2XX`
-    /// response). This method allows the transaction to be committed explicitly.
-    ///
-    /// **Note:** trying to use the `Tx` extractor again after calling `commit` will currently
-    /// generate [`Error::OverlappingExtractors`] errors. This may change in future.
-    pub async fn commit(self) -> Result<(), DbErr> {
-        self.0.steal().commit().await
+impl<C: TransactionTrait, E, I> std::fmt::Debug for Tx<C, E, I> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Tx")
+            .field("backend", &self.backend)
+            .field("open", &matches!(*self.tx, Connection::Transaction(_)))
+            .field("statements", &self.state.statements)
+            .field("pinned", &self.state.pinned)
+            .field("savepoint_depth", &self.savepoint_depth)
+            .field(
+                "savepoints",
+                &self
+                    .savepoints
+                    .iter()
+                    .map(|(name, _)| name)
+                    .collect::<Vec<_>>(),
+            )
+            .field("options", &self.options)
+            .finish_non_exhaustive()
     }
 }
 
-impl<C: TransactionTrait, E> AsRef<DatabaseTransaction> for Tx<C, E> {
-    fn as_ref(&self) -> &DatabaseTransaction {
-        &self.0
+impl<C: TransactionTrait, E, I> Tx<C, E, I> {
+    fn new(
+        tx: Lease<Connection>,
+        pool: C,
+        options: BeginOptions,
+        state: Arc<TxState>,
+        begun_at: Instant,
+    ) -> Self {
+        let backend = tx.get_database_backend();
+        Self {
+            tx,
+            backend,
+            pool,
+            options,
+            state,
+            begun_at,
+            savepoint_depth: 0,
+            savepoints: Vec::new(),
+            _marker: PhantomData,
+        }
     }
-}
 
-impl<C: TransactionTrait, E> AsMut<DatabaseTransaction> for Tx<C, E> {
-    fn as_mut(&mut self) -> &mut DatabaseTransaction {
-        &mut self.0
+    /// Apply the layer's statement rewriter (if any) to `stmt`, and record it in the audit log.
+    fn prepare(&self, mut stmt: Statement) -> Statement {
+        if let Some(rewriter) = &self.options.rewriter {
+            (rewriter.0)(&mut stmt);
+        }
+        #[cfg(feature = "audit")]
+        if let Some(audit) = &self.options.audit {
+            audit.record(&stmt);
+        }
+        self.note_statement(&stmt.sql);
+        stmt
     }
-}
 
-impl<C: TransactionTrait, E> std::ops::Deref for Tx<C, E> {
-    type Target = DatabaseTransaction;
+    /// Note that `sql` is about to run, for [`Layer::rollback_readonly`].
+    ///
+    /// Anything other than a plain `SELECT` is assumed to write (e.g. SeaORM runs inserts on
+    /// Postgres as queries with `RETURNING`).
+    ///
+    /// [`Layer::rollback_readonly`]: crate::Layer::rollback_readonly
+    fn note_statement(&self, sql: &str) {
+        let select = sql
+            .trim_start()
+            .get(..6)
+            .is_some_and(|keyword| keyword.eq_ignore_ascii_case("select"));
+        if !select {
+            self.note_write();
+        }
+    }
 
-    fn deref(&self) -> &Self::Target {
-        &self.0
+    /// Note that the transaction may have been written to.
+    fn note_write(&self) {
+        self.state.written.store(true, Ordering::Relaxed);
     }
-}
 
-impl<C: TransactionTrait, E> std::ops::DerefMut for Tx<C, E> {
-    fn deref_mut(&mut self) -> &mut Self::Target {
-        &mut self.0
+    /// Whether the connection has been pinned by [`commit_and_pin`](Self::commit_and_pin).
+    fn is_pinned(&self) -> bool {
+        self.state.pinned.load(Ordering::Relaxed)
     }
-}
 
-impl<C: TransactionTrait + Sync, E: Sync> ConnectionTrait for Tx<C, E> {
-    fn get_database_backend(&self) -> sea_orm::DbBackend {
-        self.0.get_database_backend()
+    /// Take the connection to resolve it, leaving the `Tx` closed until a new one is put in its
+    /// place (so it's still usable, if only to fail with [`Error::Closed`], should that fail).
+    fn close(&mut self) -> Connection {
+        std::mem::replace(&mut *self.tx, Connection::Closed(self.backend))
     }
 
-    fn execute<'life0, 'async_trait>(
-        &'life0 self,
-        stmt: sea_orm::Statement,
-    ) -> core::pin::Pin<
-        Box<
-            dyn core::future::Future<Output = Result<sea_orm::ExecResult, DbErr>>
-                + core::marker::Send
-                + 'async_trait,
-        >,
-    >
-    where
-        'life0: 'async_trait,
-        Self: 'async_trait,
-    {
-        self.0.execute(stmt)
+    /// Finish running a statement through the [`ConnectionTrait`] methods, which produced `result`
+    /// after starting at `started`.
+    async fn finish<T>(
+        &self,
+        result: Result<T, DbErr>,
+        explainable: Option<Statement>,
+        started: Instant,
+    ) -> Result<T, DbErr> {
+        self.explain_if_slow(explainable, started).await;
+        self.note_result(&result, self.savepoint_depth);
+        self.commit_pinned(result).await
     }
 
-    fn query_one<'life0, 'async_trait>(
-        &'life0 self,
-        stmt: sea_orm::Statement,
-    ) -> core::pin::Pin<
-        Box<
-            dyn core::future::Future<Output = Result<Option<sea_orm::QueryResult>, DbErr>>
-                + core::marker::Send
-                + 'async_trait,
-        >,
-    >
-    where
-        'life0: 'async_trait,
-        Self: 'async_trait,
-    {
-        self.0.query_one(stmt)
+    /// Note whether `result`'s statement, run `depth` savepoints deep, aborted the transaction (see
+    /// [`is_aborted`](Self::is_aborted)).
+    ///
+    /// A failure within a savepoint can be recovered from by rolling back to it, so only counts at
+    /// the top level, and pinned connections are committed (or rather, rolled back) straight away.
+    fn note_result<T>(&self, result: &Result<T, DbErr>, depth: usize) {
+        let Err(error) = result else {
+            return;
+        };
+        if depth == 0
+            && !self.is_pinned()
+            && matches!(*self.tx, Connection::Transaction(_))
+            && aborts_transaction(self.backend, error)
+        {
+            self.state.failed.store(true, Ordering::Relaxed);
+        }
     }
 
-    fn query_all<'life0, 'async_trait>(
-        &'life0 self,
-        stmt: sea_orm::Statement,
-    ) -> core::pin::Pin<
-        Box<
-            dyn core::future::Future<Output = Result<Vec<sea_orm::QueryResult>, DbErr>>
-                + core::marker::Send
-                + 'async_trait,
-        >,
-    >
-    where
-        'life0: 'async_trait,
-        Self: 'async_trait,
-    {
-        self.0.query_all(stmt)
+    /// Commit the statement that produced `result` if the connection is pinned, so that it behaves
+    /// as if in autocommit mode.
+    ///
+    /// A transaction is begun again straight away, so the connection is always in a transaction
+    /// as far as SeaORM is concerned, and can be committed or rolled back as usual (there's just
+    /// nothing in it).
+    async fn commit_pinned<T>(&self, result: Result<T, DbErr>) -> Result<T, DbErr> {
+        if self.is_pinned() {
+            self.tx.execute_unprepared("COMMIT").await?;
+            self.tx.execute_unprepared("BEGIN").await?;
+        }
+        result
     }
-}
 
-impl<C: TransactionTrait + Send + Sync, E: Send + Sync> StreamTrait for Tx<C, E> {
-    type Stream<'a> = <DatabaseTransaction as StreamTrait>::Stream<'a> where E: 'a, C: 'a;
+    /// Fail if nested transactions are disallowed by
+    /// [`Layer::strict_nesting`](crate::Layer::strict_nesting).
+    fn check_nesting(&self) -> Result<(), DbErr> {
+        if !self.options.strict_nesting {
+            return Ok(());
+        }
+        #[cfg(feature = "tracing")]
+        tracing::warn!("nested transaction begun on the request transaction with strict nesting");
+        Err(DbErr::Custom(Error::NestedTransaction.to_string()))
+    }
 
-    fn stream<'a>(
-        &'a self,
-        stmt: sea_orm::Statement,
-    ) -> std::pin::Pin<
-        Box<dyn futures_core::Future<Output = Result<Self::Stream<'a>, DbErr>> + 'a + Send>,
-    > {
-        self.0.stream(stmt)
+    /// Count a statement that's about to run, or fail if it would exceed
+    /// [`Layer::max_statements`](crate::Layer::max_statements) (or the transaction was aborted
+    /// through a [`TxRegistry`](crate::TxRegistry)).
+    fn count_statement(&self) -> Result<(), DbErr> {
+        self.check_aborted()?;
+
+        let max = self.options.max_statements;
+        let counted =
+            self.state
+                .statements
+                .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |count| match max {
+                    Some(max) if count >= max => None,
+                    _ => Some(count + 1),
+                });
+
+        match (counted, max) {
+            (Err(_), Some(max)) => {
+                #[cfg(feature = "tracing")]
+                tracing::warn!(
+                    max,
+                    "request transaction exceeded its maximum number of statements"
+                );
+                Err(DbErr::Custom(Error::TooManyStatements { max }.to_string()))
+            }
+            _ => Ok(()),
+        }
     }
-}
 
-impl<C: TransactionTrait, E> TransactionTrait for Tx<C, E> {
-    fn begin<'life0, 'async_trait>(
-        &'life0 self,
-    ) -> core::pin::Pin<
-        Box<
-            dyn core::future::Future<Output = Result<DatabaseTransaction, DbErr>>
-                + core::marker::Send
-                + 'async_trait,
-        >,
-    >
-    where
-        'life0: 'async_trait,
-        Self: 'async_trait,
-    {
-        self.0.begin()
+    /// Fail if the transaction was aborted through a [`TxRegistry`](crate::TxRegistry).
+    fn check_aborted(&self) -> Result<(), DbErr> {
+        if self.state.aborted.load(Ordering::Relaxed) {
+            return Err(DbErr::Custom(Error::Aborted.to_string()));
+        }
+        Ok(())
     }
 
-    fn begin_with_config<'life0, 'async_trait>(
-        &'life0 self,
-        isolation_level: Option<sea_orm::IsolationLevel>,
-        access_mode: Option<sea_orm::AccessMode>,
-    ) -> core::pin::Pin<
-        Box<
-            dyn core::future::Future<Output = Result<DatabaseTransaction, DbErr>>
-                + core::marker::Send
-                + 'async_trait,
-        >,
-    >
-    where
-        'life0: 'async_trait,
-        Self: 'async_trait,
-    {
-        self.0.begin_with_config(isolation_level, access_mode)
+    /// A copy of `stmt` to explain if it turns out to be slow (see
+    /// [`Layer::explain_slow_queries`](crate::Layer::explain_slow_queries)), if it's explainable.
+    #[cfg_attr(not(feature = "tracing"), allow(unused_variables))]
+    fn explainable(&self, stmt: &Statement) -> Option<Statement> {
+        #[cfg(feature = "tracing")]
+        if self.options.explain_threshold.is_some() && is_explainable(&stmt.sql) {
+            return Some(stmt.clone());
+        }
+        None
     }
 
-    fn transaction<'life0, 'async_trait, F, T, TE>(
-        &'life0 self,
-        callback: F,
-    ) -> core::pin::Pin<
-        Box<
-            dyn core::future::Future<Output = Result<T, sea_orm::TransactionError<TE>>>
-                + core::marker::Send
-                + 'async_trait,
-        >,
-    >
-    where
-        F: for<'c> FnOnce(
-                &'c DatabaseTransaction,
-            ) -> std::pin::Pin<
-                Box<dyn futures_core::Future<Output = Result<T, TE>> + Send + 'c>,
-            > + Send,
-        T: Send,
-        TE: std::error::Error + Send,
-        F: 'async_trait,
-        T: 'async_trait,
-        TE: 'async_trait,
-        'life0: 'async_trait,
-        Self: 'async_trait,
-    {
-        self.0.transaction(callback)
+    /// Log the plan of `stmt` (from [`explainable`](Self::explainable)), if it took longer than
+    /// the threshold since `started`.
+    #[cfg_attr(not(feature = "tracing"), allow(unused_variables))]
+    async fn explain_if_slow(&self, stmt: Option<Statement>, started: Instant) {
+        let (Some(stmt), Some(threshold)) = (stmt, self.options.explain_threshold) else {
+            return;
+        };
+        let elapsed = started.elapsed();
+        if elapsed <= threshold {
+            return;
+        }
+
+        match explain(&self.tx, &stmt).await {
+            Ok(plan) => {
+                #[cfg(feature = "tracing")]
+                tracing::warn!(
+                    kind = "tx.slow_query",
+                    sql = stmt.sql.as_str(),
+                    plan = plan.as_str(),
+                    ?elapsed,
+                    ?threshold,
+                    "slow statement in request transaction"
+                );
+            }
+            Err(error) => {
+                #[cfg(feature = "tracing")]
+                tracing::warn!(%error, sql = stmt.sql.as_str(), "failed to explain slow statement");
+            }
+        }
     }
 
-    fn transaction_with_config<'life0, 'async_trait, F, T, TE>(
-        &'life0 self,
-        callback: F,
-        isolation_level: Option<sea_orm::IsolationLevel>,
-        access_mode: Option<sea_orm::AccessMode>,
-    ) -> core::pin::Pin<
-        Box<
-            dyn core::future::Future<Output = Result<T, sea_orm::TransactionError<TE>>>
-                + core::marker::Send
-                + 'async_trait,
-        >,
-    >
-    where
-        F: for<'c> FnOnce(
-                &'c DatabaseTransaction,
-            ) -> std::pin::Pin<
-                Box<dyn futures_core::Future<Output = Result<T, TE>> + Send + 'c>,
-            > + Send,
-        T: Send,
-        TE: std::error::Error + Send,
-        F: 'async_trait,
-        T: 'async_trait,
-        TE: 'async_trait,
-        'life0: 'async_trait,
-        Self: 'async_trait,
-    {
-        self.0
-            .transaction_with_config(callback, isolation_level, access_mode)
+    /// The backend of the database the transaction is running on.
+    ///
+    /// This is the same as [`ConnectionTrait::get_database_backend`], which is handy for building
+    /// [`Statement`]s without importing the trait. The backend is looked up once, when the `Tx` is
+    /// extracted.
+    pub fn backend(&self) -> DbBackend {
+        self.backend
     }
-}
 
-#[async_trait]
-impl<C: TransactionTrait + Send + Sync + 'static, S: Sync, E> FromRequestParts<S> for Tx<C, E>
-where
-    E: From<Error> + IntoResponse,
-{
-    type Rejection = E;
+    /// Details of the database the transaction is running on, if the
+    /// [`Layer`](crate::Layer) was constructed with
+    /// [`Layer::new_with_options`](crate::Layer::new_with_options).
+    pub fn meta(&self) -> Option<&TxMeta> {
+        self.options.meta.as_deref()
+    }
 
-    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
-        let ext: &mut Lazy<C> = parts.extensions.get_mut().ok_or(Error::MissingExtension)?;
+    /// The pool the transaction was begun from, for statements that must run outside of it.
+    ///
+    /// Statements run on the pool aren't part of the request's transaction, so they're committed
+    /// whatever happens to it, e.g. to record an error that the rollback mustn't discard:
+    ///
+    /// ```
+    /// use axum_sea_orm_tx::Tx;
+    /// use sea_orm::{ConnectionTrait, DbErr};
+    ///
+    /// async fn handler(tx: Tx<sea_orm::DatabaseConnection>) -> Result<(), DbErr> {
+    ///     if let Err(error) = tx.execute_unprepared("INSERT INTO users VALUES (1)").await {
+    ///         tx.pool().execute_unprepared("INSERT INTO errors DEFAULT VALUES").await?;
+    ///         return Err(error);
+    ///     }
+    ///     Ok(())
+    /// }
+    /// ```
+    ///
+    /// With [`Layer::read_write_split`](crate::Layer::read_write_split), this is the replica for
+    /// requests routed to it. Statements on the pool need a connection besides the transaction's,
+    /// so they wait for one if the pool is exhausted (and never get one from a pool of one).
+    pub fn pool(&self) -> &C {
+        &self.pool
+    }
 
-        let tx = ext.get_or_begin().await?;
+    /// Whether statements run through this `Tx` are in a transaction that's yet to be resolved.
+    ///
+    /// This is `false` once the connection has been pinned by
+    /// [`commit_and_pin`](Self::commit_and_pin), since statements are then committed as they run,
+    /// and if a new transaction couldn't be begun in place of one resolved early (see
+    /// [`Error::Closed`]). An aborted transaction is still open, until it's rolled back (see
+    /// [`is_aborted`](Self::is_aborted)).
+    pub fn is_open(&self) -> bool {
+        matches!(*self.tx, Connection::Transaction(_)) && !self.is_pinned()
+    }
 
-        Ok(Self(tx, PhantomData))
+    /// How long it's been since the transaction was begun.
+    ///
+    /// This restarts when a new transaction is begun in its place, e.g. by
+    /// [`commit_and_begin`](Self::commit_and_begin), so a handler working through a batch can
+    /// commit once the transaction is getting old:
+    ///
+    /// ```
+    /// use std::time::Duration;
+    ///
+    /// use axum_sea_orm_tx::Tx;
+    /// use sea_orm::{ConnectionTrait, DbErr};
+    ///
+    /// async fn handler(mut tx: Tx<sea_orm::DatabaseConnection>) -> Result<(), DbErr> {
+    ///     for id in 0..1000 {
+    ///         tx.execute_unprepared(&format!("INSERT INTO items VALUES ({id})")).await?;
+    ///         if tx.elapsed() > Duration::from_secs(1) {
+    ///             tx.commit_and_begin().await?;
+    ///         }
+    ///     }
+    /// #   Ok(())
+    /// }
+    /// ```
+    pub fn elapsed(&self) -> Duration {
+        self.begun_at.elapsed()
     }
-}
 
-/// The OG `Slot` – the transaction (if any) returns here when the `Extension` is dropped.
-pub(crate) struct TxSlot(Slot<Option<Slot<DatabaseTransaction>>>);
+    /// How many statements the request has run through the [`ConnectionTrait`] methods of `Tx` so
+    /// far.
+    ///
+    /// This is the count that [`Layer::max_statements`](crate::Layer::max_statements) limits, so it
+    /// carries on across [`commit_and_begin`](Self::commit_and_begin) and the like. Statements run
+    /// directly on the `DatabaseTransaction` (through `Deref` etc.) aren't counted.
+    pub fn statements_executed(&self) -> usize {
+        self.state.statements.load(Ordering::Relaxed)
+    }
 
-impl TxSlot {
-    /// Create a `TxSlot` bound to the given request extensions.
+    /// Whether the transaction has been aborted, so statements run in it will fail and it will be
+    /// rolled back.
     ///
-    /// When the request extensions are dropped, `commit` can be called to commit the transaction
-    /// (if any).
-    pub(crate) fn bind<C: TransactionTrait + Send + Sync + 'static>(
-        extensions: &mut http::Extensions,
-        pool: C,
-    ) -> Self {
-        let (slot, tx) = Slot::new_leased(None);
-        extensions.insert(Lazy { pool, tx });
-        Self(slot)
+    /// This lets handlers that run several statements bail out once one has failed, rather than
+    /// running the rest only for them to fail too:
+    ///
+    /// ```
+    /// use axum_sea_orm_tx::Tx;
+    /// use sea_orm::ConnectionTrait;
+    ///
+    /// async fn handler(tx: Tx<sea_orm::DatabaseConnection>) -> &'static str {
+    ///     if tx.execute_unprepared("INSERT INTO ...").await.is_err() && tx.is_aborted() {
+    ///         return "the transaction can't be used any more";
+    ///     }
+    ///     /* ... */
+    /// #   ""
+    /// }
+    /// ```
+    ///
+    /// A transaction is aborted if it was aborted through a [`TxRegistry`](crate::TxRegistry), or
+    /// (on a best-effort basis) if a statement run through the [`ConnectionTrait`] methods failed
+    /// in a way that aborts the transaction on its backend:
+    ///
+    /// - On Postgres, any failure aborts the transaction, and later statements fail with "current
+    ///   transaction is aborted".
+    /// - On MySQL, a deadlock rolls back the transaction, but other failures only roll back the
+    ///   failed statement.
+    /// - On SQLite, failures only roll back the failed statement (except in rare cases, like
+    ///   running out of memory, which aren't detected).
+    ///
+    /// Failures within [`with_savepoint`](Self::with_savepoint) don't count, since they're
+    /// recovered from by rolling back to the savepoint (as is a failure after a savepoint that's
+    /// rolled back to with [`rollback_to`](Self::rollback_to)), and nor do failures of statements run
+    /// directly on the `DatabaseTransaction` (through `Deref` etc.) or streamed. An aborted
+    /// transaction is always rolled back by the [`Service`](crate::Service) middleware (with
+    /// [`RollbackReason::Aborted`](crate::RollbackReason::Aborted) or
+    /// [`RollbackReason::StatementFailed`](crate::RollbackReason::StatementFailed)), and [`commit`](Self::commit)
+    /// fails with [`Error::Aborted`].
+    pub fn is_aborted(&self) -> bool {
+        self.state.aborted.load(Ordering::Relaxed) || self.state.failed.load(Ordering::Relaxed)
     }
 
-    pub(crate) async fn commit(self) -> Result<(), DbErr> {
-        if let Some(tx) = self.0.into_inner().flatten().and_then(Slot::into_inner) {
-            tx.commit().await?;
+    /// Change the error type `E`, e.g. to hand the transaction from generic middleware to code
+    /// expecting an application's own error type.
+    ///
+    /// `E` is only used for the rejection when extracting `Tx`, so this changes nothing about the
+    /// transaction itself:
+    ///
+    /// ```
+    /// use axum_sea_orm_tx::Tx;
+    /// use sea_orm::DatabaseConnection;
+    ///
+    /// # struct MyError;
+    /// fn with_my_error(tx: Tx<DatabaseConnection>) -> Tx<DatabaseConnection, MyError> {
+    ///     tx.map_err_type()
+    /// }
+    /// ```
+    pub fn map_err_type<E2>(self) -> Tx<C, E2, I> {
+        Tx {
+            tx: self.tx,
+            backend: self.backend,
+            pool: self.pool,
+            options: self.options,
+            state: self.state,
+            begun_at: self.begun_at,
+            savepoint_depth: self.savepoint_depth,
+            savepoints: self.savepoints,
+            _marker: PhantomData,
         }
-        Ok(())
     }
-}
 
-/// A lazily acquired transaction.
-///
-/// When the transaction is started, it's inserted into the `Option` leased from the `TxSlot`, so
-/// that when `Lazy` is dropped the transaction is moved to the `TxSlot`.
-struct Lazy<C: TransactionTrait = DatabaseConnection> {
-    pool: C,
-    tx: Lease<Option<Slot<DatabaseTransaction>>>,
-}
+    /// Execute each of `stmts` in order, stopping at the first error.
+    ///
+    /// The results of each statement are returned on success. If a statement fails, the error is
+    /// returned and any statements that already ran are left in the transaction, so they'll be
+    /// rolled back as usual if the handler returns an error response.
+    pub async fn execute_many(
+        &mut self,
+        stmts: impl IntoIterator<Item = Statement>,
+    ) -> Result<Vec<ExecResult>, DbErr> {
+        let mut results = Vec::new();
+        for stmt in stmts {
+            self.count_statement()?;
+            let stmt = self.prepare(stmt);
+            let explainable = self.explainable(&stmt);
+            let started = Instant::now();
+            let result = self.tx.execute(stmt).await;
+            results.push(self.finish(result, explainable, started).await?);
+        }
+        Ok(results)
+    }
 
-impl<C: TransactionTrait> Lazy<C> {
-    async fn get_or_begin(&mut self) -> Result<Lease<DatabaseTransaction>, Error> {
-        let tx = if let Some(tx) = self.tx.as_mut() {
-            tx
-        } else {
-            let tx = self.pool.begin().await?;
-            self.tx.insert(Slot::new(tx))
-        };
+    /// Execute `stmt` (e.g. an `INSERT`), and return the ID it generated.
+    ///
+    /// Databases report generated IDs differently, so this hides the difference for inserts that
+    /// don't use `RETURNING`:
+    ///
+    /// - on MySQL and SQLite, it's the last insert ID reported by the driver (`LAST_INSERT_ID()`
+    ///   and `last_insert_rowid()`);
+    /// - on Postgres, it's the value of `lastval()` after running the statement, i.e. the value
+    ///   most recently generated by any sequence in the session (typically the `SERIAL` or
+    ///   `IDENTITY` column of the inserted row).
+    ///
+    /// Fails with [`DbErr::Custom`] if the ID doesn't fit in an `i64`. On Postgres, if no sequence
+    /// has been used in the session yet, the database's error from `lastval()` is returned.
+    pub async fn execute_last_insert_id(&mut self, stmt: Statement) -> Result<i64, DbErr>
+    where
+        C: Sync,
+        E: Sync,
+        I: Sync,
+    {
+        let result = self.execute(stmt).await?;
+        match self.backend {
+            DbBackend::MySql | DbBackend::Sqlite => {
+                let id = result.last_insert_id();
+                i64::try_from(id)
+                    .map_err(|_| DbErr::Custom(format!("last insert ID {id} is out of range")))
+            }
+            DbBackend::Postgres => {
+                let row = self
+                    .tx
+                    .query_one(Statement::from_string(
+                        self.backend,
+                        "SELECT lastval() AS id".to_string(),
+                    ))
+                    .await?
+                    .ok_or_else(|| DbErr::Custom("lastval() returned no rows".to_string()))?;
+                row.try_get("", "id")
+            }
+        }
+    }
+
+    /// Check that the connection backing the transaction is still alive.
+    ///
+    /// This runs a no-op `SELECT 1` in the transaction, which is useful before a long pause in a
+    /// handler (e.g. awaiting an external service) to fail fast if the connection has died. If it
+    /// returns an error, returning early from the handler with an error response will roll back the
+    /// transaction as usual.
+    pub async fn ping(&self) -> Result<(), DbErr> {
+        self.tx
+            .query_one(Statement::from_string(self.backend, "SELECT 1".to_string()))
+            .await?;
+        Ok(())
+    }
 
-        tx.lease().ok_or(Error::OverlappingExtractors)
+    /// Run `f` within a savepoint, rolling back to the savepoint if it returns an error.
+    ///
+    /// This is like SeaORM's [`TransactionTrait::transaction`], but nested within the request's
+    /// transaction: a `SAVEPOINT` is created before calling `f`, and is released if `f` returns
+    /// `Ok`, or rolled back to if `f` returns `Err`. Either way the work done before the savepoint
+    /// is kept, so a handler can recover from a failed step:
+    ///
+    /// ```
+    /// use axum_sea_orm_tx::Tx;
+    /// use sea_orm::{ConnectionTrait, DbErr};
+    ///
+    /// async fn handler(mut tx: Tx<sea_orm::DatabaseConnection>) -> Result<(), DbErr> {
+    ///     let inserted = tx
+    ///         .with_savepoint(|tx| {
+    ///             Box::pin(async move {
+    ///                 tx.execute_unprepared("INSERT INTO ...").await?;
+    ///                 Ok::<_, DbErr>(true)
+    ///             })
+    ///         })
+    ///         .await
+    ///         .unwrap_or(false);
+    ///     /* ... */
+    /// #   Ok(())
+    /// }
+    /// ```
+    ///
+    /// Savepoints can be nested by calling `with_savepoint` on the `Tx` passed to `f`. Errors
+    /// creating or resolving the savepoint are converted into `TE`.
+    pub async fn with_savepoint<T, TE, F>(&mut self, f: F) -> Result<T, TE>
+    where
+        F: for<'a> FnOnce(&'a mut Self) -> BoxFuture<'a, Result<T, TE>>,
+        TE: From<DbErr>,
+    {
+        self.savepoint(async move |tx| f(tx).await).await
+    }
+
+    /// Run the async closure `f` within a savepoint, rolling back to the savepoint if it returns
+    /// an error.
+    ///
+    /// This is [`with_savepoint`](Self::with_savepoint) without the boxing, for trying an optional
+    /// step without poisoning the rest of the request's transaction:
+    ///
+    /// ```
+    /// use axum_sea_orm_tx::Tx;
+    /// use sea_orm::{ConnectionTrait, DbErr};
+    ///
+    /// async fn handler(mut tx: Tx<sea_orm::DatabaseConnection>) -> Result<(), DbErr> {
+    ///     let inserted = tx
+    ///         .savepoint(async |sp| {
+    ///             sp.execute_unprepared("INSERT INTO ...").await?;
+    ///             Ok::<_, DbErr>(true)
+    ///         })
+    ///         .await
+    ///         .unwrap_or(false);
+    ///     /* ... */
+    /// #   Ok(())
+    /// }
+    /// ```
+    pub async fn savepoint<T, TE, F>(&mut self, f: F) -> Result<T, TE>
+    where
+        F: AsyncFnOnce(&mut Self) -> Result<T, TE>,
+        TE: From<DbErr>,
+    {
+        self.savepoint_depth += 1;
+        let name = format!("axum_sea_orm_tx_{}", self.savepoint_depth);
+        let result = self.run_in_savepoint(&name, f).await;
+        // Named savepoints created within this one went with it
+        let depth = self.savepoint_depth;
+        self.savepoints
+            .retain(|(_, created_at)| *created_at < depth);
+        self.savepoint_depth -= 1;
+        result
+    }
+
+    async fn run_in_savepoint<T, TE, F>(&mut self, name: &str, f: F) -> Result<T, TE>
+    where
+        F: AsyncFnOnce(&mut Self) -> Result<T, TE>,
+        TE: From<DbErr>,
+    {
+        self.savepoint_statement(&format!("SAVEPOINT {name}"))
+            .await?;
+
+        match f(self).await {
+            Ok(value) => {
+                self.savepoint_statement(&format!("RELEASE SAVEPOINT {name}"))
+                    .await?;
+                Ok(value)
+            }
+            Err(error) => {
+                // Rolling back to a savepoint doesn't release it, so release it too
+                self.savepoint_statement(&format!("ROLLBACK TO SAVEPOINT {name}"))
+                    .await?;
+                self.savepoint_statement(&format!("RELEASE SAVEPOINT {name}"))
+                    .await?;
+                Err(error)
+            }
+        }
+    }
+
+    /// Run `sql` to manage the current savepoint.
+    async fn savepoint_statement(&self, sql: &str) -> Result<(), DbErr> {
+        let result = self.tx.execute_unprepared(sql).await.map(drop);
+        // Failing to resolve the savepoint leaves its parent (possibly the transaction) aborted
+        self.note_result(&result, self.savepoint_depth - 1);
+        result
+    }
+
+    /// Create a savepoint called `name`, which the transaction can later be rolled back to with
+    /// [`rollback_to`](Self::rollback_to).
+    ///
+    /// Unlike [`with_savepoint`](Self::with_savepoint), the savepoint isn't tied to a closure, so a
+    /// handler doing its work in phases can mark each one, and undo the work of a later phase
+    /// without losing the earlier ones:
+    ///
+    /// ```
+    /// use axum_sea_orm_tx::Tx;
+    /// use sea_orm::{ConnectionTrait, DbErr};
+    ///
+    /// async fn import(mut tx: Tx<sea_orm::DatabaseConnection>) -> Result<(), DbErr> {
+    ///     tx.execute_unprepared("INSERT INTO batches ...").await?;
+    ///     tx.savepoint_named("rows").await?;
+    ///     if tx.execute_unprepared("INSERT INTO rows ...").await.is_err() {
+    ///         // Keep the batch, but none of its rows
+    ///         tx.rollback_to("rows").await?;
+    ///     }
+    ///     /* ... */
+    /// #   Ok(())
+    /// }
+    /// ```
+    ///
+    /// Names may only contain ASCII letters, digits and underscores, and must be unique within the
+    /// transaction. A savepoint lasts until the transaction is resolved, or until the savepoint it
+    /// was created after is rolled back to (or, within [`with_savepoint`](Self::with_savepoint),
+    /// until `with_savepoint` returns). Fails with [`Error::Aborted`] if the transaction has been
+    /// aborted, since the savepoint couldn't be used to recover from that.
+    pub async fn savepoint_named(&mut self, name: &str) -> Result<(), DbErr> {
+        if self.is_aborted() {
+            return Err(DbErr::Custom(Error::Aborted.to_string()));
+        }
+        if self.savepoints.iter().any(|(existing, _)| existing == name) {
+            return Err(DbErr::Custom(format!("savepoint {name} already exists")));
+        }
+        let quoted = quote_savepoint(self.backend, name)?;
+        self.named_savepoint_statement(&format!("SAVEPOINT {quoted}"))
+            .await?;
+        self.savepoints
+            .push((name.to_string(), self.savepoint_depth));
+        Ok(())
+    }
+
+    /// Roll back the transaction to the savepoint created by
+    /// [`savepoint_named`](Self::savepoint_named) with `name`, undoing everything run since.
+    ///
+    /// The savepoint itself is kept, so the transaction can be rolled back to it again, but any
+    /// savepoints created after it are gone. This also recovers a transaction aborted by a failed
+    /// statement (see [`is_aborted`](Self::is_aborted)), as long as the failure came after the
+    /// savepoint.
+    ///
+    /// Fails without running anything if there's no such savepoint, or if it was created outside
+    /// the [`with_savepoint`](Self::with_savepoint) closure currently running.
+    pub async fn rollback_to(&mut self, name: &str) -> Result<(), DbErr> {
+        let Some(index) = self
+            .savepoints
+            .iter()
+            .position(|(existing, _)| existing == name)
+        else {
+            return Err(DbErr::Custom(format!("no savepoint named {name}")));
+        };
+        if self.savepoints[index].1 != self.savepoint_depth {
+            return Err(DbErr::Custom(format!(
+                "savepoint {name} was created outside the current with_savepoint"
+            )));
+        }
+
+        let quoted = quote_savepoint(self.backend, name)?;
+        self.named_savepoint_statement(&format!("ROLLBACK TO SAVEPOINT {quoted}"))
+            .await?;
+        self.savepoints.truncate(index + 1);
+        if self.savepoint_depth == 0 {
+            self.state.failed.store(false, Ordering::Relaxed);
+        }
+        Ok(())
+    }
+
+    /// Run `sql` to manage a savepoint created with [`savepoint_named`](Self::savepoint_named).
+    async fn named_savepoint_statement(&self, sql: &str) -> Result<(), DbErr> {
+        let result = self.tx.execute_unprepared(sql).await.map(drop);
+        self.note_result(&result, self.savepoint_depth);
+        result
+    }
+
+    /// Explicitly commit the transaction.
+    ///
+    /// By default, the transaction will be committed when a successful response is returned
+    /// (specifically, when the [`Service`](crate::Service) middleware intercepts an HTTP `2XX`
+    /// response). This method allows the transaction to be committed explicitly.
+    ///
+    /// Extracting `Tx` again after calling `commit` (e.g. in the handler, after middleware has
+    /// committed its own work) begins a fresh transaction, which is resolved by the middleware as
+    /// usual. Use [`commit_and_begin`](Self::commit_and_begin) to keep running statements through
+    /// the same `Tx` after committing.
+    ///
+    /// With [`Layer::dry_run`](crate::Layer::dry_run), this leaves the transaction open, to be
+    /// rolled back by the middleware.
+    pub async fn commit(self) -> Result<(), DbErr> {
+        if self.options.dry_run {
+            return Ok(());
+        }
+        if self.is_aborted() {
+            // The transaction is left for the middleware to roll back
+            return Err(DbErr::Custom(Error::Aborted.to_string()));
+        }
+        self.state.resolved.store(true, Ordering::Relaxed);
+        self.options.commit(self.tx.steal()).await?;
+        self.state.committed().await;
+        Ok(())
+    }
+
+    /// Commit the transaction, but keep its connection for the rest of the request.
+    ///
+    /// This is for reading your own writes after committing them: unlike
+    /// [`commit_and_reopen`](Self::commit_and_reopen), which begins a new transaction on whichever
+    /// connection the pool hands out, follow-up statements are guaranteed to run on the same
+    /// connection (and so the same backend, e.g. with a replicated database behind a proxy), and
+    /// see the committed data immediately:
+    ///
+    /// ```
+    /// use axum_sea_orm_tx::Tx;
+    /// use sea_orm::{ConnectionTrait, DbErr};
+    ///
+    /// async fn handler(mut tx: Tx<sea_orm::DatabaseConnection>) -> Result<(), DbErr> {
+    ///     tx.execute_unprepared("INSERT INTO users VALUES (1)").await?;
+    ///     tx.commit_and_pin().await?;
+    ///     tx.execute_unprepared("SELECT * FROM users").await?;
+    ///     /* ... */
+    /// #   Ok(())
+    /// }
+    /// ```
+    ///
+    /// Once pinned, statements run through the [`ConnectionTrait`] methods behave as if in
+    /// autocommit mode: each is committed as soon as it has run (by committing and beginning a
+    /// transaction after it, so this costs two extra round-trips per statement), and the
+    /// [`Service`](crate::Service) middleware's commit or rollback has nothing left to resolve.
+    /// Statements run directly on the `DatabaseTransaction` (through `Deref` etc.) and streams
+    /// aren't committed this way, so they're still resolved by the middleware.
+    ///
+    /// The connection is returned to the pool when the request's transaction is resolved, as
+    /// usual, so a pinned connection is held for no longer than an unpinned one. The
+    /// [`on_commit`](Self::on_commit) futures registered so far are run once the transaction has
+    /// been committed.
+    ///
+    /// With [`Layer::dry_run`](crate::Layer::dry_run), this does nothing: statements keep running
+    /// in the same transaction, so they still see the earlier writes.
+    ///
+    /// Like [`commit`](Self::commit), this fails with [`Error::Aborted`] if the transaction
+    /// [`is_aborted`](Self::is_aborted), leaving it for the middleware to roll back.
+    pub async fn commit_and_pin(&mut self) -> Result<(), DbErr> {
+        if self.options.dry_run {
+            return Ok(());
+        }
+        if self.is_aborted() {
+            // Committing an aborted Postgres transaction "succeeds" by rolling it back
+            return Err(DbErr::Custom(Error::Aborted.to_string()));
+        }
+        if !self.is_pinned() {
+            match &*self.tx {
+                Connection::Transaction(tx) => {
+                    tx.execute_unprepared("COMMIT").await?;
+                    tx.execute_unprepared("BEGIN").await?;
+                }
+                Connection::Autocommit(_) => {}
+                Connection::Closed(_) => return Err(closed()),
+            }
+            self.state.pinned.store(true, Ordering::Relaxed);
+        }
+        self.state.committed().await;
+        Ok(())
+    }
+
+    /// Make the writes run so far visible to other connections, without giving up the request's
+    /// connection, if the mode the transaction is in allows it.
+    ///
+    /// **In a transaction (the default mode), this fails** with a [`DbErr::Custom`] holding
+    /// [`Error::FlushInTransaction`]'s message, and the transaction is left as it was: other
+    /// connections can't see a transaction's writes until it commits, and committing it early is
+    /// what [`commit_and_pin`](Self::commit_and_pin) (or [`commit_and_reopen`](Self::commit_and_reopen))
+    /// is for. In the other modes:
+    ///
+    /// - in autocommit mode (see [`AutocommitTx`]), every statement is committed as it runs, so
+    ///   this does nothing;
+    /// - once pinned by `commit_and_pin`, statements run through [`ConnectionTrait`] are already
+    ///   committed, and this also commits anything run directly on the `DatabaseTransaction`
+    ///   (through `Deref` etc.), running the [`on_commit`](Self::on_commit) futures registered so
+    ///   far;
+    /// - with [`Layer::dry_run`](crate::Layer::dry_run), nothing is ever committed, so this does
+    ///   nothing (like `commit_and_pin`).
+    ///
+    /// ```
+    /// use axum_sea_orm_tx::Tx;
+    /// use sea_orm::{ConnectionTrait, DbErr};
+    ///
+    /// async fn handler(mut tx: Tx<sea_orm::DatabaseConnection>) -> Result<(), DbErr> {
+    ///     tx.commit_and_pin().await?;
+    ///     tx.execute_unprepared("INSERT INTO jobs VALUES (1)").await?;
+    ///     tx.flush().await?;
+    ///     /* wait for a worker to pick the job up */
+    /// #   Ok(())
+    /// }
+    /// ```
+    pub async fn flush(&mut self) -> Result<(), DbErr> {
+        if self.options.dry_run {
+            return Ok(());
+        }
+        match &*self.tx {
+            Connection::Autocommit(_) => Ok(()),
+            Connection::Transaction(tx) if self.is_pinned() => {
+                self.check_aborted()?;
+                tx.execute_unprepared("COMMIT").await?;
+                tx.execute_unprepared("BEGIN").await?;
+                self.state.committed().await;
+                Ok(())
+            }
+            Connection::Transaction(_) => Err(DbErr::Custom(Error::FlushInTransaction.to_string())),
+            Connection::Closed(_) => Err(closed()),
+        }
+    }
+
+    /// Send a Postgres notification on `channel` with `payload`, once the transaction commits.
+    ///
+    /// Notifications are transactional, so `LISTEN`ers receive it exactly when (and if) the
+    /// transaction commits, and it's discarded if the transaction is rolled back:
+    ///
+    /// ```
+    /// use axum_sea_orm_tx::Tx;
+    /// use sea_orm::{ConnectionTrait, DbErr};
+    ///
+    /// async fn handler(tx: Tx<sea_orm::DatabaseConnection>) -> Result<(), DbErr> {
+    ///     tx.execute_unprepared("INSERT INTO orders VALUES (1)").await?;
+    ///     tx.notify("orders", "1").await
+    /// }
+    /// ```
+    ///
+    /// This runs `SELECT pg_notify(channel, payload)`, which counts as a write (e.g. for
+    /// [`Layer::rollback_readonly`](crate::Layer::rollback_readonly)). Other backends fail with a
+    /// [`DbErr::Custom`] error.
+    pub async fn notify(&self, channel: &str, payload: &str) -> Result<(), DbErr> {
+        let backend = self.tx.get_database_backend();
+        if backend != DbBackend::Postgres {
+            return Err(DbErr::Custom(
+                "notifications are only supported on Postgres".to_string(),
+            ));
+        }
+
+        self.execute(Statement::from_sql_and_values(
+            backend,
+            "SELECT pg_notify($1, $2)",
+            [channel.into(), payload.into()],
+        ))
+        .await?;
+        self.note_write();
+        Ok(())
+    }
+
+    /// Commit the transaction once the handler is done, even if the response isn't successful.
+    ///
+    /// Normally a response like `500 Internal Server Error` rolls the transaction back, which is
+    /// usually right, but not if the error has nothing to do with the database. For example, if
+    /// building the response fails after the handler's writes succeeded, those writes can be kept
+    /// by calling this once they're done:
+    ///
+    /// ```
+    /// use axum_sea_orm_tx::Tx;
+    /// use http::StatusCode;
+    /// use sea_orm::ConnectionTrait;
+    ///
+    /// async fn handler(tx: Tx<sea_orm::DatabaseConnection>) -> Result<String, StatusCode> {
+    ///     tx.execute_unprepared("INSERT INTO users VALUES (1)")
+    ///         .await
+    ///         .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    ///
+    ///     // The user was created, whatever happens next
+    ///     tx.commit_anyway();
+    ///
+    ///     render_profile(1).ok_or(StatusCode::INTERNAL_SERVER_ERROR)
+    /// }
+    /// # fn render_profile(_: i32) -> Option<String> { None }
+    /// ```
+    ///
+    /// This overrides the response status, and [`Layer::auto_commit`](crate::Layer::auto_commit)
+    /// being disabled, but not a [`Commit`](crate::Commit) or [`Rollback`](crate::Rollback)
+    /// response, which decides explicitly. Unlike returning a `Commit` response, it can be decided
+    /// before the response (or error) is known, e.g. before an error is returned with `?`.
+    pub fn commit_anyway(&self) {
+        self.state.commit_anyway.store(true, Ordering::Relaxed);
+    }
+
+    /// Turn the transaction into a handle that can be cloned and shared with other tasks.
+    ///
+    /// `Tx` can't be borrowed by a spawned task (which must be `'static`), but a [`SharedTx`]
+    /// can be cloned into it, e.g. to write from a task doing CPU-heavy work that the handler
+    /// awaits:
+    ///
+    /// ```
+    /// use axum_sea_orm_tx::Tx;
+    /// use sea_orm::{ConnectionTrait, DatabaseConnection};
+    ///
+    /// async fn handler(tx: Tx<DatabaseConnection>) -> Result<(), axum_sea_orm_tx::Error> {
+    ///     let tx = tx.into_shared();
+    ///
+    ///     let task = tokio::spawn({
+    ///         let tx = tx.clone();
+    ///         async move {
+    ///             // ... heavy lifting ...
+    ///             tx.execute_unprepared("INSERT INTO reports DEFAULT VALUES").await
+    ///         }
+    ///     });
+    ///     task.await.unwrap()?;
+    ///
+    ///     tx.execute_unprepared("UPDATE users SET report_count = report_count + 1").await?;
+    ///     Ok(())
+    /// }
+    /// ```
+    ///
+    /// See [`SharedTx`] for when the transaction is resolved.
+    pub fn into_shared(self) -> SharedTx<C, E, I> {
+        SharedTx {
+            backend: self.backend,
+            tx: Arc::new(futures_util::lock::Mutex::new(self)),
+        }
+    }
+
+    /// Run `future` once the transaction has been committed.
+    ///
+    /// This is useful for side effects that should only happen if the request's changes are
+    /// persisted, like sending an email:
+    ///
+    /// ```
+    /// use axum_sea_orm_tx::Tx;
+    ///
+    /// async fn handler(mut tx: Tx<sea_orm::DatabaseConnection>) {
+    ///     /* ... */
+    ///     tx.on_commit(async { /* send the email */ });
+    /// }
+    /// ```
+    ///
+    /// Futures run in the order they were registered, once the [`Service`](crate::Service)
+    /// middleware has committed the transaction and before the response is returned (or, if the
+    /// handler commits explicitly, as part of [`commit`](Self::commit) or
+    /// [`commit_and_reopen`](Self::commit_and_reopen)). If the transaction is rolled back, or
+    /// committing it fails, they're dropped without being run.
+    pub fn on_commit(&mut self, future: impl Future<Output = ()> + Send + 'static) {
+        self.state.on_commit.lock().push(Box::pin(future));
+    }
+
+    /// Commit the transaction, and begin a new one in its place, through a reference.
+    ///
+    /// This is like [`commit_and_reopen`](Self::commit_and_reopen), but keeps the same `Tx`, so it
+    /// can be used where the handler only has `&mut Tx` (e.g. in a helper function):
+    ///
+    /// ```
+    /// use axum_sea_orm_tx::Tx;
+    /// use sea_orm::{ConnectionTrait, DbErr};
+    ///
+    /// async fn import(tx: &mut Tx<sea_orm::DatabaseConnection>, batches: &[&str]) -> Result<(), DbErr> {
+    ///     for batch in batches {
+    ///         tx.execute_unprepared(batch).await?;
+    ///         // Keep each batch, even if a later one fails
+    ///         tx.commit_and_begin().await?;
+    ///     }
+    ///     Ok(())
+    /// }
+    /// ```
+    ///
+    /// The new transaction is begun with the same options as the original, and is resolved by the
+    /// [`Service`](crate::Service) middleware as usual. If the transaction was aborted (see
+    /// [`is_aborted`](Self::is_aborted)), it's left for the middleware to roll back, and this fails
+    /// with [`Error::Aborted`]. If committing or beginning the new transaction fails, the error is
+    /// returned and the `Tx` is left without a transaction, so statements run through it fail with
+    /// [`Error::Closed`] (as does committing it), until
+    /// [`rollback_and_reopen`](Self::rollback_and_reopen) manages to begin a new one.
+    ///
+    /// Fails without doing anything if it's called within [`with_savepoint`](Self::with_savepoint),
+    /// since that would commit the savepoint's work along with the rest of the transaction.
+    ///
+    /// With [`Layer::dry_run`](crate::Layer::dry_run), this keeps the same transaction, without
+    /// committing it.
+    pub async fn commit_and_begin(&mut self) -> Result<(), DbErr> {
+        if self.savepoint_depth > 0 {
+            return Err(DbErr::Custom(
+                "Tx::commit_and_begin can't be used within a savepoint".to_string(),
+            ));
+        }
+        if self.options.dry_run {
+            return Ok(());
+        }
+        if self.is_aborted() {
+            return Err(DbErr::Custom(Error::Aborted.to_string()));
+        }
+
+        let tx = self.close();
+        self.savepoints.clear();
+        self.options.commit(tx).await?;
+        self.state.committed().await;
+        self.state.written.store(false, Ordering::Relaxed);
+        self.state.pinned.store(false, Ordering::Relaxed);
+
+        self.begun_at = Instant::now();
+        *self.tx = begin_connection(&self.pool, &self.options).await?;
+        Ok(())
+    }
+
+    /// Commit the transaction, and begin a new one in its place.
+    ///
+    /// This is useful when the handler needs its changes committed before it's done, but still
+    /// wants to run statements afterwards (e.g. to read back the committed data to build the
+    /// response):
+    ///
+    /// ```
+    /// use axum_sea_orm_tx::Tx;
+    /// use sea_orm::{ConnectionTrait, DbErr};
+    ///
+    /// async fn handler(tx: Tx<sea_orm::DatabaseConnection>) -> Result<(), DbErr> {
+    ///     tx.execute_unprepared("INSERT INTO users VALUES (1)").await?;
+    ///     let tx = tx.commit_and_reopen().await?;
+    ///     tx.execute_unprepared("SELECT * FROM users").await?;
+    ///     Ok(())
+    /// }
+    /// ```
+    ///
+    /// The new transaction is begun with the same options as the original, and is resolved by the
+    /// [`Service`](crate::Service) middleware as usual (i.e. committed if the response is
+    /// successful). If committing fails, no new transaction is begun and the error is returned. See
+    /// [`commit_and_begin`](Self::commit_and_begin) to do this through a reference.
+    ///
+    /// With [`Layer::dry_run`](crate::Layer::dry_run), this returns the same transaction,
+    /// without committing it.
+    pub async fn commit_and_reopen(mut self) -> Result<Self, DbErr> {
+        self.commit_and_begin().await?;
+        Ok(self)
+    }
+
+    /// Roll back the transaction, and begin a new one in its place.
+    ///
+    /// This is useful to retry the handler's work from scratch without returning from the handler,
+    /// e.g. when optimistic concurrency control detects a conflict:
+    ///
+    /// ```
+    /// use axum_sea_orm_tx::Tx;
+    /// use sea_orm::{ConnectionTrait, DbErr};
+    ///
+    /// async fn handler(mut tx: Tx<sea_orm::DatabaseConnection>) -> Result<(), DbErr> {
+    ///     loop {
+    ///         let updated = tx
+    ///             .execute_unprepared("UPDATE accounts SET balance = 0 WHERE version = 1")
+    ///             .await?;
+    ///         if updated.rows_affected() == 0 {
+    ///             tx.rollback_and_reopen().await?;
+    ///             continue;
+    ///         }
+    ///         return Ok(());
+    ///     }
+    /// }
+    /// ```
+    ///
+    /// The new transaction is begun with the same options as the original, and is resolved by the
+    /// [`Service`](crate::Service) middleware as usual. The [`on_commit`](Self::on_commit) futures
+    /// registered so far are dropped with the rolled back transaction.
+    ///
+    /// The transaction is rolled back before the new one is begun, so this never needs more than
+    /// one connection. If rolling back or beginning the new transaction fails, the error is
+    /// returned and the `Tx` is left without a transaction, as with
+    /// [`commit_and_begin`](Self::commit_and_begin), so this can be called again to retry.
+    ///
+    /// Fails without doing anything if it's called within [`with_savepoint`](Self::with_savepoint),
+    /// whose savepoint wouldn't survive the rollback, or with [`Error::Aborted`] if the transaction
+    /// was aborted through a [`TxRegistry`](crate::TxRegistry).
+    pub async fn rollback_and_reopen(&mut self) -> Result<(), DbErr> {
+        if self.savepoint_depth > 0 {
+            return Err(DbErr::Custom(
+                "Tx::rollback_and_reopen can't be used within a savepoint".to_string(),
+            ));
+        }
+        if self.state.aborted.load(Ordering::Relaxed) {
+            return Err(DbErr::Custom(Error::Aborted.to_string()));
+        }
+
+        let tx = self.close();
+        self.savepoints.clear();
+        self.state.on_commit.lock().clear();
+        self.state.written.store(false, Ordering::Relaxed);
+        self.state.pinned.store(false, Ordering::Relaxed);
+        self.state.failed.store(false, Ordering::Relaxed);
+        tx.rollback().await?;
+
+        self.begun_at = Instant::now();
+        *self.tx = begin_connection(&self.pool, &self.options).await?;
+        Ok(())
+    }
+
+    /// Run `f`, and if it fails with an error that's resolved by retrying the transaction (e.g. a
+    /// serialization failure under `SERIALIZABLE` isolation), roll back and run it again in a new
+    /// transaction, waiting between attempts as given by `policy`:
+    ///
+    /// ```
+    /// use std::time::Duration;
+    ///
+    /// use axum_sea_orm_tx::{BackoffPolicy, Serializable, Tx};
+    /// use sea_orm::{ConnectionTrait, DbErr};
+    ///
+    /// async fn handler(
+    ///     mut tx: Tx<sea_orm::DatabaseConnection, axum_sea_orm_tx::Error, Serializable>,
+    /// ) -> Result<(), DbErr> {
+    ///     let policy = BackoffPolicy::exponential(Duration::from_millis(10), Duration::from_secs(1), 3);
+    ///     tx.retry(policy, |tx| {
+    ///         Box::pin(async move {
+    ///             tx.execute_unprepared("UPDATE accounts SET balance = balance - 10 WHERE id = 1")
+    ///                 .await?;
+    ///             tx.execute_unprepared("UPDATE accounts SET balance = balance + 10 WHERE id = 2")
+    ///                 .await?;
+    ///             Ok(())
+    ///         })
+    ///     })
+    ///     .await
+    /// }
+    /// ```
+    ///
+    /// The retryable errors are serialization failures and deadlocks on Postgres, deadlocks on
+    /// MySQL, and "database is locked" on SQLite. SeaORM doesn't expose the database's error codes,
+    /// so these are detected from the error messages. Other errors are returned straight away, as
+    /// is the last error once `policy` has no retries left.
+    ///
+    /// Serialization failures can't be recovered from with a savepoint, so each retry is run in a
+    /// fresh transaction (see [`rollback_and_reopen`](Self::rollback_and_reopen)). This discards
+    /// everything the transaction had done before `retry` was called too, so `f` should do all of
+    /// the request's work. For the same reason, `f` isn't retried within
+    /// [`with_savepoint`](Self::with_savepoint), whose savepoint wouldn't survive a retry:
+    /// `rollback_and_reopen`'s error is returned instead.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `policy` waits between attempts and there's no runtime to wait with (see
+    /// [`Layer::runtime`](crate::Layer::runtime)).
+    pub async fn retry<T, F>(&mut self, policy: BackoffPolicy, mut f: F) -> Result<T, DbErr>
+    where
+        F: for<'a> FnMut(&'a mut Self) -> BoxFuture<'a, Result<T, DbErr>>,
+    {
+        let mut attempt = 1;
+        loop {
+            let error = match f(self).await {
+                Err(error) if is_retryable(self.backend, &error) => error,
+                result => return result,
+            };
+            let Some(delay) = policy.next_delay(attempt) else {
+                return Err(error);
+            };
+
+            #[cfg(feature = "tracing")]
+            tracing::warn!(%error, attempt, ?delay, "retrying request transaction");
+            self.rollback_and_reopen().await?;
+            if !delay.is_zero() {
+                let sleep = self
+                    .options
+                    .sleep
+                    .expect("retry with a delay requires a runtime");
+                sleep(delay).await;
+            }
+            attempt += 1;
+        }
+    }
+
+    /// Take the transaction out of the request, for code that needs to own a
+    /// `DatabaseTransaction` (e.g. a library that commits it itself).
+    ///
+    /// The [`Service`](crate::Service) middleware won't resolve the returned transaction, whatever
+    /// the response: it's up to the caller to commit it, and SeaORM rolls it back if it's dropped
+    /// without being committed. Since the middleware can't tell whether it was committed, the
+    /// [`on_commit`](Self::on_commit) futures registered so far are dropped, and
+    /// [`Layer::dry_run`](crate::Layer::dry_run) doesn't apply. As after [`commit`](Self::commit),
+    /// extracting `Tx` again in the same request begins a new transaction.
+    ///
+    /// Fails without taking the transaction if it's been aborted (see
+    /// [`is_aborted`](Self::is_aborted)), or if a new transaction couldn't be begun in its place
+    /// (see [`Error::Closed`]), since there's no transaction to take.
+    pub fn into_inner(self) -> Result<DatabaseTransaction, DbErr> {
+        if self.is_aborted() {
+            return Err(DbErr::Custom(Error::Aborted.to_string()));
+        }
+        match *self.tx {
+            Connection::Transaction(_) => {}
+            Connection::Autocommit(_) => return Err(autocommit_unsupported("Tx::into_inner")),
+            Connection::Closed(_) => return Err(closed()),
+        }
+        self.note_write();
+        self.state.on_commit.lock().clear();
+        self.state.resolved.store(true, Ordering::Relaxed);
+        match self.tx.steal() {
+            Connection::Transaction(tx) => Ok(tx),
+            Connection::Autocommit(_) | Connection::Closed(_) => unreachable!("BUG: checked above"),
+        }
+    }
+
+    /// Detach the transaction from the response, to finish the request's work in the background.
+    ///
+    /// This returns a future that runs `f`, then commits the transaction if it succeeded, or rolls
+    /// it back if it failed. The future is meant to be spawned, so the handler can respond straight
+    /// away (e.g. with `202 Accepted`), and awaiting it in the spawned task gives the outcome, for
+    /// error reporting:
+    ///
+    /// ```
+    /// use axum_sea_orm_tx::Tx;
+    /// use http::StatusCode;
+    /// use sea_orm::{ConnectionTrait, DbErr};
+    ///
+    /// async fn handler(tx: Tx<sea_orm::DatabaseConnection>) -> StatusCode {
+    ///     let work = tx.detach(|tx| {
+    ///         Box::pin(async move {
+    ///             tx.execute_unprepared("UPDATE reports SET ready = true").await?;
+    ///             Ok::<_, DbErr>(())
+    ///         })
+    ///     });
+    ///     tokio::spawn(async move {
+    ///         if let Err(error) = work.await {
+    ///             eprintln!("background work failed: {error}");
+    ///         }
+    ///     });
+    ///     StatusCode::ACCEPTED
+    /// }
+    /// ```
+    ///
+    /// The [`Service`](crate::Service) middleware doesn't resolve a detached transaction, whatever
+    /// the response, and [`Layer::leak_check`](crate::Layer::leak_check) doesn't report it. The
+    /// [`on_commit`](Self::on_commit) futures are run once the future has committed the
+    /// transaction. Committing is the same as [`commit`](Self::commit), e.g. it fails with
+    /// [`Error::Aborted`] if the transaction was aborted, and does nothing with
+    /// [`Layer::dry_run`](crate::Layer::dry_run). If the future is dropped before it's done, the
+    /// transaction is rolled back.
+    pub fn detach<T, TE, F>(self, f: F) -> impl Future<Output = Result<T, TE>> + Send + 'static
+    where
+        C: Send + Sync + 'static,
+        E: Send + Sync + 'static,
+        I: Send + Sync + 'static,
+        T: Send + 'static,
+        TE: From<DbErr> + Send + 'static,
+        F: for<'a> FnOnce(&'a mut Self) -> BoxFuture<'a, Result<T, TE>> + Send + 'static,
+    {
+        self.state.detached.store(true, Ordering::Relaxed);
+        let mut tx = self;
+        async move {
+            match f(&mut tx).await {
+                Ok(value) => {
+                    tx.commit().await?;
+                    Ok(value)
+                }
+                Err(error) => {
+                    tx.state.resolved.store(true, Ordering::Relaxed);
+                    #[allow(unused_variables)]
+                    if let Err(error) = tx.options.rollback(tx.tx.steal()).await {
+                        #[cfg(feature = "tracing")]
+                        tracing::warn!(%error, "failed to roll back detached transaction");
+                    }
+                    Err(error)
+                }
+            }
+        }
+    }
+}
+
+impl<C, E, I> Tx<C, E, I>
+where
+    C: TransactionTrait + Send + Sync + 'static,
+    E: Send + Sync + 'static,
+    I: Send + Sync + 'static,
+{
+    /// Stream the results of `stmt`, keeping the transaction open until the stream completes.
+    ///
+    /// Rows are converted to `T` as they're read, and the database is only read as fast as the
+    /// stream is consumed. The returned [`TxStream`] owns the transaction, so it can be used as (or
+    /// mapped into) a streaming response body:
+    ///
+    /// ```
+    /// use axum::body::StreamBody;
+    /// use axum_sea_orm_tx::Tx;
+    /// use futures_util::TryStreamExt;
+    /// use sea_orm::{ConnectionTrait, FromQueryResult, Statement};
+    ///
+    /// #[derive(FromQueryResult)]
+    /// struct Number {
+    ///     number: i32,
+    /// }
+    ///
+    /// async fn handler(tx: Tx<sea_orm::DatabaseConnection>) -> impl axum::response::IntoResponse {
+    ///     let stmt = Statement::from_string(tx.get_database_backend(), "SELECT ...".to_string());
+    ///     let numbers = tx
+    ///         .stream_response::<Number>(stmt)
+    ///         .map_ok(|Number { number }| format!("{number}\n"));
+    ///     StreamBody::new(numbers)
+    /// }
+    /// ```
+    ///
+    /// When the transaction is still held by a stream in the response body, the
+    /// [`Service`](crate::Service) middleware waits until the body has been fully sent before
+    /// committing. If the body is dropped before it completes (e.g. because the client
+    /// disconnected), or if the stream yields an error, the transaction is rolled back instead.
+    pub fn stream_response<T>(self, stmt: Statement) -> TxStream<T>
+    where
+        T: FromQueryResult + Send + 'static,
+    {
+        TxStream::new(self, stmt)
+    }
+
+    /// Build the response with the transaction, after the handler has returned.
+    ///
+    /// Responses are usually converted with [`IntoResponse`] once the handler has returned (and
+    /// its `Tx` has been dropped), and `into_response` can't run queries anyway. `respond_with`
+    /// returns a placeholder [`TxResponse`] instead, which the [`Service`](crate::Service)
+    /// middleware renders by calling `f` with the transaction, still open, once the inner service
+    /// has returned it:
+    ///
+    /// ```
+    /// use axum::Json;
+    /// use axum_sea_orm_tx::{Tx, TxResponse};
+    /// use sea_orm::{ConnectionTrait, DatabaseConnection, Statement};
+    ///
+    /// async fn handler(tx: Tx<DatabaseConnection>) -> TxResponse {
+    ///     /* ... */
+    ///     tx.respond_with(|tx| async move {
+    ///         let stmt = Statement::from_string(tx.backend(), "SELECT ...".to_string());
+    ///         let rows = tx.query_all(stmt).await.map_err(|error| error.to_string())?;
+    ///         Ok::<_, String>(Json(rows.len()))
+    ///     })
+    /// }
+    /// ```
+    ///
+    /// The order of events is then:
+    ///
+    /// 1. the handler returns, and the placeholder goes through any middleware inside the
+    ///    [`Layer`](crate::Layer);
+    /// 2. the `Service` renders the response with `f`;
+    /// 3. the transaction is resolved based on the rendered response, as usual (so `f` can also
+    ///    return a streaming body that holds on to the transaction, see
+    ///    [`stream_response`](Self::stream_response)).
+    ///
+    /// Middleware inside the layer only sees the placeholder, which is an empty `200 OK`. The
+    /// rendered response replaces it, except for its extensions, so wrapping the `TxResponse` in
+    /// [`Commit`](crate::Commit) or [`Rollback`](crate::Rollback) still works.
+    pub fn respond_with<F, Fut, R>(self, f: F) -> TxResponse
+    where
+        F: FnOnce(Self) -> Fut + Send + 'static,
+        Fut: Future<Output = R> + Send + 'static,
+        R: IntoResponse,
+    {
+        TxResponse::new(Box::pin(async move { f(self).await.into_response() }))
+    }
+}
+
+// Statements run directly on the `DatabaseTransaction` can't be seen, so accessing it counts as a
+// write for `Layer::rollback_readonly`.
+
+impl<C: TransactionTrait, E, I> AsRef<DatabaseTransaction> for Tx<C, E, I> {
+    fn as_ref(&self) -> &DatabaseTransaction {
+        self.note_write();
+        self.tx.as_transaction()
+    }
+}
+
+impl<C: TransactionTrait, E, I> AsMut<DatabaseTransaction> for Tx<C, E, I> {
+    fn as_mut(&mut self) -> &mut DatabaseTransaction {
+        self.note_write();
+        self.tx.as_transaction_mut()
+    }
+}
+
+impl<C: TransactionTrait, E, I> std::ops::Deref for Tx<C, E, I> {
+    type Target = DatabaseTransaction;
+
+    fn deref(&self) -> &Self::Target {
+        self.note_write();
+        self.tx.as_transaction()
+    }
+}
+
+impl<C: TransactionTrait, E, I> std::ops::DerefMut for Tx<C, E, I> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        self.note_write();
+        self.tx.as_transaction_mut()
+    }
+}
+
+impl<C: TransactionTrait + Sync, E: Sync, I: Sync> ConnectionTrait for Tx<C, E, I> {
+    fn get_database_backend(&self) -> sea_orm::DbBackend {
+        self.backend
+    }
+
+    fn support_returning(&self) -> bool {
+        self.tx.support_returning()
+    }
+
+    fn is_mock_connection(&self) -> bool {
+        self.tx.is_mock_connection()
+    }
+
+    fn execute<'life0, 'async_trait>(
+        &'life0 self,
+        stmt: sea_orm::Statement,
+    ) -> core::pin::Pin<
+        Box<
+            dyn core::future::Future<Output = Result<sea_orm::ExecResult, DbErr>>
+                + core::marker::Send
+                + 'async_trait,
+        >,
+    >
+    where
+        'life0: 'async_trait,
+        Self: 'async_trait,
+    {
+        if let Err(error) = self.count_statement() {
+            return Box::pin(async { Err(error) });
+        }
+        let stmt = self.prepare(stmt);
+        let explainable = self.explainable(&stmt);
+        Box::pin(async move {
+            let started = Instant::now();
+            let result = self.tx.execute(stmt).await;
+            self.finish(result, explainable, started).await
+        })
+    }
+
+    fn execute_unprepared<'life0, 'life1, 'async_trait>(
+        &'life0 self,
+        sql: &'life1 str,
+    ) -> core::pin::Pin<
+        Box<
+            dyn core::future::Future<Output = Result<sea_orm::ExecResult, DbErr>>
+                + core::marker::Send
+                + 'async_trait,
+        >,
+    >
+    where
+        'life0: 'async_trait,
+        'life1: 'async_trait,
+        Self: 'async_trait,
+    {
+        if let Err(error) = self.count_statement() {
+            return Box::pin(async { Err(error) });
+        }
+        #[cfg(feature = "audit")]
+        if let Some(audit) = &self.options.audit {
+            audit.record_sql(sql.to_string(), Vec::new());
+        }
+        self.note_statement(sql);
+        let explainable = self.explainable(&Statement::from_string(self.backend, sql.to_string()));
+        Box::pin(async move {
+            let started = Instant::now();
+            let result = self.tx.execute_unprepared(sql).await;
+            self.finish(result, explainable, started).await
+        })
+    }
+
+    fn query_one<'life0, 'async_trait>(
+        &'life0 self,
+        stmt: sea_orm::Statement,
+    ) -> core::pin::Pin<
+        Box<
+            dyn core::future::Future<Output = Result<Option<sea_orm::QueryResult>, DbErr>>
+                + core::marker::Send
+                + 'async_trait,
+        >,
+    >
+    where
+        'life0: 'async_trait,
+        Self: 'async_trait,
+    {
+        if let Err(error) = self.count_statement() {
+            return Box::pin(async { Err(error) });
+        }
+        let stmt = self.prepare(stmt);
+        let explainable = self.explainable(&stmt);
+        Box::pin(async move {
+            let started = Instant::now();
+            let result = self.tx.query_one(stmt).await;
+            self.finish(result, explainable, started).await
+        })
+    }
+
+    fn query_all<'life0, 'async_trait>(
+        &'life0 self,
+        stmt: sea_orm::Statement,
+    ) -> core::pin::Pin<
+        Box<
+            dyn core::future::Future<Output = Result<Vec<sea_orm::QueryResult>, DbErr>>
+                + core::marker::Send
+                + 'async_trait,
+        >,
+    >
+    where
+        'life0: 'async_trait,
+        Self: 'async_trait,
+    {
+        if let Err(error) = self.count_statement() {
+            return Box::pin(async { Err(error) });
+        }
+        let stmt = self.prepare(stmt);
+        let explainable = self.explainable(&stmt);
+        Box::pin(async move {
+            let started = Instant::now();
+            let result = self.tx.query_all(stmt).await;
+            self.finish(result, explainable, started).await
+        })
+    }
+}
+
+impl<C: TransactionTrait + Send + Sync, E: Send + Sync, I: Send + Sync> StreamTrait
+    for Tx<C, E, I>
+{
+    type Stream<'a>
+        = <DatabaseTransaction as StreamTrait>::Stream<'a>
+    where
+        E: 'a,
+        C: 'a,
+        I: 'a;
+
+    fn stream<'a>(
+        &'a self,
+        stmt: sea_orm::Statement,
+    ) -> std::pin::Pin<
+        Box<dyn futures_core::Future<Output = Result<Self::Stream<'a>, DbErr>> + 'a + Send>,
+    > {
+        if let Err(error) = self.count_statement() {
+            return Box::pin(async { Err(error) });
+        }
+        self.tx.stream(self.prepare(stmt))
+    }
+}
+
+impl<C: TransactionTrait, E, I> TransactionTrait for Tx<C, E, I> {
+    fn begin<'life0, 'async_trait>(
+        &'life0 self,
+    ) -> core::pin::Pin<
+        Box<
+            dyn core::future::Future<Output = Result<DatabaseTransaction, DbErr>>
+                + core::marker::Send
+                + 'async_trait,
+        >,
+    >
+    where
+        'life0: 'async_trait,
+        Self: 'async_trait,
+    {
+        if let Err(error) = self.check_nesting() {
+            return Box::pin(async { Err(error) });
+        }
+        self.note_write();
+        self.tx.begin()
+    }
+
+    fn begin_with_config<'life0, 'async_trait>(
+        &'life0 self,
+        isolation_level: Option<sea_orm::IsolationLevel>,
+        access_mode: Option<sea_orm::AccessMode>,
+    ) -> core::pin::Pin<
+        Box<
+            dyn core::future::Future<Output = Result<DatabaseTransaction, DbErr>>
+                + core::marker::Send
+                + 'async_trait,
+        >,
+    >
+    where
+        'life0: 'async_trait,
+        Self: 'async_trait,
+    {
+        if let Err(error) = self.check_nesting() {
+            return Box::pin(async { Err(error) });
+        }
+        self.note_write();
+        self.tx.begin_with_config(isolation_level, access_mode)
+    }
+
+    fn transaction<'life0, 'async_trait, F, T, TE>(
+        &'life0 self,
+        callback: F,
+    ) -> core::pin::Pin<
+        Box<
+            dyn core::future::Future<Output = Result<T, sea_orm::TransactionError<TE>>>
+                + core::marker::Send
+                + 'async_trait,
+        >,
+    >
+    where
+        F: for<'c> FnOnce(
+                &'c DatabaseTransaction,
+            ) -> std::pin::Pin<
+                Box<dyn futures_core::Future<Output = Result<T, TE>> + Send + 'c>,
+            > + Send,
+        T: Send,
+        TE: std::error::Error + Send,
+        F: 'async_trait,
+        T: 'async_trait,
+        TE: 'async_trait,
+        'life0: 'async_trait,
+        Self: 'async_trait,
+    {
+        if let Err(error) = self.check_nesting() {
+            return Box::pin(async { Err(sea_orm::TransactionError::Connection(error)) });
+        }
+        self.note_write();
+        self.tx.transaction(callback)
+    }
+
+    fn transaction_with_config<'life0, 'async_trait, F, T, TE>(
+        &'life0 self,
+        callback: F,
+        isolation_level: Option<sea_orm::IsolationLevel>,
+        access_mode: Option<sea_orm::AccessMode>,
+    ) -> core::pin::Pin<
+        Box<
+            dyn core::future::Future<Output = Result<T, sea_orm::TransactionError<TE>>>
+                + core::marker::Send
+                + 'async_trait,
+        >,
+    >
+    where
+        F: for<'c> FnOnce(
+                &'c DatabaseTransaction,
+            ) -> std::pin::Pin<
+                Box<dyn futures_core::Future<Output = Result<T, TE>> + Send + 'c>,
+            > + Send,
+        T: Send,
+        TE: std::error::Error + Send,
+        F: 'async_trait,
+        T: 'async_trait,
+        TE: 'async_trait,
+        'life0: 'async_trait,
+        Self: 'async_trait,
+    {
+        if let Err(error) = self.check_nesting() {
+            return Box::pin(async { Err(sea_orm::TransactionError::Connection(error)) });
+        }
+        self.note_write();
+        self.tx
+            .transaction_with_config(callback, isolation_level, access_mode)
+    }
+}
+
+#[async_trait]
+impl<C, S, E, I> FromRequestParts<S> for Tx<C, E, I>
+where
+    C: TransactionTrait + Clone + Send + Sync + 'static,
+    S: Sync,
+    E: From<Error> + IntoResponse,
+    I: IsolationMarker,
+{
+    type Rejection = E;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        Ok(Self::from_lazy(parts, None, false).await?)
+    }
+}
+
+impl<C, E, I> Tx<C, E, I>
+where
+    C: TransactionTrait + Clone + Send + Sync + 'static,
+    I: IsolationMarker,
+{
+    /// Get the transaction of the [`Layer`](crate::Layer) constructed with
+    /// [`Layer::with_key`](crate::Layer::with_key) and `key`, from the head of the request.
+    ///
+    /// This is for when the number of databases isn't known statically, so a layer (and
+    /// transaction) per database can't be told apart by the type of the pool. The handler needs
+    /// the request head to look the transaction up, e.g. by extracting [`Parts`] (which must be the
+    /// last argument, since it takes the request):
+    ///
+    /// ```
+    /// use axum_sea_orm_tx::Tx;
+    /// use http::request::Parts;
+    /// use sea_orm::{ConnectionTrait, DatabaseConnection};
+    ///
+    /// async fn handler(mut parts: Parts) -> Result<(), axum_sea_orm_tx::Error> {
+    ///     let analytics = Tx::<DatabaseConnection>::keyed(&mut parts, "analytics").await?;
+    ///     analytics.execute_unprepared("INSERT INTO events DEFAULT VALUES").await?;
+    ///     Ok(())
+    /// }
+    /// ```
+    ///
+    /// As with extracting [`Tx`], there can only be one `Tx` for each key at a time. Fails with
+    /// [`Error::MissingExtension`] if there's no layer with the given key.
+    pub async fn keyed(parts: &mut Parts, key: &str) -> Result<Self, E>
+    where
+        E: From<Error>,
+    {
+        Ok(Self::from_lazy(parts, Some(key), false).await?)
+    }
+
+    /// Begin the request's transaction from its head, outside of axum's extraction.
+    ///
+    /// This is for middleware that wants the transaction begun (e.g. to reserve a connection, or
+    /// to run setup statements) before the handler runs. If the transaction hasn't been begun yet,
+    /// it's begun with the settings from `I`, otherwise the existing transaction is returned (as
+    /// long as it satisfies `I`). Unlike extracting `Tx`, errors are always returned as [`Error`]:
+    ///
+    /// - [`Error::MissingExtension`] if the [`Layer`](crate::Layer) isn't installed;
+    /// - [`Error::OverlappingExtractors`] if the transaction is already held by another `Tx`;
+    /// - [`Error::ConflictingIsolation`] if the transaction was begun with other settings than
+    ///   `I`'s;
+    /// - [`Error::Database`] if beginning the transaction failed.
+    ///
+    /// The returned `Tx` must be dropped before the handler extracts its own, or the handler will
+    /// get [`Error::OverlappingExtractors`]:
+    ///
+    /// ```
+    /// use axum::{http::Request, middleware::Next, response::Response};
+    /// use axum_sea_orm_tx::Tx;
+    /// use sea_orm::{ConnectionTrait, DatabaseConnection};
+    ///
+    /// async fn set_timeout<B>(req: Request<B>, next: Next<B>) -> Result<Response, axum_sea_orm_tx::Error> {
+    ///     let (mut parts, body) = req.into_parts();
+    ///     let tx = Tx::<DatabaseConnection>::begin_for(&mut parts).await?;
+    ///     tx.execute_unprepared("SET LOCAL statement_timeout = 1000").await?;
+    ///     drop(tx);
+    ///
+    ///     Ok(next.run(Request::from_parts(parts, body)).await)
+    /// }
+    /// ```
+    pub async fn begin_for(parts: &mut Parts) -> Result<Self, Error> {
+        Self::from_lazy(parts, None, false).await
+    }
+
+    /// Get the transaction from the request's [`Lazy`], beginning it if necessary.
+    ///
+    /// Fails with [`Error::Autocommit`] if it fell back to autocommit mode, unless `autocommit`
+    /// (i.e. it's for an [`AutocommitTx`]), since a `Tx` must dereference to a transaction.
+    async fn from_lazy(
+        parts: &mut Parts,
+        key: Option<&str>,
+        autocommit: bool,
+    ) -> Result<Self, Error> {
+        let ext = Lazy::<C>::begin::<I>(parts, key).await?;
+        let begun = ext
+            .tx
+            .as_mut()
+            .as_mut()
+            .expect("BUG: transaction not begun");
+        let Some(tx) = begun.conn.lease() else {
+            return Err(overlapping_extractors(parts));
+        };
+
+        let mut options = begun.options.clone();
+        if !autocommit {
+            if let Connection::Autocommit(_) = *tx {
+                return Err(Error::Autocommit);
+            }
+            // Nor can the transactions begun by `commit_and_begin` etc. fall back
+            options.autocommit_fallback = None;
+        }
+        Ok(Self::new(
+            tx,
+            ext.pool.clone(),
+            options,
+            begun.state.clone(),
+            begun.begun_at,
+        ))
+    }
+}
+
+/// An extractor that begins the request's transaction without using it.
+///
+/// Normally the transaction is begun lazily, the first time [`Tx`] is extracted, so a handler that
+/// never extracts `Tx` never begins a transaction. Extracting `EagerTx` guarantees that `BEGIN` has
+/// happened by the time extraction returns (e.g. to reserve a connection before doing other work),
+/// and the transaction is resolved as usual when the response is returned.
+///
+/// `EagerTx` doesn't hold on to the transaction, so it can be used alongside [`Tx`] in the same
+/// handler:
+///
+/// ```
+/// use axum_sea_orm_tx::{EagerTx, Tx};
+/// use sea_orm::DatabaseConnection;
+///
+/// async fn handler(_: EagerTx<DatabaseConnection>, tx: Tx<DatabaseConnection>) {
+///     /* ... */
+/// }
+/// ```
+#[derive(Debug)]
+pub struct EagerTx<C: TransactionTrait, E = Error> {
+    _marker: PhantomData<(C, E)>,
+}
+
+#[async_trait]
+impl<C: TransactionTrait + Send + Sync + 'static, S: Sync, E> FromRequestParts<S> for EagerTx<C, E>
+where
+    E: From<Error> + IntoResponse,
+{
+    type Rejection = E;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        Lazy::<C>::begin::<DefaultIsolation>(parts, None).await?;
+
+        Ok(Self {
+            _marker: PhantomData,
+        })
+    }
+}
+
+/// An extractor for the request's transaction, if there is one, that's only begun when it's used.
+///
+/// Extracting [`Tx`] fails with [`Error::MissingExtension`] when the [`Layer`](crate::Layer) isn't
+/// installed, or the request's path is skipped (see [`Layer::skip_paths`](crate::Layer::skip_paths)).
+/// Extracting `OptionalTx` always succeeds, which is useful for handlers shared between apps that
+/// sometimes run without a database. The transaction is begun by [`begin`](Self::begin), which
+/// returns `None` if there's no transaction to begin:
+///
+/// ```
+/// use axum_sea_orm_tx::OptionalTx;
+/// use sea_orm::{ConnectionTrait, DatabaseConnection};
+///
+/// async fn handler(tx: OptionalTx<DatabaseConnection>) -> Result<(), axum_sea_orm_tx::Error> {
+///     if let Some(tx) = tx.begin().await? {
+///         tx.execute_unprepared("INSERT INTO visits DEFAULT VALUES").await?;
+///     }
+///     Ok(())
+/// }
+/// ```
+///
+/// `OptionalTx` takes the transaction out of the request, so it can't be used alongside [`Tx`] in
+/// the same handler: a `Tx` extracted after it fails with [`Error::MissingExtension`]. Only the
+/// layer without a key (see [`Layer::with_key`](crate::Layer::with_key)) is looked up.
+#[derive(Debug)]
+pub struct OptionalTx<C: TransactionTrait = DatabaseConnection, E = Error, I = DefaultIsolation> {
+    /// A copy of the request head, holding the request's [`Lazy`] (if any).
+    parts: Option<Parts>,
+    _marker: PhantomData<(C, E, I)>,
+}
+
+#[async_trait]
+impl<C, S, E, I> FromRequestParts<S> for OptionalTx<C, E, I>
+where
+    C: TransactionTrait + Clone + Send + Sync + 'static,
+    S: Sync,
+    E: From<Error> + IntoResponse,
+    I: IsolationMarker,
+{
+    type Rejection = E;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        let Some(lazy) = parts.extensions.remove::<Lazy<C>>() else {
+            return Ok(Self {
+                parts: None,
+                _marker: PhantomData,
+            });
+        };
+
+        // Only the head is needed to begin the transaction, so keep a copy to begin it later
+        let (mut head, ()) = http::Request::new(()).into_parts();
+        head.method = parts.method.clone();
+        head.uri = parts.uri.clone();
+        head.version = parts.version;
+        head.headers = parts.headers.clone();
+        if let Some(path) = parts.extensions.get::<MatchedPath>() {
+            head.extensions.insert(path.clone());
+        }
+        if let Some(config) = parts.extensions.get::<TxConfig>() {
+            head.extensions.insert(*config);
+        }
+        head.extensions.insert(lazy);
+
+        Ok(Self {
+            parts: Some(head),
+            _marker: PhantomData,
+        })
+    }
+}
+
+impl<C, E, I> OptionalTx<C, E, I>
+where
+    C: TransactionTrait + Clone + Send + Sync + 'static,
+    I: IsolationMarker,
+{
+    /// Whether there's a transaction to begin, i.e. whether [`begin`](Self::begin) returns
+    /// `Some`, unless beginning the transaction fails.
+    pub fn is_available(&self) -> bool {
+        self.parts.is_some()
+    }
+
+    /// Begin the request's transaction, or return `None` if the request doesn't have one.
+    ///
+    /// The transaction is resolved by the [`Service`](crate::Service) middleware as usual.
+    pub async fn begin(self) -> Result<Option<Tx<C, E, I>>, E>
+    where
+        E: From<Error>,
+    {
+        let Some(mut parts) = self.parts else {
+            return Ok(None);
+        };
+        Ok(Some(Tx::from_lazy(&mut parts, None, false).await?))
+    }
+}
+
+/// An extractor for the request's transaction that also accepts autocommit mode.
+///
+/// With [`Layer::autocommit_fallback`](crate::Layer::autocommit_fallback), the request's
+/// "transaction" may be running each statement directly on the pool, with no
+/// [`sea_orm::DatabaseTransaction`] for [`Tx`] to dereference to, so extracting `Tx` then fails
+/// with [`Error::Autocommit`]. Handlers that can cope with either mode extract `AutocommitTx`
+/// instead, which only implements [`ConnectionTrait`]:
+///
+/// ```
+/// use axum_sea_orm_tx::AutocommitTx;
+/// use sea_orm::{ConnectionTrait, DatabaseConnection, DbErr};
+///
+/// async fn handler(tx: AutocommitTx<DatabaseConnection>) -> Result<(), DbErr> {
+///     tx.execute_unprepared("INSERT INTO events DEFAULT VALUES").await?;
+///     Ok(())
+/// }
+/// ```
+///
+/// If the transaction could be begun, it's resolved by the [`Service`](crate::Service)
+/// middleware as usual, and the same rules apply as for `Tx`, e.g. there can only be one of
+/// either at a time.
+#[derive(Debug)]
+pub struct AutocommitTx<C: TransactionTrait = DatabaseConnection, E = Error>(Tx<C, E>);
+
+#[async_trait]
+impl<C, S, E> FromRequestParts<S> for AutocommitTx<C, E>
+where
+    C: TransactionTrait + Clone + Send + Sync + 'static,
+    S: Sync,
+    E: From<Error> + IntoResponse,
+{
+    type Rejection = E;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        Ok(Self(Tx::from_lazy(parts, None, true).await?))
+    }
+}
+
+impl<C: TransactionTrait, E> AutocommitTx<C, E> {
+    /// Whether statements are committed as they run, since the transaction fell back to
+    /// autocommit mode.
+    pub fn is_autocommit(&self) -> bool {
+        matches!(*self.0.tx, Connection::Autocommit(_))
+    }
+
+    /// Commit the transaction, as with [`Tx::commit`]. This does nothing in autocommit mode.
+    pub async fn commit(self) -> Result<(), DbErr> {
+        self.0.commit().await
+    }
+
+    /// Make the writes run so far visible to other connections, as with [`Tx::flush`]. In
+    /// autocommit mode every statement is committed as it runs, so this does nothing.
+    pub async fn flush(&mut self) -> Result<(), DbErr> {
+        self.0.flush().await
+    }
+}
+
+#[async_trait]
+impl<C, E> ConnectionTrait for AutocommitTx<C, E>
+where
+    C: TransactionTrait + Sync,
+    E: Sync,
+{
+    fn get_database_backend(&self) -> DbBackend {
+        self.0.get_database_backend()
+    }
+
+    fn support_returning(&self) -> bool {
+        self.0.support_returning()
+    }
+
+    async fn execute(&self, stmt: Statement) -> Result<ExecResult, DbErr> {
+        self.0.execute(stmt).await
+    }
+
+    async fn execute_unprepared(&self, sql: &str) -> Result<ExecResult, DbErr> {
+        self.0.execute_unprepared(sql).await
+    }
+
+    async fn query_one(&self, stmt: Statement) -> Result<Option<QueryResult>, DbErr> {
+        self.0.query_one(stmt).await
+    }
+
+    async fn query_all(&self, stmt: Statement) -> Result<Vec<QueryResult>, DbErr> {
+        self.0.query_all(stmt).await
+    }
+}
+
+/// A borrow of the request's transaction, for tower services that aren't axum handlers.
+///
+/// Middleware below the [`Layer`](crate::Layer) can use the request's transaction without going
+/// through axum's extractors, by borrowing it from the request. The borrow dereferences to a
+/// [`Tx`], and returns the transaction when it's dropped, so it must be dropped before calling
+/// the inner service (which can then extract `Tx` as usual, without
+/// [`Error::OverlappingExtractors`]):
+///
+/// ```
+/// use axum_sea_orm_tx::TxBorrow;
+/// use sea_orm::{ConnectionTrait, DatabaseConnection};
+///
+/// async fn record_request<B>(req: &mut http::Request<B>) -> Result<(), axum_sea_orm_tx::Error> {
+///     let tx = TxBorrow::<DatabaseConnection>::from_request(req).await?;
+///     tx.execute_unprepared("INSERT INTO requests DEFAULT VALUES").await?;
+///     Ok(())
+/// }
+/// ```
+///
+/// Like `Tx`, this begins the transaction if it hasn't been already. The transaction is resolved
+/// by the [`Service`](crate::Service) middleware as usual, once the response is ready.
+pub struct TxBorrow<C: TransactionTrait = DatabaseConnection>(Tx<C>);
+
+impl<C: TransactionTrait + Clone + Send + Sync + 'static> TxBorrow<C> {
+    /// Borrow the transaction of the request with head `parts`.
+    pub async fn from_parts(parts: &mut Parts) -> Result<Self, Error> {
+        Tx::<C>::from_request_parts(parts, &()).await.map(Self)
+    }
+
+    /// Borrow the transaction of `req`.
+    pub async fn from_request<B>(req: &mut http::Request<B>) -> Result<Self, Error> {
+        // Only the head is needed, so move it into `Parts` temporarily rather than taking the body
+        let (mut parts, ()) = http::Request::new(()).into_parts();
+        parts.method = req.method().clone();
+        parts.uri = req.uri().clone();
+        parts.version = req.version();
+        parts.headers = std::mem::take(req.headers_mut());
+        parts.extensions = std::mem::take(req.extensions_mut());
+
+        let result = Self::from_parts(&mut parts).await;
+
+        *req.headers_mut() = parts.headers;
+        *req.extensions_mut() = parts.extensions;
+        result
+    }
+}
+
+impl<C: TransactionTrait> std::ops::Deref for TxBorrow<C> {
+    type Target = Tx<C>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+impl<C: TransactionTrait> std::ops::DerefMut for TxBorrow<C> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.0
+    }
+}
+
+/// An extractor for a consistent, read-only snapshot of the database.
+///
+/// The request's transaction is begun `REPEATABLE READ` and `READ ONLY` (see [`Snapshot`]), so
+/// every query sees the database as it was at the first one, whatever other transactions commit
+/// in the meantime. This is useful for endpoints that make several reads which must agree with
+/// each other, e.g. a page of results and their total count:
+///
+/// ```
+/// use axum_sea_orm_tx::SnapshotTx;
+/// use sea_orm::{ConnectionTrait, DatabaseConnection};
+///
+/// async fn handler(tx: SnapshotTx<DatabaseConnection>) -> Result<(), axum_sea_orm_tx::Error> {
+///     tx.execute_unprepared("SELECT * FROM users LIMIT 10").await?;
+///     tx.execute_unprepared("SELECT COUNT(*) FROM users").await?;
+///     Ok(())
+/// }
+/// ```
+///
+/// The transaction is always rolled back, whatever the response, with
+/// [`RollbackReason::Snapshot`](crate::RollbackReason::Snapshot). `SnapshotTx` dereferences to
+/// the underlying [`Tx`], and the same rules apply, e.g. it conflicts with a [`Tx`] extracted
+/// earlier in the request (with [`Error::ConflictingIsolation`]), and a `Tx` extracted after it
+/// shares the snapshot (and is rolled back too).
+///
+/// Note that SQLite doesn't support per-transaction settings, so there the transaction is only
+/// guaranteed to be rolled back.
+#[derive(Debug)]
+pub struct SnapshotTx<C: TransactionTrait = DatabaseConnection, E = Error>(Tx<C, E, Snapshot>);
+
+#[async_trait]
+impl<C, S, E> FromRequestParts<S> for SnapshotTx<C, E>
+where
+    C: TransactionTrait + Clone + Send + Sync + 'static,
+    S: Sync,
+    E: From<Error> + IntoResponse,
+{
+    type Rejection = E;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let tx = Tx::<C, E, Snapshot>::from_request_parts(parts, state).await?;
+        tx.state.snapshot.store(true, Ordering::Relaxed);
+        Ok(Self(tx))
+    }
+}
+
+impl<C, E> SnapshotTx<C, E>
+where
+    C: TransactionTrait + Send + Sync,
+    E: Send + Sync,
+{
+    /// Run `stmt` and collect the rows it returns until `deadline` has passed, then stop.
+    ///
+    /// This is for best-effort reads (e.g. dashboards), where showing some of the results is better
+    /// than failing the request. If the deadline passes, the rows fetched so far are returned with
+    /// [`truncated`](PartialRows::truncated) set, rather than an error:
+    ///
+    /// ```
+    /// use std::time::Duration;
+    /// use axum_sea_orm_tx::SnapshotTx;
+    /// use sea_orm::{DbBackend, DbErr, FromQueryResult, Statement};
+    ///
+    /// #[derive(FromQueryResult)]
+    /// struct Event {
+    ///     name: String,
+    /// }
+    ///
+    /// async fn handler(tx: SnapshotTx) -> Result<String, axum_sea_orm_tx::Error> {
+    ///     let stmt = Statement::from_string(DbBackend::Postgres, "SELECT name FROM events".into());
+    ///     let events = tx.stream_best_effort::<Event>(stmt, Duration::from_secs(1)).await?;
+    ///     Ok(format!("{} events (truncated: {})", events.rows.len(), events.truncated))
+    /// }
+    /// ```
+    ///
+    /// The `SnapshotTx` is consumed, since the statement may still be running when the deadline
+    /// passes. The transaction is rolled back by the [`Service`](crate::Service) middleware as
+    /// usual, once the handler returns. On Postgres, the transaction's `statement_timeout` is also
+    /// set to `deadline`, so the database stops the statement rather than finishing it before the
+    /// transaction can be rolled back. Other backends don't have a per-transaction timeout, so
+    /// rolling back may wait for the statement to finish.
+    ///
+    /// # Panics
+    ///
+    /// Panics if there's no runtime to time the deadline with (see
+    /// [`Layer::runtime`](crate::Layer::runtime)).
+    pub async fn stream_best_effort<T: FromQueryResult>(
+        self,
+        stmt: Statement,
+        deadline: Duration,
+    ) -> Result<PartialRows<T>, DbErr> {
+        let tx = self.0;
+        let sleep = (tx.options.sleep).expect("stream_best_effort requires a runtime");
+        let started = Instant::now();
+        let mut timer = sleep(deadline);
+
+        if tx.backend == DbBackend::Postgres {
+            tx.execute(Statement::from_sql_and_values(
+                DbBackend::Postgres,
+                "SELECT set_config('statement_timeout', $1, true)",
+                [format!("{}ms", deadline.as_millis().max(1)).into()],
+            ))
+            .await?;
+        }
+
+        let mut rows = Vec::new();
+        let mut stream = tx.stream(stmt).await?;
+        let truncated = loop {
+            match future::select(stream.next(), &mut timer).await {
+                Either::Left((Some(Ok(row)), _)) => rows.push(T::from_query_result(&row, "")?),
+                Either::Left((None, _)) => break false,
+                // e.g. Postgres cancelling the statement at the deadline
+                Either::Left((Some(Err(_)), _)) if started.elapsed() >= deadline => break true,
+                Either::Left((Some(Err(error)), _)) => return Err(error),
+                Either::Right(((), _)) => break true,
+            }
+        };
+
+        #[cfg(feature = "tracing")]
+        if truncated {
+            tracing::debug!(rows = rows.len(), ?deadline, "best-effort stream truncated");
+        }
+        Ok(PartialRows { rows, truncated })
+    }
+}
+
+impl<C: TransactionTrait, E> std::ops::Deref for SnapshotTx<C, E> {
+    type Target = Tx<C, E, Snapshot>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+impl<C: TransactionTrait, E> std::ops::DerefMut for SnapshotTx<C, E> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.0
+    }
+}
+
+/// A handle to the request's transaction that can be shared between tasks.
+///
+/// Created with [`Tx::into_shared`]. Handles can be cloned and sent to other tasks (e.g. with
+/// `tokio::spawn`), and implement [`ConnectionTrait`] so they can be used much like a [`Tx`].
+/// The transaction can only run one statement at a time, so each statement waits for any running
+/// on other handles (i.e. statements are serialized, not run concurrently). To run several
+/// statements without other handles' statements in between, or use the rest of `Tx`'s API, hold
+/// the lock from [`lock`](Self::lock).
+///
+/// The transaction is held until every handle has been dropped, so they should all be dropped
+/// (e.g. by awaiting the tasks that hold them) before the handler returns. If a handle is still
+/// alive when the response is ready, the transaction is treated like one held by a streaming
+/// response body (see [`Tx::stream_response`]): it's resolved once the body has been sent, and
+/// rolled back if a handle is still alive then.
+pub struct SharedTx<C: TransactionTrait = DatabaseConnection, E = Error, I = DefaultIsolation> {
+    backend: DbBackend,
+    tx: Arc<futures_util::lock::Mutex<Tx<C, E, I>>>,
+}
+
+impl<C: TransactionTrait, E, I> Clone for SharedTx<C, E, I> {
+    fn clone(&self) -> Self {
+        Self {
+            backend: self.backend,
+            tx: self.tx.clone(),
+        }
+    }
+}
+
+impl<C: TransactionTrait, E, I> std::fmt::Debug for SharedTx<C, E, I> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SharedTx")
+            .field("backend", &self.backend)
+            .field("handles", &Arc::strong_count(&self.tx))
+            .finish_non_exhaustive()
+    }
+}
+
+impl<C: TransactionTrait, E, I> SharedTx<C, E, I> {
+    /// Wait for exclusive access to the transaction.
+    ///
+    /// Statements run through other handles wait until the returned guard is dropped.
+    pub async fn lock(&self) -> impl std::ops::DerefMut<Target = Tx<C, E, I>> + '_ {
+        self.tx.lock().await
+    }
+
+    /// Run `future` with this transaction available from [`current_tx`](crate::current_tx).
+    ///
+    /// The handle is dropped as soon as `future` completes.
+    pub fn scope<F: Future>(&self, future: F) -> impl Future<Output = F::Output>
+    where
+        C: Send + 'static,
+        E: Send + 'static,
+        I: Send + 'static,
+    {
+        Scoped::new(self.clone(), future)
+    }
+}
+
+#[async_trait]
+impl<C, E, I> ConnectionTrait for SharedTx<C, E, I>
+where
+    C: TransactionTrait + Send + Sync,
+    E: Send + Sync,
+    I: Send + Sync,
+{
+    fn get_database_backend(&self) -> DbBackend {
+        self.backend
+    }
+
+    async fn execute(&self, stmt: Statement) -> Result<ExecResult, DbErr> {
+        self.tx.lock().await.execute(stmt).await
+    }
+
+    async fn execute_unprepared(&self, sql: &str) -> Result<ExecResult, DbErr> {
+        self.tx.lock().await.execute_unprepared(sql).await
+    }
+
+    async fn query_one(&self, stmt: Statement) -> Result<Option<QueryResult>, DbErr> {
+        self.tx.lock().await.query_one(stmt).await
+    }
+
+    async fn query_all(&self, stmt: Statement) -> Result<Vec<QueryResult>, DbErr> {
+        self.tx.lock().await.query_all(stmt).await
+    }
+}
+
+/// An extractor for the request's [`Tx`] along with the matched route path.
+///
+/// This is equivalent to extracting both [`Tx`] and [`axum::extract::MatchedPath`], for handlers
+/// that want to record which route wrote the data (e.g. in audit rows). The path is the pattern the
+/// route was registered with, like `/users/:id`, rather than the request path:
+///
+/// ```
+/// use axum_sea_orm_tx::TxWithRoute;
+/// use sea_orm::DatabaseConnection;
+///
+/// async fn handler(TxWithRoute(tx, route): TxWithRoute<DatabaseConnection>) {
+///     /* ... */
+/// }
+/// ```
+///
+/// Extraction fails with [`Error::MissingMatchedPath`] if the request wasn't routed to a path
+/// (e.g. in a fallback handler), in which case the transaction isn't begun.
+#[derive(Debug)]
+pub struct TxWithRoute<C: TransactionTrait = DatabaseConnection, E = Error>(
+    pub Tx<C, E>,
+    pub String,
+);
+
+#[async_trait]
+impl<C: TransactionTrait + Clone + Send + Sync + 'static, S: Sync, E> FromRequestParts<S>
+    for TxWithRoute<C, E>
+where
+    E: From<Error> + IntoResponse,
+{
+    type Rejection = E;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let route = parts
+            .extensions
+            .get::<MatchedPath>()
+            .ok_or(Error::MissingMatchedPath)?
+            .as_str()
+            .to_string();
+        let tx = Tx::<C, E>::from_request_parts(parts, state).await?;
+
+        Ok(Self(tx, route))
+    }
+}
+
+/// An extractor for the request's [`Tx`] along with its JSON body.
+///
+/// This is equivalent to extracting both [`Tx`] and [`axum::Json<T>`] (so `T` must implement
+/// `serde::de::DeserializeOwned`), in a single argument:
+///
+/// ```
+/// use axum_sea_orm_tx::TxJson;
+/// use sea_orm::DatabaseConnection;
+///
+/// #[derive(serde::Deserialize)]
+/// struct NewUser {
+///     name: String,
+/// }
+///
+/// async fn handler(TxJson(tx, user): TxJson<NewUser, DatabaseConnection>) {
+///     /* ... */
+/// }
+/// ```
+///
+/// The body is parsed first, so the transaction isn't begun if it's invalid. Either failure is
+/// reported with a [`TxJsonRejection`].
+#[derive(Debug)]
+pub struct TxJson<T, C: TransactionTrait = DatabaseConnection, E = Error>(pub Tx<C, E>, pub T);
+
+/// Rejection used for [`TxJson`].
+#[derive(Debug)]
+pub enum TxJsonRejection<E = Error> {
+    /// The request body couldn't be parsed as JSON.
+    Json(JsonRejection),
+
+    /// The [`Tx`] couldn't be extracted.
+    Tx(E),
+}
+
+impl<E: IntoResponse> IntoResponse for TxJsonRejection<E> {
+    fn into_response(self) -> axum_core::response::Response {
+        match self {
+            Self::Json(rejection) => rejection.into_response(),
+            Self::Tx(error) => error.into_response(),
+        }
+    }
+}
+
+#[async_trait]
+impl<T, C, S, B, E> FromRequest<S, B> for TxJson<T, C, E>
+where
+    Json<T>: FromRequest<S, B, Rejection = JsonRejection>,
+    T: Send,
+    C: TransactionTrait + Clone + Send + Sync + 'static,
+    S: Send + Sync,
+    B: Send + 'static,
+    E: From<Error> + IntoResponse,
+{
+    type Rejection = TxJsonRejection<E>;
+
+    async fn from_request(req: http::Request<B>, state: &S) -> Result<Self, Self::Rejection> {
+        let (mut parts, body) = req.into_parts();
+
+        // `Json` only looks at the headers, and consumes the request, so give it a copy
+        let mut body = http::Request::new(body);
+        *body.headers_mut() = parts.headers.clone();
+        let Json(value) = Json::<T>::from_request(body, state)
+            .await
+            .map_err(TxJsonRejection::Json)?;
+
+        let tx = Tx::<C, E>::from_request_parts(&mut parts, state)
+            .await
+            .map_err(TxJsonRejection::Tx)?;
+
+        Ok(Self(tx, value))
+    }
+}
+
+/// The OG `Slot` – the transaction (if any) returns here when the `Extension` is dropped.
+///
+/// With [`Layer::leak_check`](crate::Layer::leak_check), it also holds the route to report leaks
+/// for.
+pub(crate) struct TxSlot(Slot<Option<Begun>>, Option<LeakCheck>);
+
+/// Reporting of transactions that aren't resolved by the end of the request, see
+/// [`Layer::leak_check`](crate::Layer::leak_check).
+struct LeakCheck {
+    #[cfg_attr(not(feature = "tracing"), allow(dead_code))]
+    route: Option<String>,
+}
+
+impl LeakCheck {
+    /// Report a transaction whose connection wasn't returned to the slot, unless it was resolved
+    /// by the handler (i.e. report it if it's still `leased` by a `Tx`, or was taken without being
+    /// resolved), or detached with [`Tx::detach`].
+    #[cfg_attr(not(feature = "tracing"), allow(unused_variables))]
+    fn check(&self, leased: bool, state: &TxState) {
+        let accounted_for = state.detached.load(Ordering::Relaxed)
+            || !leased && state.resolved.load(Ordering::Relaxed);
+        #[cfg(feature = "tracing")]
+        if !accounted_for {
+            tracing::error!(
+                kind = "tx.leaked",
+                route = self.route.as_deref(),
+                leased,
+                "request transaction wasn't resolved by the end of the request; check for a Tx \
+                 that's kept beyond the handler (e.g. by a spawned task, or std::mem::forget)"
+            );
+        }
+    }
+}
+
+impl TxSlot {
+    /// Create a `TxSlot` bound to the given request extensions.
+    ///
+    /// When the request extensions are dropped, `commit` can be called to commit the transaction
+    /// (if any).
+    ///
+    /// If the extensions are already bound to a `TxSlot` for the same type of pool (e.g. because
+    /// the [`Layer`](crate::Layer) was applied twice), the existing binding is left alone and
+    /// `None` is returned, since replacing it could leak the existing transaction.
+    ///
+    /// If `read_only` is set, the transaction is begun in read-only mode (e.g. because `pool` is a
+    /// read replica). Layers with a key (see [`Layer::with_key`](crate::Layer::with_key)) are
+    /// bound separately for each key.
+    pub(crate) fn bind<C: TransactionTrait + Send + Sync + 'static>(
+        extensions: &mut http::Extensions,
+        pool: C,
+        read_only: bool,
+        config: Arc<Config>,
+        #[cfg(feature = "audit")] audit: Option<AuditLog>,
+    ) -> Option<Self> {
+        if Lazy::<C>::get(extensions, config.key.as_deref()).is_some() {
+            #[cfg(feature = "tracing")]
+            tracing::warn!(
+                "axum_sea_orm_tx::Layer applied more than once for the same pool type and key, \
+                the inner layer will be ignored"
+            );
+            return None;
+        }
+
+        let (slot, tx) = Slot::new_leased(None);
+        let leak_check = config.leak_check.then(|| LeakCheck {
+            route: extensions
+                .get::<MatchedPath>()
+                .map(|path| path.as_str().to_string()),
+        });
+        let key = config.key.clone();
+        let lazy = Lazy {
+            pool,
+            read_only,
+            config,
+            #[cfg(feature = "audit")]
+            audit,
+            tx,
+        };
+        match key {
+            None => {
+                extensions.insert(lazy);
+            }
+            Some(key) => {
+                if extensions.get::<KeyedLazy<C>>().is_none() {
+                    extensions.insert(KeyedLazy::<C>(HashMap::new()));
+                }
+                let keyed: &mut KeyedLazy<C> = extensions.get_mut().expect("BUG: just inserted");
+                keyed.0.insert(key, lazy);
+            }
+        }
+        Some(Self(slot, leak_check))
+    }
+
+    /// Check whether the transaction (if any) is still leased, e.g. by a [`TxStream`].
+    ///
+    /// Note that this is `false` if the request extensions have not been dropped yet.
+    ///
+    /// [`TxStream`]: crate::TxStream
+    pub(crate) fn is_leased(&mut self) -> bool {
+        self.0
+            .lease()
+            .is_some_and(|tx| tx.as_ref().as_ref().is_some_and(|tx| tx.conn.is_leased()))
+    }
+
+    /// Whether the transaction may have been written to, i.e. a [`Tx`] ran anything but a plain
+    /// `SELECT` (see [`Layer::rollback_readonly`](crate::Layer::rollback_readonly)).
+    ///
+    /// Note that this is `false` if the request extensions have not been dropped yet.
+    pub(crate) fn is_written(&mut self) -> bool {
+        self.0.lease().is_some_and(|tx| {
+            tx.as_ref()
+                .as_ref()
+                .is_some_and(|tx| tx.state.written.load(Ordering::Relaxed))
+        })
+    }
+
+    /// Whether the handler asked for the transaction to be committed whatever the response (see
+    /// [`Tx::commit_anyway`]).
+    ///
+    /// Note that this is `false` if the request extensions have not been dropped yet.
+    pub(crate) fn is_commit_anyway(&mut self) -> bool {
+        self.0.lease().is_some_and(|tx| {
+            tx.as_ref()
+                .as_ref()
+                .is_some_and(|tx| tx.state.commit_anyway.load(Ordering::Relaxed))
+        })
+    }
+
+    /// Whether the transaction was extracted as a [`SnapshotTx`], so must be rolled back.
+    ///
+    /// Note that this is `false` if the request extensions have not been dropped yet.
+    pub(crate) fn is_snapshot(&mut self) -> bool {
+        self.0.lease().is_some_and(|tx| {
+            tx.as_ref()
+                .as_ref()
+                .is_some_and(|tx| tx.state.snapshot.load(Ordering::Relaxed))
+        })
+    }
+
+    /// Whether the transaction was aborted through a [`TxRegistry`], so must be rolled back.
+    ///
+    /// Note that this is `false` if the request extensions have not been dropped yet.
+    pub(crate) fn is_aborted(&mut self) -> bool {
+        self.0.lease().is_some_and(|tx| {
+            tx.as_ref()
+                .as_ref()
+                .is_some_and(|tx| tx.state.aborted.load(Ordering::Relaxed))
+        })
+    }
+
+    /// Whether a statement failed in a way that aborted the transaction (see
+    /// [`Tx::is_aborted`]), so it must be rolled back.
+    ///
+    /// Note that this is `false` if the request extensions have not been dropped yet.
+    pub(crate) fn is_failed(&mut self) -> bool {
+        self.0.lease().is_some_and(|tx| {
+            tx.as_ref()
+                .as_ref()
+                .is_some_and(|tx| tx.state.failed.load(Ordering::Relaxed))
+        })
+    }
+
+    /// How many statements have been run through [`Tx`], if the transaction has been begun.
+    ///
+    /// Note that this is `None` if the request extensions have not been dropped yet.
+    pub(crate) fn statements(&mut self) -> Option<usize> {
+        self.0
+            .lease()?
+            .as_ref()
+            .as_ref()
+            .map(|tx| tx.state.statements.load(Ordering::Relaxed))
+    }
+
+    /// When the transaction was begun, if it has been.
+    ///
+    /// Note that this is `None` if the request extensions have not been dropped yet.
+    pub(crate) fn begun_at(&mut self) -> Option<Instant> {
+        self.0.lease()?.as_ref().as_ref().map(|tx| tx.begun_at)
+    }
+
+    /// Commit the transaction, if any, and then run its [`Tx::on_commit`] futures.
+    ///
+    /// Returns `Ok(true)` if a transaction was committed, or `Ok(false)` if there was nothing to
+    /// commit (e.g. the transaction was never begun, or was already resolved by the handler).
+    ///
+    /// If `deadline` passes (timed with its runtime's `sleep`) before the commit finishes, it's
+    /// abandoned with [`Error::CommitTimeout`], and the transaction is rolled back when the
+    /// connection is next used (as when it's dropped). The [`Tx::on_commit`] futures aren't
+    /// subject to the deadline.
+    pub(crate) async fn commit(self, deadline: Option<(Duration, Sleep)>) -> Result<bool, Error> {
+        let Some((tx, options, state)) = self.into_inner() else {
+            return Ok(false);
+        };
+
+        let commit = options.commit(tx);
+        let result = match deadline {
+            Some((deadline, sleep)) => runtime::timeout(sleep, deadline, commit)
+                .await
+                .ok_or(Error::CommitTimeout { deadline })?,
+            None => commit.await,
+        };
+        result.map_err(Error::commit_failed)?;
+        state.committed().await;
+        Ok(true)
+    }
+
+    /// Prepare the transaction, if any, for two-phase commit (see
+    /// [`Layer::two_phase`](crate::Layer::two_phase)).
+    ///
+    /// Returns `Ok(true)` if a transaction was prepared. Its [`Tx::on_commit`] futures are dropped,
+    /// since whether it's committed is up to the coordinator.
+    pub(crate) async fn prepare(self, gid: &str) -> Result<bool, DbErr> {
+        if let Some((tx, ..)) = self.into_inner() {
+            tx.prepare(gid).await?;
+            Ok(true)
+        } else {
+            Ok(false)
+        }
+    }
+
+    /// Run `sql` in the transaction, if it's been begun on Postgres and isn't held by a
+    /// [`TxStream`], before resolving it (see
+    /// [`Layer::annotate_outcome`](crate::Layer::annotate_outcome)).
+    ///
+    /// Failures are logged and otherwise ignored, since they mustn't change the outcome.
+    ///
+    /// [`TxStream`]: crate::TxStream
+    pub(crate) async fn annotate(&mut self, sql: String) {
+        let Some(mut tx) = self.0.lease() else {
+            return;
+        };
+        let Some(conn) = tx.as_mut().as_mut().and_then(|tx| tx.conn.lease()) else {
+            return;
+        };
+        if !matches!(*conn, Connection::Transaction(_))
+            || conn.get_database_backend() != DbBackend::Postgres
+        {
+            return;
+        }
+
+        let stmt = Statement::from_string(DbBackend::Postgres, sql);
+        #[allow(unused_variables)]
+        if let Err(error) = conn.execute(stmt).await {
+            #[cfg(feature = "tracing")]
+            tracing::warn!(%error, "failed to annotate request transaction outcome");
+        }
+    }
+
+    /// Roll back the transaction, if any.
+    ///
+    /// Returns `true` if there was a transaction to roll back. Errors are not returned, since the
+    /// transaction is discarded either way.
+    pub(crate) async fn rollback(self) -> bool {
+        let Some((tx, options, _)) = self.into_inner() else {
+            return false;
+        };
+
+        #[allow(unused_variables)]
+        if let Err(error) = options.rollback(tx).await {
+            #[cfg(feature = "tracing")]
+            tracing::warn!(%error, "failed to roll back request transaction");
+        }
+        true
+    }
+
+    /// Drop the transaction (if any) without waiting for it to be rolled back.
+    ///
+    /// SeaORM starts rolling back a transaction as soon as it's dropped, and the connection is
+    /// returned to the pool once that's done. Returns `true` if there was a transaction.
+    pub(crate) fn abandon(self) -> bool {
+        self.into_inner().is_some()
+    }
+
+    fn into_inner(self) -> Option<(Connection, BeginOptions, Arc<TxState>)> {
+        let tx = self.0.into_inner().flatten()?;
+        let leased = tx.conn.is_leased();
+        let Some(conn) = tx.conn.into_inner() else {
+            if let Some(leak_check) = &self.1 {
+                leak_check.check(leased, &tx.state);
+            }
+            return None;
+        };
+        Some((conn, tx.options, tx.state))
+    }
+}
+
+/// A lazily acquired transaction.
+///
+/// Nothing is computed or allocated for the transaction until it's begun. When it is, the
+/// transaction is inserted into the `Option` leased from the `TxSlot`, so that when `Lazy` is
+/// dropped the transaction is moved to the `TxSlot`.
+struct Lazy<C: TransactionTrait = DatabaseConnection> {
+    pool: C,
+    read_only: bool,
+    config: Arc<Config>,
+    #[cfg(feature = "audit")]
+    audit: Option<AuditLog>,
+    tx: Lease<Option<Begun>>,
+}
+
+/// The [`Lazy`]s of the layers constructed with [`Layer::with_key`](crate::Layer::with_key), by
+/// key.
+struct KeyedLazy<C: TransactionTrait>(HashMap<String, Lazy<C>>);
+
+/// A transaction that has been begun.
+struct Begun {
+    conn: Slot<Connection>,
+    begun_at: Instant,
+    options: BeginOptions,
+    state: Arc<TxState>,
+}
+
+impl Begun {
+    /// Whether the transaction was taken by a [`Tx`] to resolve it itself (with [`Tx::commit`] or
+    /// [`Tx::into_inner`]), leaving nothing in the slot.
+    fn is_taken(&self) -> bool {
+        self.state.resolved.load(Ordering::Relaxed) && !self.conn.is_leased()
+    }
+}
+
+/// State shared by a transaction's [`Tx`]s and its `TxSlot`.
+#[derive(Default)]
+pub(crate) struct TxState {
+    /// Whether the transaction may have been written to (see
+    /// [`Layer::rollback_readonly`](crate::Layer::rollback_readonly)).
+    written: AtomicBool,
+
+    /// Futures registered with [`Tx::on_commit`].
+    on_commit: Mutex<Vec<BoxFuture<'static, ()>>>,
+
+    /// How many statements have been run through [`Tx`].
+    statements: AtomicUsize,
+
+    /// Whether the connection has been pinned by [`Tx::commit_and_pin`].
+    pinned: AtomicBool,
+
+    /// Whether the transaction should be committed whatever the response, see
+    /// [`Tx::commit_anyway`].
+    commit_anyway: AtomicBool,
+
+    /// Whether the transaction was extracted as a [`SnapshotTx`], so must be rolled back.
+    snapshot: AtomicBool,
+
+    /// Whether the transaction was aborted through a [`TxRegistry`](crate::TxRegistry), so
+    /// statements fail and it must be rolled back.
+    aborted: AtomicBool,
+
+    /// Whether a statement failed in a way that aborted the transaction, see [`Tx::is_aborted`].
+    failed: AtomicBool,
+
+    /// Whether a [`Tx`] took the transaction from the request to resolve it itself (with
+    /// [`Tx::commit`] or [`Tx::into_inner`]), for [`Layer::leak_check`](crate::Layer::leak_check)
+    /// and so that extracting `Tx` again begins a new transaction.
+    resolved: AtomicBool,
+
+    /// Whether the transaction was detached from the response by [`Tx::detach`], so it's resolved
+    /// by the detached future rather than the middleware.
+    detached: AtomicBool,
+}
+
+impl TxState {
+    /// Abort the transaction, see [`TxRegistry::abort`](crate::TxRegistry::abort).
+    pub(crate) fn abort(&self) {
+        self.aborted.store(true, Ordering::Relaxed);
+    }
+
+    /// Run the futures registered with [`Tx::on_commit`], now that the transaction has committed.
+    async fn committed(&self) {
+        let futures = std::mem::take(&mut *self.on_commit.lock());
+        for future in futures {
+            future.await;
+        }
+    }
+}
+
+impl std::fmt::Debug for TxState {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("TxState")
+            .field("written", &self.written)
+            .field("on_commit", &self.on_commit.lock().len())
+            .field("statements", &self.statements)
+            .field("pinned", &self.pinned)
+            .field("commit_anyway", &self.commit_anyway)
+            .field("snapshot", &self.snapshot)
+            .field("aborted", &self.aborted)
+            .field("failed", &self.failed)
+            .field("resolved", &self.resolved)
+            .field("detached", &self.detached)
+            .finish()
+    }
+}
+
+/// Report that the [`Layer`](crate::Layer) isn't installed.
+///
+/// This is a mistake in the application's router rather than something that happens at runtime,
+/// so debug builds also log an error saying how to fix it, in case the 500 response isn't noticed.
+fn missing_layer() -> Error {
+    #[cfg(all(feature = "tracing", debug_assertions))]
+    tracing::error!(
+        kind = "tx.missing_layer",
+        "axum_sea_orm_tx::Tx was extracted, but the axum_sea_orm_tx::Layer middleware isn't \
+         installed; add `.layer(axum_sea_orm_tx::Layer::new(pool))` to the router (or route) \
+         that uses Tx"
+    );
+    Error::MissingExtension
+}
+
+/// Report that the transaction is already held by another [`Tx`].
+///
+/// The error itself can't say where the other `Tx` is, so log the route (if it's been matched yet)
+/// to help find the handler or middleware that extracts it twice.
+#[cfg_attr(not(feature = "tracing"), allow(unused_variables))]
+fn overlapping_extractors(parts: &Parts) -> Error {
+    #[cfg(feature = "tracing")]
+    tracing::error!(
+        kind = "tx.overlapping_extractors",
+        route = parts.extensions.get::<MatchedPath>().map(MatchedPath::as_str),
+        "axum_sea_orm_tx::Tx was extracted while the request's transaction was held by another Tx; \
+         check for a handler with more than one Tx argument, or middleware that doesn't drop its \
+         Tx before calling the handler"
+    );
+    Error::OverlappingExtractors
+}
+
+impl<C: TransactionTrait + Send + Sync + 'static> Lazy<C> {
+    /// Get the request's `Lazy` for the layer with `key` (if any).
+    fn get<'a>(extensions: &'a http::Extensions, key: Option<&str>) -> Option<&'a Self> {
+        match key {
+            None => extensions.get(),
+            Some(key) => extensions.get::<KeyedLazy<C>>()?.0.get(key),
+        }
+    }
+
+    /// Get the request's `Lazy` for the layer with `key` (if any) mutably.
+    fn get_mut<'a>(
+        extensions: &'a mut http::Extensions,
+        key: Option<&str>,
+    ) -> Option<&'a mut Self> {
+        match key {
+            None => extensions.get_mut(),
+            Some(key) => extensions.get_mut::<KeyedLazy<C>>()?.0.get_mut(key),
+        }
+    }
+
+    /// Get the request's `Lazy` for the layer with `key` (if any) from `parts`, beginning the
+    /// transaction with the settings from `I` if it hasn't been already.
+    ///
+    /// If the transaction has already been begun, it must satisfy `I`.
+    async fn begin<'a, I: IsolationMarker>(
+        parts: &'a mut Parts,
+        key: Option<&str>,
+    ) -> Result<&'a mut Self, Error> {
+        let ext = Self::get_mut(&mut parts.extensions, key).ok_or_else(missing_layer)?;
+        // A transaction taken by a `Tx` is gone for good, so begin another in its place
+        if ext.tx.as_ref().as_ref().is_some_and(Begun::is_taken) {
+            *ext.tx = None;
+        }
+
+        let ext = Self::get(&parts.extensions, key).expect("BUG: extension removed");
+
+        if let Some(begun) = ext.tx.as_ref() {
+            if !begun.options.satisfies::<I>() {
+                return Err(Error::ConflictingIsolation);
+            }
+        }
+
+        // The options are computed from the request head, so do that before borrowing it mutably
+        let options = ext.tx.is_none().then(|| {
+            let mut options = ext.config.begin_options(parts);
+            if let Some(route) = parts.extensions.get::<TxConfig>() {
+                route.apply(&mut options);
+            }
+            if ext.read_only {
+                // Prewarmed transactions and held connections are on the primary
+                options.access_mode = Some(AccessMode::ReadOnly);
+                options.prewarmed = None;
+                options.affinity = None;
+            }
+            #[cfg(feature = "audit")]
+            {
+                options.audit = ext.audit.clone();
+            }
+            options.isolation_level = I::ISOLATION_LEVEL.or(options.isolation_level);
+            options.access_mode = I::ACCESS_MODE.or(options.access_mode);
+            options
+        });
+
+        let ext = Self::get_mut(&mut parts.extensions, key).expect("BUG: extension removed");
+        if let Some(options) = options {
+            let begun_at = Instant::now();
+            let tx = begin_connection(&ext.pool, &options).await;
+            // Tagged, so failures to begin can be told apart from handlers' database errors
+            #[cfg(feature = "tracing")]
+            let tx = tx.inspect_err(|error| {
+                tracing::error!(kind = "tx.begin_failed", %error, "failed to begin request transaction");
+            });
+            let tx = tx?;
+            let state = Arc::<TxState>::default();
+            if let Some((registry, request_id)) = &options.registry {
+                registry.register(request_id, &state, backend_pid(&tx).await);
+            }
+            *ext.tx = Some(Begun {
+                conn: Slot::new(tx),
+                begun_at,
+                options,
+                state,
+            });
+        }
+
+        Ok(ext)
+    }
+}
+
+/// The PID of the Postgres backend running `conn`'s transaction, so that a [`TxRegistry`] can
+/// cancel its statements.
+async fn backend_pid(conn: &Connection) -> Option<i32> {
+    let Connection::Transaction(tx) = conn else {
+        return None;
+    };
+    if tx.get_database_backend() != DbBackend::Postgres {
+        return None;
+    }
+    let stmt = Statement::from_string(DbBackend::Postgres, "SELECT pg_backend_pid() AS pid".into());
+    let row = tx.query_one(stmt).await.ok()??;
+    row.try_get("", "pid").ok()
+}
+
+/// Whether `sql` is a statement that `EXPLAIN` accepts, see
+/// [`Layer::explain_slow_queries`](crate::Layer::explain_slow_queries).
+#[cfg(feature = "tracing")]
+fn is_explainable(sql: &str) -> bool {
+    let keyword = sql.split_whitespace().next().unwrap_or_default();
+    ["SELECT", "INSERT", "UPDATE", "DELETE", "WITH"]
+        .iter()
+        .any(|explainable| keyword.eq_ignore_ascii_case(explainable))
+}
+
+/// Quote `name` as the name of a savepoint on `backend` (see [`Tx::savepoint_named`]).
+///
+/// Savepoint names can't be bound as parameters, so only simple identifiers are accepted.
+fn quote_savepoint(backend: DbBackend, name: &str) -> Result<String, DbErr> {
+    if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+        return Err(DbErr::Custom(format!("invalid savepoint name: {name:?}")));
+    }
+    Ok(match backend {
+        DbBackend::MySql => format!("`{name}`"),
+        DbBackend::Postgres | DbBackend::Sqlite => format!("\"{name}\""),
+    })
+}
+
+/// Whether `error`, from a statement run on `backend`, is resolved by retrying the transaction
+/// (see [`Tx::retry`]).
+fn is_retryable(backend: DbBackend, error: &DbErr) -> bool {
+    let message = error.to_string();
+    match backend {
+        DbBackend::Postgres => {
+            message.contains("could not serialize access") || message.contains("deadlock detected")
+        }
+        DbBackend::MySql => message.contains("Deadlock found"),
+        DbBackend::Sqlite => is_locked(error),
+    }
+}
+
+/// Whether `error`, from a statement run on `backend`, aborted the transaction it ran in (see
+/// [`Tx::is_aborted`]).
+fn aborts_transaction(backend: DbBackend, error: &DbErr) -> bool {
+    match backend {
+        DbBackend::Postgres => true,
+        // SeaORM doesn't expose the database's error codes, so this matches MySQL's message
+        DbBackend::MySql => error.to_string().contains("Deadlock found"),
+        DbBackend::Sqlite => false,
+    }
+}
+
+/// Get the estimated plan of `stmt` from the database, without running it.
+async fn explain(conn: &Connection, stmt: &Statement) -> Result<String, DbErr> {
+    let backend = conn.get_database_backend();
+    let (explain, column) = match backend {
+        DbBackend::Postgres => ("EXPLAIN", "QUERY PLAN"),
+        DbBackend::MySql => ("EXPLAIN FORMAT=TREE", "EXPLAIN"),
+        DbBackend::Sqlite => ("EXPLAIN QUERY PLAN", "detail"),
+    };
+    let explain = Statement {
+        sql: format!("{explain} {}", stmt.sql),
+        values: stmt.values.clone(),
+        db_backend: backend,
+    };
+
+    // A failed statement aborts a Postgres transaction, so the request could carry on regardless
+    let savepoint = backend == DbBackend::Postgres && matches!(conn, Connection::Transaction(_));
+    if savepoint {
+        conn.execute_unprepared("SAVEPOINT axum_sea_orm_tx_explain")
+            .await?;
+    }
+    let rows = conn.query_all(explain).await;
+    if savepoint {
+        let sql = match rows {
+            Ok(_) => "RELEASE SAVEPOINT axum_sea_orm_tx_explain",
+            Err(_) => "ROLLBACK TO SAVEPOINT axum_sea_orm_tx_explain",
+        };
+        conn.execute_unprepared(sql).await?;
+    }
+
+    let lines = rows?
+        .iter()
+        .map(|row| row.try_get::<String>("", column))
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(lines.join("\n"))
+}
+
+/// Begin a transaction on `pool` and apply `options` to it.
+async fn begin_connection<C: TransactionTrait>(
+    pool: &C,
+    options: &BeginOptions,
+) -> Result<Connection, DbErr> {
+    let begun = if options.isolation_level.is_some() || options.access_mode.is_some() {
+        // Prewarmed transactions were begun with the default settings, so can't be used
+        pool.begin_with_config(options.isolation_level, options.access_mode)
+            .await
+    } else {
+        let held = (options.affinity.as_ref()).and_then(|(affinity, key)| affinity.take(key));
+        match held.or_else(|| options.prewarmed.as_ref().and_then(Prewarmed::take)) {
+            Some(tx) => Ok(tx),
+            None => pool.begin().await,
+        }
+    };
+    match begun {
+        Ok(tx) => {
+            options.apply(&tx).await?;
+            Ok(Connection::Transaction(tx))
+        }
+        Err(error) => match &options.autocommit_fallback {
+            Some(conn) if transactions_unsupported(&error) => {
+                Ok(Connection::Autocommit(conn.clone()))
+            }
+            _ => Err(error),
+        },
+    }
+}
+
+/// Per-request options for beginning the transaction, computed by the [`Service`].
+///
+/// [`Service`]: crate::Service
+#[derive(Clone, Default)]
+pub(crate) struct BeginOptions {
+    /// The Postgres `application_name` to set for the duration of the transaction.
+    pub(crate) application_name: Option<String>,
+
+    /// The Postgres `search_path` to set for the duration of the transaction.
+    pub(crate) search_path: Option<String>,
+
+    /// The connection to use if transactions aren't supported.
+    pub(crate) autocommit_fallback: Option<AutocommitConnection>,
+
+    /// Transactions begun ahead of time, to use instead of beginning a new one.
+    pub(crate) prewarmed: Option<Prewarmed>,
+
+    /// The connections held for requests sharing a key, and this request's key, see
+    /// [`Layer::connection_affinity`](crate::Layer::connection_affinity).
+    pub(crate) affinity: Option<(Affinity, AffinityKey)>,
+
+    /// A function applied to statements before they're run.
+    pub(crate) rewriter: Option<StatementRewriter>,
+
+    /// The isolation level to begin the transaction with.
+    pub(crate) isolation_level: Option<IsolationLevel>,
+
+    /// The access mode to begin the transaction with.
+    pub(crate) access_mode: Option<AccessMode>,
+
+    /// How to retry a commit that fails because the database is locked.
+    pub(crate) commit_backoff: Option<BackoffPolicy>,
+
+    /// The runtime's `sleep`, to wait between commit attempts.
+    pub(crate) sleep: Option<Sleep>,
+
+    /// Details of the database, see [`Layer::new_with_options`](crate::Layer::new_with_options).
+    pub(crate) meta: Option<Arc<TxMeta>>,
+
+    /// The registry to register the transaction in, and the request ID to register it under.
+    pub(crate) registry: Option<(TxRegistry, String)>,
+
+    /// The maximum number of statements to run through [`Tx`].
+    pub(crate) max_statements: Option<usize>,
+
+    /// How long a statement can take before its plan is logged, see
+    /// [`Layer::explain_slow_queries`](crate::Layer::explain_slow_queries).
+    pub(crate) explain_threshold: Option<Duration>,
+
+    /// Whether explicit commits are ignored, see [`Layer::dry_run`](crate::Layer::dry_run).
+    pub(crate) dry_run: bool,
+
+    /// Whether nested transactions are disallowed, see
+    /// [`Layer::strict_nesting`](crate::Layer::strict_nesting).
+    pub(crate) strict_nesting: bool,
+
+    /// The log to record executed statements in.
+    #[cfg(feature = "audit")]
+    pub(crate) audit: Option<AuditLog>,
+}
+
+/// A function applied to statements before they're run by [`Tx`].
+#[derive(Clone)]
+pub(crate) struct StatementRewriter(pub(crate) Arc<dyn Fn(&mut Statement) + Send + Sync>);
+
+impl std::fmt::Debug for StatementRewriter {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_tuple("StatementRewriter").finish_non_exhaustive()
+    }
+}
+
+impl std::fmt::Debug for BeginOptions {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("BeginOptions")
+            .field("application_name", &self.application_name)
+            .field("search_path", &self.search_path)
+            .field("isolation_level", &self.isolation_level)
+            .field("access_mode", &self.access_mode)
+            .finish_non_exhaustive()
+    }
+}
+
+impl BeginOptions {
+    /// Whether a transaction begun with these options has the settings asked for by `I`.
+    fn satisfies<I: IsolationMarker>(&self) -> bool {
+        (I::ISOLATION_LEVEL.is_none() || I::ISOLATION_LEVEL == self.isolation_level)
+            && (I::ACCESS_MODE.is_none() || I::ACCESS_MODE == self.access_mode)
+    }
+
+    /// Commit `conn`, retrying if configured to (see
+    /// [`Layer::retry_commit`](crate::Layer::retry_commit)).
+    async fn commit(&self, conn: Connection) -> Result<(), DbErr> {
+        let conn = match (&self.affinity, conn) {
+            (Some((affinity, key)), Connection::Transaction(tx)) => {
+                let backoff = self.commit_backoff.as_ref();
+                return affinity.commit(key.clone(), tx, backoff, self.sleep).await;
+            }
+            (_, conn) => conn,
+        };
+        match &self.commit_backoff {
+            Some(backoff) => conn.commit_retrying(backoff, self.sleep).await,
+            None => conn.commit().await,
+        }
+    }
+
+    /// Roll back `conn`, keeping its connection if it has an affinity (see
+    /// [`Layer::connection_affinity`](crate::Layer::connection_affinity)).
+    async fn rollback(&self, conn: Connection) -> Result<(), DbErr> {
+        match (&self.affinity, conn) {
+            (Some((affinity, key)), Connection::Transaction(tx)) => {
+                affinity.rollback(key.clone(), tx).await
+            }
+            (_, conn) => conn.rollback().await,
+        }
+    }
+
+    /// Apply the options to a newly begun transaction.
+    async fn apply(&self, tx: &DatabaseTransaction) -> Result<(), DbErr> {
+        let backend = tx.get_database_backend();
+
+        if let (Some(name), DbBackend::Postgres) = (&self.application_name, backend) {
+            tx.execute(Statement::from_sql_and_values(
+                backend,
+                "SELECT set_config('application_name', $1, true)",
+                [name.as_str().into()],
+            ))
+            .await?;
+        }
+
+        if let (Some(path), DbBackend::Postgres) = (&self.search_path, backend) {
+            tx.execute(Statement::from_sql_and_values(
+                backend,
+                "SELECT set_config('search_path', $1, true)",
+                [path.as_str().into()],
+            ))
+            .await?;
+        }
+
+        Ok(())
     }
 }
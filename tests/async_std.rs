@@ -0,0 +1,66 @@
+//! Tests for the `runtime-async-std` feature.
+
+#![cfg(feature = "runtime-async-std")]
+
+use std::time::{Duration, Instant};
+
+use axum_sea_orm_tx::{AsyncStd, Layer, Runtime};
+use sea_orm::{ConnectionTrait, Database, DatabaseConnection};
+use tempfile::NamedTempFile;
+use tower::ServiceExt;
+
+type Tx = axum_sea_orm_tx::Tx<DatabaseConnection>;
+
+#[test]
+fn sleep() {
+    let started = Instant::now();
+    async_std::task::block_on(AsyncStd::sleep(Duration::from_millis(10)));
+    assert!(started.elapsed() >= Duration::from_millis(10));
+}
+
+#[tokio::test]
+async fn commit_deadline() {
+    let db = NamedTempFile::new().unwrap();
+    let pool = Database::connect(&format!("sqlite://{}", db.path().display()))
+        .await
+        .unwrap();
+    pool.execute_unprepared("CREATE TABLE users (id INT PRIMARY KEY)")
+        .await
+        .unwrap();
+
+    let app = axum::Router::new()
+        .route(
+            "/",
+            axum::routing::get(|tx: Tx| async move {
+                tx.execute_unprepared("INSERT INTO users VALUES (1)")
+                    .await
+                    .unwrap();
+            }),
+        )
+        .layer(
+            Layer::new(pool.clone())
+                .runtime::<AsyncStd>()
+                .commit_deadline(Duration::from_secs(5)),
+        );
+
+    let response = app
+        .oneshot(
+            http::Request::builder()
+                .uri("/")
+                .body(axum::body::Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+
+    assert!(response.status().is_success());
+    let count = pool
+        .query_one(sea_orm::Statement::from_string(
+            pool.get_database_backend(),
+            "SELECT COUNT(*) AS count FROM users".to_string(),
+        ))
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(count.try_get::<i64>("", "count").unwrap(), 1);
+}
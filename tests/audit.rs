@@ -0,0 +1,123 @@
+//! Tests for the `audit` feature.
+
+#![cfg(feature = "audit")]
+
+use std::sync::{Arc, Mutex};
+
+use axum_sea_orm_tx::{AuditRecord, AuditedStatement, TxOutcome};
+use sea_orm::{ConnectionTrait, Database, DatabaseConnection, Statement};
+use tempfile::NamedTempFile;
+use tower::ServiceExt;
+
+type Tx = axum_sea_orm_tx::Tx<DatabaseConnection>;
+
+#[tokio::test]
+async fn records_statements_and_outcome() {
+    let records = run(false, http::StatusCode::OK).await;
+
+    assert_eq!(records.len(), 1);
+    assert_eq!(
+        summarize(&records[0].statements),
+        vec![
+            (
+                "INSERT INTO users VALUES (?, ?)",
+                vec!["Int(Some(1))", r#"String(Some("huge hackerman"))"#]
+            ),
+            ("SELECT * FROM users", vec![]),
+        ]
+    );
+    let outcome = records[0].outcome.unwrap();
+    assert!(outcome.committed);
+}
+
+#[tokio::test]
+async fn records_rollback() {
+    let records = run(false, http::StatusCode::BAD_REQUEST).await;
+
+    assert_eq!(records.len(), 1);
+    assert_eq!(records[0].statements.len(), 2);
+    let outcome: TxOutcome = records[0].outcome.unwrap();
+    assert!(!outcome.committed);
+}
+
+#[tokio::test]
+async fn redacts_values() {
+    let records = run(true, http::StatusCode::OK).await;
+
+    assert_eq!(
+        summarize(&records[0].statements)[0],
+        (
+            "INSERT INTO users VALUES (?, ?)",
+            vec!["<redacted>", "<redacted>"]
+        )
+    );
+}
+
+/// Insert a user and list the users, responding with `status`, and return the audit records.
+async fn run(redact: bool, status: http::StatusCode) -> Vec<AuditRecord> {
+    let db = NamedTempFile::new().unwrap();
+    let pool = Database::connect(&format!("sqlite://{}", db.path().display()))
+        .await
+        .unwrap();
+    pool.execute_unprepared("CREATE TABLE users (id INT PRIMARY KEY, name TEXT)")
+        .await
+        .unwrap();
+
+    let records = Arc::new(Mutex::new(Vec::new()));
+
+    let app = axum::Router::new()
+        .route(
+            "/",
+            axum::routing::get(move |tx: Tx| async move {
+                tx.execute(Statement::from_sql_and_values(
+                    tx.backend(),
+                    "INSERT INTO users VALUES (?, ?)",
+                    [1.into(), "huge hackerman".into()],
+                ))
+                .await
+                .unwrap();
+                tx.query_all(Statement::from_string(
+                    tx.backend(),
+                    "SELECT * FROM users".to_string(),
+                ))
+                .await
+                .unwrap();
+                status
+            }),
+        )
+        // No transaction is begun here, so nothing is recorded
+        .route("/none", axum::routing::get(|| async {}))
+        .layer(
+            axum_sea_orm_tx::Layer::new(pool)
+                .audit({
+                    let records = records.clone();
+                    move |record| records.lock().unwrap().push(record)
+                })
+                .redact_audit_values(redact),
+        );
+
+    for uri in ["/", "/none"] {
+        app.clone()
+            .oneshot(
+                http::Request::builder()
+                    .uri(uri)
+                    .body(axum::body::Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+    }
+
+    let records = records.lock().unwrap();
+    records.clone()
+}
+
+fn summarize(statements: &[AuditedStatement]) -> Vec<(&str, Vec<&str>)> {
+    statements
+        .iter()
+        .map(|statement| {
+            let values = statement.values.iter().map(String::as_str).collect();
+            (statement.sql.as_str(), values)
+        })
+        .collect()
+}
@@ -0,0 +1,1229 @@
+//! Tests for Postgres-specific behaviour.
+//!
+//! These run against the database at `DATABASE_URL`, and are skipped if it's not set.
+
+use axum::response::IntoResponse;
+use sea_orm::{ConnectionTrait, Database, DatabaseConnection, Statement};
+use tower::ServiceExt;
+
+type Tx<E = axum_sea_orm_tx::Error> = axum_sea_orm_tx::Tx<DatabaseConnection, E>;
+
+#[tokio::test]
+async fn application_name() {
+    let Some(pool) = connect().await else { return };
+
+    let app = axum::Router::new()
+        .route(
+            "/users/:id",
+            axum::routing::get(|tx: Tx| async move {
+                let row = tx
+                    .query_one(Statement::from_string(
+                        tx.get_database_backend(),
+                        "SELECT current_setting('application_name') AS name".to_string(),
+                    ))
+                    .await
+                    .unwrap()
+                    .unwrap();
+                row.try_get::<String>("", "name").unwrap()
+            }),
+        )
+        .layer(
+            axum_sea_orm_tx::Layer::new(pool)
+                .application_name_from(|parts| format!("{} {}", parts.method, parts.uri.path())),
+        );
+
+    let (status, body) = get(app, "/users/1").await;
+
+    assert!(status.is_success());
+    assert_eq!(body, "GET /users/1");
+}
+
+#[tokio::test]
+async fn isolation_marker() {
+    let Some(pool) = connect().await else { return };
+
+    type SerializableTx = axum_sea_orm_tx::Tx<
+        DatabaseConnection,
+        axum_sea_orm_tx::Error,
+        axum_sea_orm_tx::Serializable,
+    >;
+
+    let app = axum::Router::new()
+        .route(
+            "/",
+            axum::routing::get(|tx: Tx| async move { transaction_isolation(&tx).await }),
+        )
+        .route(
+            "/serializable",
+            axum::routing::get(
+                |tx: SerializableTx| async move { transaction_isolation(&tx).await },
+            ),
+        )
+        .layer(axum_sea_orm_tx::Layer::new(pool));
+
+    let (status, body) = get(app.clone(), "/").await;
+    assert!(status.is_success());
+    assert_eq!(body, "read committed");
+
+    let (status, body) = get(app, "/serializable").await;
+    assert!(status.is_success());
+    assert_eq!(body, "serializable");
+}
+
+#[tokio::test]
+async fn layer_isolation_level() {
+    let Some(pool) = connect().await else { return };
+
+    type SerializableTx = axum_sea_orm_tx::Tx<
+        DatabaseConnection,
+        axum_sea_orm_tx::Error,
+        axum_sea_orm_tx::Serializable,
+    >;
+
+    let app = axum::Router::new()
+        .route(
+            "/",
+            axum::routing::get(|tx: Tx| async move {
+                format!(
+                    "{} {}",
+                    transaction_isolation(&tx).await,
+                    transaction_read_only(&tx).await
+                )
+            }),
+        )
+        .route(
+            "/serializable",
+            axum::routing::get(
+                |tx: SerializableTx| async move { transaction_isolation(&tx).await },
+            ),
+        )
+        .layer(
+            axum_sea_orm_tx::Layer::new(pool)
+                .isolation_level(sea_orm::IsolationLevel::RepeatableRead)
+                .access_mode(sea_orm::AccessMode::ReadOnly),
+        );
+
+    let (status, body) = get(app.clone(), "/").await;
+    assert!(status.is_success());
+    assert_eq!(body, "repeatable read on");
+
+    // The marker takes precedence
+    let (status, body) = get(app, "/serializable").await;
+    assert!(status.is_success());
+    assert_eq!(body, "serializable");
+}
+
+#[tokio::test]
+async fn route_tx_config() {
+    let Some(pool) = connect().await else { return };
+
+    let handler = |tx: Tx| async move { transaction_isolation(&tx).await };
+    let app = axum::Router::new()
+        .route("/serializable", axum::routing::get(handler))
+        .route_layer(
+            axum_sea_orm_tx::TxConfig::new().isolation_level(sea_orm::IsolationLevel::Serializable),
+        )
+        .route("/", axum::routing::get(handler))
+        .layer(
+            axum_sea_orm_tx::Layer::new(pool)
+                .isolation_level(sea_orm::IsolationLevel::RepeatableRead),
+        );
+
+    let (status, body) = get(app.clone(), "/").await;
+    assert!(status.is_success());
+    assert_eq!(body, "repeatable read");
+
+    let (status, body) = get(app, "/serializable").await;
+    assert!(status.is_success());
+    assert_eq!(body, "serializable");
+}
+
+#[tokio::test]
+async fn route_tx_config_optional_tx() {
+    let Some(pool) = connect().await else { return };
+
+    let app = axum::Router::new()
+        .route(
+            "/",
+            axum::routing::get(
+                |tx: axum_sea_orm_tx::OptionalTx<DatabaseConnection>| async move {
+                    let tx = tx.begin().await.unwrap().unwrap();
+                    transaction_isolation(&tx).await
+                },
+            ),
+        )
+        .route_layer(
+            axum_sea_orm_tx::TxConfig::new().isolation_level(sea_orm::IsolationLevel::Serializable),
+        )
+        .layer(
+            axum_sea_orm_tx::Layer::new(pool)
+                .isolation_level(sea_orm::IsolationLevel::RepeatableRead),
+        );
+
+    let (status, body) = get(app, "/").await;
+    assert!(status.is_success());
+    assert_eq!(body, "serializable");
+}
+
+async fn transaction_isolation(conn: &impl ConnectionTrait) -> String {
+    conn.query_one(Statement::from_string(
+        conn.get_database_backend(),
+        "SELECT current_setting('transaction_isolation') AS level".to_string(),
+    ))
+    .await
+    .unwrap()
+    .unwrap()
+    .try_get::<String>("", "level")
+    .unwrap()
+}
+
+#[tokio::test]
+async fn read_write_split_read_only() {
+    let Some(pool) = connect().await else { return };
+
+    let app = axum::Router::new()
+        .route(
+            "/",
+            axum::routing::get(|tx: Tx| async move { transaction_read_only(&tx).await })
+                .post(|tx: Tx| async move { transaction_read_only(&tx).await }),
+        )
+        .layer(axum_sea_orm_tx::Layer::read_write_split(pool.clone(), pool));
+
+    let (status, body) = get(app.clone(), "/").await;
+    assert!(status.is_success());
+    assert_eq!(body, "on");
+
+    let response = app
+        .oneshot(
+            http::Request::post("/")
+                .body(axum::body::Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+    assert_eq!(body, "off");
+}
+
+async fn transaction_read_only(conn: &impl ConnectionTrait) -> String {
+    conn.query_one(Statement::from_string(
+        conn.get_database_backend(),
+        "SELECT current_setting('transaction_read_only') AS read_only".to_string(),
+    ))
+    .await
+    .unwrap()
+    .unwrap()
+    .try_get::<String>("", "read_only")
+    .unwrap()
+}
+
+#[tokio::test]
+async fn unique_violation_on_commit() {
+    let Some(pool) = connect().await else { return };
+
+    pool.execute_unprepared(
+        "DROP TABLE IF EXISTS unique_violation_test;
+        CREATE TABLE unique_violation_test (
+            id INT,
+            CONSTRAINT unique_violation_test_id_key UNIQUE (id) DEFERRABLE INITIALLY DEFERRED
+        );",
+    )
+    .await
+    .unwrap();
+
+    let app = axum::Router::new()
+        .route(
+            "/",
+            axum::routing::get(|tx: Tx| async move {
+                tx.execute_unprepared("INSERT INTO unique_violation_test VALUES (1), (1)")
+                    .await
+                    .unwrap();
+            }),
+        )
+        .layer(axum_sea_orm_tx::Layer::new_with_error::<ViolationError>(
+            pool,
+        ));
+
+    let (status, body) = get(app, "/").await;
+
+    assert_eq!(status, http::StatusCode::CONFLICT);
+    assert_eq!(body, "Unique unique_violation_test_id_key");
+}
+
+#[tokio::test]
+async fn search_path() {
+    let Some(pool) = connect().await else { return };
+
+    pool.execute_unprepared(
+        "DROP SCHEMA IF EXISTS tenant_a CASCADE;
+        DROP SCHEMA IF EXISTS tenant_b CASCADE;
+        CREATE SCHEMA tenant_a;
+        CREATE SCHEMA tenant_b;
+        CREATE TABLE tenant_a.things (name TEXT);
+        CREATE TABLE tenant_b.things (name TEXT);
+        INSERT INTO tenant_a.things VALUES ('a');
+        INSERT INTO tenant_b.things VALUES ('b');",
+    )
+    .await
+    .unwrap();
+
+    let app = axum::Router::new()
+        .route(
+            "/",
+            axum::routing::get(|tx: Tx| async move {
+                let row = tx
+                    .query_one(Statement::from_string(
+                        tx.get_database_backend(),
+                        "SELECT current_setting('search_path') AS path".to_string(),
+                    ))
+                    .await
+                    .unwrap()
+                    .unwrap();
+                let path = row.try_get::<String>("", "path").unwrap();
+
+                let name = tx
+                    .query_one(Statement::from_string(
+                        tx.get_database_backend(),
+                        "SELECT name FROM things".to_string(),
+                    ))
+                    .await
+                    .ok()
+                    .flatten()
+                    .map(|row| row.try_get::<String>("", "name").unwrap());
+
+                format!("{path} {}", name.unwrap_or_default())
+            }),
+        )
+        .layer(axum_sea_orm_tx::Layer::new(pool).search_path_from(|parts| {
+            let tenant = parts.headers.get("x-tenant")?.to_str().ok()?;
+            Some(format!("tenant_{tenant}"))
+        }));
+
+    let get_tenant = |tenant: Option<&str>| {
+        let mut req = http::Request::builder().uri("/");
+        if let Some(tenant) = tenant {
+            req = req.header("x-tenant", tenant);
+        }
+        app.clone()
+            .oneshot(req.body(axum::body::Body::empty()).unwrap())
+    };
+
+    for (tenant, expected) in [(Some("a"), "tenant_a a"), (Some("b"), "tenant_b b")] {
+        let response = get_tenant(tenant).await.unwrap();
+        assert!(response.status().is_success());
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        assert_eq!(body, expected);
+    }
+
+    // Without a tenant, the default search_path is used
+    let response = get_tenant(None).await.unwrap();
+    let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+    assert!(body.starts_with(b"\"$user\", public"));
+}
+
+#[tokio::test]
+async fn two_phase_commit() {
+    let Some(pool) = connect().await else { return };
+
+    let max_prepared = pool
+        .query_one(Statement::from_string(
+            pool.get_database_backend(),
+            "SELECT current_setting('max_prepared_transactions')::INT AS n".to_string(),
+        ))
+        .await
+        .unwrap()
+        .unwrap()
+        .try_get::<i32>("", "n")
+        .unwrap();
+    if max_prepared == 0 {
+        eprintln!("max_prepared_transactions is 0, skipping two-phase commit test");
+        return;
+    }
+
+    pool.execute_unprepared(
+        "DROP TABLE IF EXISTS two_phase_test;
+        CREATE TABLE two_phase_test (id INT);",
+    )
+    .await
+    .unwrap();
+
+    let app = axum::Router::new()
+        .route(
+            "/",
+            axum::routing::post(|tx: Tx| async move {
+                tx.execute_unprepared("INSERT INTO two_phase_test VALUES (1)")
+                    .await
+                    .unwrap();
+            }),
+        )
+        .layer(axum_sea_orm_tx::Layer::two_phase(pool.clone()));
+
+    let response = app
+        .oneshot(
+            http::Request::post("/")
+                .body(axum::body::Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+
+    assert!(response.status().is_success());
+    assert!(response
+        .extensions()
+        .get::<axum_sea_orm_tx::TxOutcome>()
+        .is_none());
+    let gid = &response
+        .extensions()
+        .get::<axum_sea_orm_tx::PreparedTransaction>()
+        .unwrap()
+        .gid;
+
+    let count = || async {
+        pool.query_one(Statement::from_string(
+            pool.get_database_backend(),
+            "SELECT COUNT(*) AS n FROM two_phase_test".to_string(),
+        ))
+        .await
+        .unwrap()
+        .unwrap()
+        .try_get::<i64>("", "n")
+        .unwrap()
+    };
+
+    // The write isn't visible until the coordinator commits the prepared transaction
+    assert_eq!(count().await, 0);
+    pool.execute_unprepared(&format!("COMMIT PREPARED '{gid}'"))
+        .await
+        .unwrap();
+    assert_eq!(count().await, 1);
+}
+
+/// Renders constraint violations as `409 Conflict` with the kind and name of the constraint.
+struct ViolationError(axum_sea_orm_tx::Error);
+
+impl From<axum_sea_orm_tx::Error> for ViolationError {
+    fn from(error: axum_sea_orm_tx::Error) -> Self {
+        Self(error)
+    }
+}
+
+impl IntoResponse for ViolationError {
+    fn into_response(self) -> axum::response::Response {
+        match self.0 {
+            axum_sea_orm_tx::Error::ConstraintViolation { violation, .. } => (
+                http::StatusCode::CONFLICT,
+                format!(
+                    "{:?} {}",
+                    violation.kind,
+                    violation.constraint_name.unwrap_or_default()
+                ),
+            )
+                .into_response(),
+            error => error.into_response(),
+        }
+    }
+}
+
+impl axum_sea_orm_tx::IntoResponseFor for ViolationError {}
+
+/// Connect to the database at `DATABASE_URL`, or return `None` if it isn't set.
+#[tokio::test]
+async fn commit_deadline() {
+    let Some(pool) = connect().await else { return };
+
+    // A deferred trigger makes the commit itself slow, like waiting for a synchronous replica
+    pool.execute_unprepared(
+        "DROP TABLE IF EXISTS slow_commit_test;
+        CREATE TABLE slow_commit_test (id INT);
+        CREATE OR REPLACE FUNCTION slow_commit() RETURNS trigger AS $$
+            BEGIN PERFORM pg_sleep(1); RETURN NULL; END
+        $$ LANGUAGE plpgsql;
+        CREATE CONSTRAINT TRIGGER slow_commit AFTER INSERT ON slow_commit_test
+            DEFERRABLE INITIALLY DEFERRED FOR EACH ROW EXECUTE FUNCTION slow_commit();",
+    )
+    .await
+    .unwrap();
+
+    let app = axum::Router::new()
+        .route(
+            "/",
+            axum::routing::get(|tx: Tx| async move {
+                tx.execute_unprepared("INSERT INTO slow_commit_test VALUES (1)")
+                    .await
+                    .unwrap();
+            }),
+        )
+        .layer(
+            axum_sea_orm_tx::Layer::new(pool)
+                .commit_deadline(std::time::Duration::from_millis(100)),
+        );
+
+    let started = std::time::Instant::now();
+    let (status, body) = get(app, "/").await;
+
+    assert!(started.elapsed() < std::time::Duration::from_secs(1));
+    assert_eq!(status, http::StatusCode::INTERNAL_SERVER_ERROR);
+    assert_eq!(
+        body,
+        axum_sea_orm_tx::Error::CommitTimeout {
+            deadline: std::time::Duration::from_millis(100)
+        }
+        .to_string()
+    );
+}
+
+#[tokio::test]
+async fn notify() {
+    let Some(pool) = connect().await else { return };
+
+    let mut listener = sqlx::postgres::PgListener::connect(&std::env::var("DATABASE_URL").unwrap())
+        .await
+        .unwrap();
+    listener.listen("tx_notify_test").await.unwrap();
+
+    let app = axum::Router::new()
+        .route(
+            "/rollback",
+            axum::routing::get(|tx: Tx| async move {
+                tx.notify("tx_notify_test", "rolled back").await.unwrap();
+                http::StatusCode::INTERNAL_SERVER_ERROR
+            }),
+        )
+        .route(
+            "/commit",
+            axum::routing::get(|tx: Tx| async move {
+                tx.notify("tx_notify_test", "committed").await.unwrap();
+            }),
+        )
+        .layer(axum_sea_orm_tx::Layer::new(pool));
+
+    let (status, _) = get(app.clone(), "/rollback").await;
+    assert!(status.is_server_error());
+    let (status, _) = get(app, "/commit").await;
+    assert!(status.is_success());
+
+    // Only the committed notification is delivered
+    let notification = listener.recv().await.unwrap();
+    assert_eq!(notification.payload(), "committed");
+    let next = tokio::time::timeout(std::time::Duration::from_millis(100), listener.recv()).await;
+    assert!(next.is_err());
+}
+
+#[tokio::test]
+async fn snapshot() {
+    let Some(pool) = connect().await else { return };
+
+    pool.execute_unprepared(
+        "DROP TABLE IF EXISTS snapshot_test;
+        CREATE TABLE snapshot_test (id INT PRIMARY KEY, value TEXT);
+        INSERT INTO snapshot_test VALUES (1, 'original');",
+    )
+    .await
+    .unwrap();
+
+    async fn read(conn: &impl ConnectionTrait) -> String {
+        conn.query_one(Statement::from_string(
+            conn.get_database_backend(),
+            "SELECT value FROM snapshot_test WHERE id = 1".to_string(),
+        ))
+        .await
+        .unwrap()
+        .unwrap()
+        .try_get("", "value")
+        .unwrap()
+    }
+
+    let app = axum::Router::new()
+        .route(
+            "/",
+            axum::routing::get({
+                let pool = pool.clone();
+                move |tx: axum_sea_orm_tx::SnapshotTx| async move {
+                    let first = read(&*tx).await;
+
+                    // A concurrent update, committed between the reads
+                    pool.execute_unprepared(
+                        "UPDATE snapshot_test SET value = 'updated' WHERE id = 1",
+                    )
+                    .await
+                    .unwrap();
+
+                    let second = read(&*tx).await;
+                    format!("{first} {second} {}", transaction_read_only(&*tx).await)
+                }
+            }),
+        )
+        .layer(axum_sea_orm_tx::Layer::new(pool.clone()));
+
+    let response = app
+        .oneshot(
+            http::Request::builder()
+                .uri("/")
+                .body(axum::body::Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert!(response.status().is_success());
+    assert_eq!(
+        response
+            .extensions()
+            .get::<axum_sea_orm_tx::TxOutcome>()
+            .unwrap()
+            .reason,
+        Some(axum_sea_orm_tx::RollbackReason::Snapshot)
+    );
+    let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+    assert_eq!(body, "original original on");
+    assert_eq!(read(&pool).await, "updated");
+}
+
+#[tokio::test]
+async fn read_only_marker() {
+    let Some(pool) = connect().await else { return };
+
+    pool.execute_unprepared(
+        "DROP TABLE IF EXISTS read_only_test;
+        CREATE TABLE read_only_test (id INT PRIMARY KEY);",
+    )
+    .await
+    .unwrap();
+
+    type ReadOnlyTx =
+        axum_sea_orm_tx::Tx<DatabaseConnection, axum_sea_orm_tx::Error, axum_sea_orm_tx::ReadOnly>;
+
+    let app = axum::Router::new()
+        .route(
+            "/",
+            axum::routing::get(|tx: ReadOnlyTx| async move {
+                // The failed write aborts the transaction, so check the setting first
+                let read_only = transaction_read_only(&*tx).await;
+                let write = tx
+                    .execute_unprepared("INSERT INTO read_only_test VALUES (1)")
+                    .await;
+                format!("{read_only} {}", write.is_err())
+            }),
+        )
+        .layer(axum_sea_orm_tx::Layer::new(pool.clone()));
+
+    let response = app
+        .oneshot(
+            http::Request::builder()
+                .uri("/")
+                .body(axum::body::Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+
+    let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+    assert_eq!(body, "on true");
+}
+
+#[tokio::test]
+async fn execute_last_insert_id() {
+    let Some(pool) = connect().await else { return };
+
+    pool.execute_unprepared(
+        "DROP TABLE IF EXISTS last_insert_id_test;
+        CREATE TABLE last_insert_id_test (id SERIAL PRIMARY KEY, name TEXT);",
+    )
+    .await
+    .unwrap();
+
+    let app = axum::Router::new()
+        .route(
+            "/",
+            axum::routing::get(|mut tx: Tx| async move {
+                let stmt = Statement::from_string(
+                    tx.backend(),
+                    "INSERT INTO last_insert_id_test (name) VALUES ('a')".to_string(),
+                );
+                tx.execute_last_insert_id(stmt).await.unwrap().to_string()
+            }),
+        )
+        .layer(axum_sea_orm_tx::Layer::new(pool.clone()));
+
+    let (status, body) = get(app, "/").await;
+    assert!(status.is_success());
+
+    let id: i32 = pool
+        .query_one(Statement::from_string(
+            pool.get_database_backend(),
+            "SELECT id FROM last_insert_id_test".to_string(),
+        ))
+        .await
+        .unwrap()
+        .unwrap()
+        .try_get("", "id")
+        .unwrap();
+    assert_eq!(body, id.to_string());
+}
+
+#[tokio::test]
+async fn annotate_outcome() {
+    let Some(mut pool) = connect().await else {
+        return;
+    };
+
+    // Capture the statements run on the pool's connections (including in transactions)
+    let statements = std::sync::Arc::new(std::sync::Mutex::new(Vec::new()));
+    pool.set_metric_callback({
+        let statements = statements.clone();
+        move |info| statements.lock().unwrap().push(info.statement.sql.clone())
+    });
+
+    let app = axum::Router::new()
+        .route(
+            "/commit",
+            axum::routing::get(|tx: Tx| async move {
+                tx.execute_unprepared("SELECT 1").await.unwrap();
+            }),
+        )
+        .route(
+            "/rollback",
+            axum::routing::get(|tx: Tx| async move {
+                tx.execute_unprepared("SELECT 1").await.unwrap();
+                http::StatusCode::INTERNAL_SERVER_ERROR
+            }),
+        )
+        .layer(axum_sea_orm_tx::Layer::new(pool).annotate_outcome(true));
+
+    for (uri, request_id) in [("/commit", "abc-123"), ("/rollback", "def */ DROP")] {
+        app.clone()
+            .oneshot(
+                http::Request::builder()
+                    .uri(uri)
+                    .header("x-request-id", request_id)
+                    .body(axum::body::Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+    }
+
+    assert_eq!(
+        *statements.lock().unwrap(),
+        vec![
+            "SELECT /* request_id=abc-123 outcome=commit */ 1".to_string(),
+            "SELECT /* request_id=defDROP outcome=rollback */ 1".to_string(),
+        ]
+    );
+}
+
+#[tokio::test]
+async fn abort_and_cancel() {
+    let Some(pool) = connect().await else { return };
+    let registry = axum_sea_orm_tx::TxRegistry::new();
+
+    let app = axum::Router::new()
+        .route(
+            "/",
+            axum::routing::get(|tx: Tx| async move {
+                tx.execute_unprepared("SELECT pg_sleep(10) /* abort_and_cancel */")
+                    .await
+                    .map(drop)
+                    .map_err(|error| (http::StatusCode::INTERNAL_SERVER_ERROR, error.to_string()))
+            }),
+        )
+        .layer(axum_sea_orm_tx::Layer::new(pool.clone()).registry(registry.clone()));
+
+    let started = std::time::Instant::now();
+    let request = tokio::spawn(
+        app.oneshot(
+            http::Request::builder()
+                .uri("/")
+                .header("x-request-id", "abort-and-cancel")
+                .body(axum::body::Body::empty())
+                .unwrap(),
+        ),
+    );
+
+    // Wait for the statement to be running, so there's something to cancel
+    loop {
+        let running = pool
+            .query_one(Statement::from_string(
+                pool.get_database_backend(),
+                "SELECT COUNT(*) AS count FROM pg_stat_activity \
+                 WHERE state = 'active' AND query LIKE '%/* abort_and_cancel */'"
+                    .to_string(),
+            ))
+            .await
+            .unwrap()
+            .unwrap()
+            .try_get::<i64>("", "count")
+            .unwrap();
+        if running > 0 {
+            break;
+        }
+        tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+    }
+
+    assert!(registry
+        .abort_and_cancel("abort-and-cancel", &pool)
+        .await
+        .unwrap());
+    let response = request.await.unwrap().unwrap();
+
+    assert_eq!(response.status(), http::StatusCode::INTERNAL_SERVER_ERROR);
+    assert!(started.elapsed() < std::time::Duration::from_secs(5));
+    assert_eq!(
+        response
+            .extensions()
+            .get::<axum_sea_orm_tx::TxOutcome>()
+            .unwrap()
+            .reason,
+        Some(axum_sea_orm_tx::RollbackReason::Aborted)
+    );
+}
+
+#[tokio::test]
+async fn explain_slow_queries() {
+    let Some(pool) = connect().await else { return };
+
+    let app = axum::Router::new()
+        .route(
+            "/",
+            axum::routing::get(|tx: Tx| async move {
+                tx.execute_unprepared("SELECT pg_sleep(0.05)")
+                    .await
+                    .unwrap();
+                // Explaining the slow statement leaves the transaction usable
+                tx.execute_unprepared("SELECT 1").await.unwrap();
+                tx.commit().await.unwrap();
+            }),
+        )
+        .layer(
+            axum_sea_orm_tx::Layer::new(pool)
+                .explain_slow_queries(std::time::Duration::from_millis(10)),
+        );
+
+    let (status, _) = get(app, "/").await;
+
+    assert!(status.is_success());
+}
+
+#[tokio::test]
+async fn is_aborted() {
+    let Some(pool) = connect().await else { return };
+
+    pool.execute_unprepared(
+        "DROP TABLE IF EXISTS is_aborted_test;
+        CREATE TABLE is_aborted_test (id INT PRIMARY KEY);",
+    )
+    .await
+    .unwrap();
+
+    let app = axum::Router::new()
+        .route(
+            "/",
+            axum::routing::get(|mut tx: Tx| async move {
+                tx.execute_unprepared("INSERT INTO is_aborted_test VALUES (1)")
+                    .await
+                    .unwrap();
+
+                // A failure within a savepoint is recovered from
+                let recovered = tx
+                    .with_savepoint(|tx| {
+                        Box::pin(async move {
+                            tx.execute_unprepared("INSERT INTO is_aborted_test VALUES (1)")
+                                .await
+                        })
+                    })
+                    .await
+                    .is_err()
+                    && !tx.is_aborted();
+
+                tx.execute_unprepared("INSERT INTO is_aborted_test VALUES (1)")
+                    .await
+                    .unwrap_err();
+                format!("{recovered} {}", tx.is_aborted())
+            }),
+        )
+        .layer(axum_sea_orm_tx::Layer::new(pool.clone()));
+
+    let response = app
+        .oneshot(
+            http::Request::builder()
+                .uri("/")
+                .body(axum::body::Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+
+    // The handler succeeded, but the transaction couldn't be committed
+    assert!(response.status().is_success());
+    assert_eq!(
+        response
+            .extensions()
+            .get::<axum_sea_orm_tx::TxOutcome>()
+            .unwrap()
+            .reason,
+        Some(axum_sea_orm_tx::RollbackReason::StatementFailed)
+    );
+    let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+    assert_eq!(body, "true true");
+
+    let count = pool
+        .query_one(Statement::from_string(
+            pool.get_database_backend(),
+            "SELECT COUNT(*) AS count FROM is_aborted_test".to_string(),
+        ))
+        .await
+        .unwrap()
+        .unwrap()
+        .try_get::<i64>("", "count")
+        .unwrap();
+    assert_eq!(count, 0);
+}
+
+#[tokio::test]
+async fn commit_and_pin_aborted() {
+    let Some(pool) = connect().await else { return };
+
+    pool.execute_unprepared(
+        "DROP TABLE IF EXISTS commit_and_pin_test;
+        CREATE TABLE commit_and_pin_test (id INT PRIMARY KEY);",
+    )
+    .await
+    .unwrap();
+
+    let committed = std::sync::Arc::new(std::sync::atomic::AtomicBool::new(false));
+    let app = axum::Router::new()
+        .route(
+            "/",
+            axum::routing::get({
+                let committed = committed.clone();
+                |mut tx: Tx| async move {
+                    tx.on_commit(async move {
+                        committed.store(true, std::sync::atomic::Ordering::Relaxed)
+                    });
+                    tx.execute_unprepared("INSERT INTO commit_and_pin_test VALUES (1)")
+                        .await
+                        .unwrap();
+                    tx.execute_unprepared("INSERT INTO commit_and_pin_test VALUES (1)")
+                        .await
+                        .unwrap_err();
+
+                    let error = tx.commit_and_pin().await.unwrap_err();
+                    assert!(error
+                        .to_string()
+                        .contains(&axum_sea_orm_tx::Error::Aborted.to_string()));
+                }
+            }),
+        )
+        .layer(axum_sea_orm_tx::Layer::new(pool.clone()));
+
+    let response = app
+        .oneshot(
+            http::Request::builder()
+                .uri("/")
+                .body(axum::body::Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+
+    assert!(response.status().is_success());
+    assert_eq!(
+        response
+            .extensions()
+            .get::<axum_sea_orm_tx::TxOutcome>()
+            .unwrap()
+            .reason,
+        Some(axum_sea_orm_tx::RollbackReason::StatementFailed)
+    );
+    assert!(!committed.load(std::sync::atomic::Ordering::Relaxed));
+}
+
+#[tokio::test]
+async fn execute_many_error() {
+    let Some(pool) = connect().await else { return };
+
+    pool.execute_unprepared(
+        "DROP TABLE IF EXISTS execute_many_test;
+        CREATE TABLE execute_many_test (id INT PRIMARY KEY);",
+    )
+    .await
+    .unwrap();
+
+    let app = axum::Router::new()
+        .route(
+            "/",
+            axum::routing::get(|mut tx: Tx| async move {
+                let backend = tx.get_database_backend();
+                let sql = "INSERT INTO execute_many_test VALUES (1)";
+                tx.execute_many([
+                    Statement::from_string(backend, sql.to_string()),
+                    Statement::from_string(backend, sql.to_string()),
+                ])
+                .await
+                .unwrap_err();
+                tx.is_aborted().to_string()
+            }),
+        )
+        .layer(axum_sea_orm_tx::Layer::new(pool.clone()));
+
+    let response = app
+        .oneshot(
+            http::Request::builder()
+                .uri("/")
+                .body(axum::body::Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+
+    // The handler succeeded, but the batch aborted the transaction
+    assert!(response.status().is_success());
+    assert_eq!(
+        response
+            .extensions()
+            .get::<axum_sea_orm_tx::TxOutcome>()
+            .unwrap()
+            .reason,
+        Some(axum_sea_orm_tx::RollbackReason::StatementFailed)
+    );
+    let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+    assert_eq!(body, "true");
+}
+
+#[tokio::test]
+async fn rollback_to_after_failure() {
+    let Some(pool) = connect().await else { return };
+
+    pool.execute_unprepared(
+        "DROP TABLE IF EXISTS rollback_to_test;
+        CREATE TABLE rollback_to_test (id INT PRIMARY KEY);",
+    )
+    .await
+    .unwrap();
+
+    let app = axum::Router::new()
+        .route(
+            "/",
+            axum::routing::get(|mut tx: Tx| async move {
+                tx.execute_unprepared("INSERT INTO rollback_to_test VALUES (1)")
+                    .await
+                    .unwrap();
+                tx.savepoint_named("duplicates").await.unwrap();
+                tx.execute_unprepared("INSERT INTO rollback_to_test VALUES (1)")
+                    .await
+                    .unwrap_err();
+                let aborted = tx.is_aborted();
+
+                // Rolling back to the savepoint recovers the transaction
+                tx.rollback_to("duplicates").await.unwrap();
+                tx.execute_unprepared("INSERT INTO rollback_to_test VALUES (2)")
+                    .await
+                    .unwrap();
+                format!("{aborted} {}", tx.is_aborted())
+            }),
+        )
+        .layer(axum_sea_orm_tx::Layer::new(pool.clone()));
+
+    let response = app
+        .oneshot(
+            http::Request::builder()
+                .uri("/")
+                .body(axum::body::Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+
+    assert!(response.status().is_success());
+    let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+    assert_eq!(body, "true false");
+
+    let count = pool
+        .query_one(Statement::from_string(
+            pool.get_database_backend(),
+            "SELECT COUNT(*) AS count FROM rollback_to_test".to_string(),
+        ))
+        .await
+        .unwrap()
+        .unwrap()
+        .try_get::<i64>("", "count")
+        .unwrap();
+    assert_eq!(count, 2);
+}
+
+#[tokio::test]
+async fn retry_serialization_failure() {
+    let Some(pool) = connect().await else { return };
+
+    pool.execute_unprepared(
+        "DROP TABLE IF EXISTS retry_test;
+        CREATE TABLE retry_test (id INT PRIMARY KEY, n INT);
+        INSERT INTO retry_test VALUES (1, 0);",
+    )
+    .await
+    .unwrap();
+
+    let app = axum::Router::new()
+        .route(
+            "/",
+            axum::routing::get(
+                |mut tx: axum_sea_orm_tx::Tx<
+                    DatabaseConnection,
+                    axum_sea_orm_tx::Error,
+                    axum_sea_orm_tx::Serializable,
+                >,
+                 axum::Extension(pool): axum::Extension<DatabaseConnection>| async move {
+                    let policy = axum_sea_orm_tx::BackoffPolicy::fixed(
+                        std::time::Duration::from_millis(1),
+                        2,
+                    );
+                    let mut attempts = 0;
+                    tx.retry(policy, |tx| {
+                        attempts += 1;
+                        let attempt = attempts;
+                        let pool = pool.clone();
+                        Box::pin(async move {
+                            tx.execute_unprepared("SELECT n FROM retry_test").await?;
+                            if attempt == 1 {
+                                // A concurrent update after the snapshot was taken
+                                pool.execute_unprepared("UPDATE retry_test SET n = n + 1")
+                                    .await
+                                    .unwrap();
+                            }
+                            tx.execute_unprepared("UPDATE retry_test SET n = n + 10")
+                                .await?;
+                            Ok(())
+                        })
+                    })
+                    .await
+                    .unwrap();
+                    attempts.to_string()
+                },
+            ),
+        )
+        .layer(axum_sea_orm_tx::Layer::new(pool.clone()))
+        .layer(axum::Extension(pool.clone()));
+
+    let response = app
+        .oneshot(
+            http::Request::builder()
+                .uri("/")
+                .body(axum::body::Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+
+    assert!(response.status().is_success());
+    let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+    assert_eq!(body, "2");
+
+    let n = pool
+        .query_one(Statement::from_string(
+            pool.get_database_backend(),
+            "SELECT n FROM retry_test".to_string(),
+        ))
+        .await
+        .unwrap()
+        .unwrap()
+        .try_get::<i32>("", "n")
+        .unwrap();
+    assert_eq!(n, 11);
+}
+
+#[tokio::test]
+async fn stream_best_effort() {
+    let Some(pool) = connect().await else { return };
+
+    #[derive(sea_orm::FromQueryResult)]
+    struct Row {
+        #[allow(dead_code)]
+        i: i32,
+    }
+
+    let app = axum::Router::new()
+        .route(
+            "/",
+            axum::routing::get(|tx: axum_sea_orm_tx::SnapshotTx| async move {
+                let stmt = Statement::from_string(
+                    tx.get_database_backend(),
+                    "SELECT i, pg_sleep(0.01)::text FROM generate_series(1, 1000) AS i".to_string(),
+                );
+                let partial = tx
+                    .stream_best_effort::<Row>(stmt, std::time::Duration::from_millis(200))
+                    .await
+                    .unwrap();
+                format!("{}", partial.truncated)
+            }),
+        )
+        .layer(axum_sea_orm_tx::Layer::new(pool));
+
+    let started = std::time::Instant::now();
+    let (status, body) = get(app, "/").await;
+
+    // The statement was stopped, rather than running for its full 10s before rolling back
+    assert!(status.is_success());
+    assert_eq!(body, "true");
+    assert!(started.elapsed() < std::time::Duration::from_secs(5));
+}
+
+#[tokio::test]
+async fn connection_affinity() {
+    let Some(pool) = connect().await else { return };
+
+    let app = axum::Router::new()
+        .route(
+            "/:tenant",
+            axum::routing::get(|tx: Tx| async move {
+                let row = tx
+                    .query_one(Statement::from_string(
+                        tx.get_database_backend(),
+                        "SELECT pg_backend_pid() AS pid".to_string(),
+                    ))
+                    .await
+                    .unwrap()
+                    .unwrap();
+                row.try_get::<i32>("", "pid").unwrap().to_string()
+            }),
+        )
+        .layer(
+            axum_sea_orm_tx::Layer::new(pool).connection_affinity(2, |parts| {
+                let tenant = parts.uri.path().trim_start_matches('/');
+                Some(axum_sea_orm_tx::AffinityKey::new(tenant))
+            }),
+        );
+
+    let mut pids = Vec::new();
+    for tenant in ["/a", "/b", "/a", "/b"] {
+        let (status, body) = get(app.clone(), tenant).await;
+        assert!(status.is_success());
+        pids.push(body);
+    }
+
+    assert_ne!(pids[0], pids[1]);
+    assert_eq!(pids[0], pids[2]);
+    assert_eq!(pids[1], pids[3]);
+}
+
+async fn connect() -> Option<DatabaseConnection> {
+    let Ok(url) = std::env::var("DATABASE_URL") else {
+        eprintln!("DATABASE_URL not set, skipping Postgres test");
+        return None;
+    };
+    Some(Database::connect(url).await.unwrap())
+}
+
+async fn get(app: axum::Router, uri: &str) -> (http::StatusCode, axum::body::Bytes) {
+    let response = app
+        .oneshot(
+            http::Request::builder()
+                .uri(uri)
+                .body(axum::body::Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    let status = response.status();
+    let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+
+    (status, body)
+}
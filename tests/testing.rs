@@ -0,0 +1,49 @@
+//! Tests for the `testing` feature.
+
+#![cfg(feature = "testing")]
+
+use axum_sea_orm_tx::TxFixture;
+use sea_orm::DatabaseConnection;
+use tempfile::NamedTempFile;
+
+type Tx = axum_sea_orm_tx::Tx<DatabaseConnection>;
+
+#[tokio::test]
+async fn fixture_sqlite() {
+    let db = NamedTempFile::new().unwrap();
+    let fixture = TxFixture::connect(&format!("sqlite://{}", db.path().display()))
+        .await
+        .unwrap();
+
+    let response = fixture
+        .run(|tx: Tx| async move {
+            TxFixture::insert_user(&tx, 1, "huge hackerman")
+                .await
+                .unwrap();
+            "created"
+        })
+        .await;
+    assert!(response.status().is_success());
+    assert_eq!(response.body(), "created");
+
+    let response = fixture
+        .run(|tx: Tx| async move {
+            TxFixture::insert_user(&tx, 2, "michael oxmaul")
+                .await
+                .unwrap();
+            http::StatusCode::BAD_REQUEST
+        })
+        .await;
+    assert!(response.status().is_client_error());
+
+    assert_eq!(
+        fixture.users().await.unwrap(),
+        vec![(1, "huge hackerman".to_string())]
+    );
+
+    // Connecting again starts from an empty table
+    let fixture = TxFixture::connect(&format!("sqlite://{}", db.path().display()))
+        .await
+        .unwrap();
+    assert_eq!(fixture.users().await.unwrap(), vec![]);
+}